@@ -0,0 +1,667 @@
+pub enum Backup {
+    Numbered,
+    Simple(String),
+    Existing(String),
+}
+
+// How the existing destination is preserved. Renaming is cheap and keeps the original file (with
+// its ACLs, attributes and identity) as the backup, but the new destination then inherits its
+// security from the parent directory. Copying duplicates the data but leaves the original file in
+// place to be overwritten, so anything attached to the destination itself is retained.
+#[derive(Clone, Copy)]
+pub enum Mode {
+    Rename,
+    Copy,
+}
+
+// The suffix given on the command line takes precedence over SIMPLE_BACKUP_SUFFIX, which takes
+// precedence over the default of '~'. The environment is passed in as a lookup function rather
+// than read directly so that the resolution does not depend on the state of the process.
+pub fn suffix<E: Fn(&str) -> Option<String>>(flag: Option<String>, env: E) -> String {
+    flag.or_else(|| env("SIMPLE_BACKUP_SUFFIX"))
+        .unwrap_or("~".to_string())
+}
+
+// A CONTROL that names no backup method, or that is an abbreviation of more than one.
+pub struct InvalidControl {
+    pub control: String,
+    pub ambiguous: bool,
+}
+
+const CONTROLS: [(&str, &str); 8] = [
+    ("none", "none"),
+    ("off", "none"),
+    ("simple", "simple"),
+    ("never", "simple"),
+    ("existing", "existing"),
+    ("nil", "existing"),
+    ("numbered", "numbered"),
+    ("t", "numbered"),
+];
+
+// Finds the method that control names. As with GNU install it can be abbreviated to any prefix
+// that only leads to one method, so 'num' is 'numbered' and 'ne' is 'never', but 'n' could be
+// any of four.
+fn method(control: &str) -> Result<&'static str, InvalidControl> {
+    if let Some((_, method)) = CONTROLS.iter().find(|(name, _)| *name == control) {
+        return Ok(method);
+    }
+
+    let mut methods = CONTROLS
+        .iter()
+        .filter(|(name, _)| name.starts_with(control))
+        .map(|(_, method)| *method);
+
+    let invalid = |ambiguous| InvalidControl {
+        control: control.to_string(),
+        ambiguous,
+    };
+
+    match methods.next() {
+        None => Err(invalid(false)),
+        Some(first) if methods.all(|m| m == first) => Ok(first),
+        Some(_) => Err(invalid(true)),
+    }
+}
+
+// Resolves the backup method from --backup/-b, where `None` means no backup was requested and
+// `Some(None)` means a backup was requested without a CONTROL. Without a CONTROL the value of
+// VERSION_CONTROL is used, and without that 'existing'.
+pub fn resolve<E: Fn(&str) -> Option<String>>(
+    control: Option<Option<String>>,
+    suffix_flag: Option<String>,
+    env: E,
+) -> Result<Option<Backup>, InvalidControl> {
+    let Some(control) = control else {
+        return Ok(None);
+    };
+
+    let control = control
+        .or_else(|| env("VERSION_CONTROL"))
+        .unwrap_or("existing".to_string());
+
+    let suffix = suffix(suffix_flag, env);
+
+    match method(&control)? {
+        "numbered" => Ok(Some(Backup::Numbered)),
+        "simple" => Ok(Some(Backup::Simple(suffix))),
+        "existing" => Ok(Some(Backup::Existing(suffix))),
+        _ => Ok(None),
+    }
+}
+
+// Makes a backup of p, returning where it was put, or the name that was tried along with the error.
+// Two installs to the same destination running at once would otherwise both choose the same
+// number, so a numbered name is claimed by creating it, which only one of them can do, before p
+// is moved or copied over it. When another install has claimed it first the directory is scanned
+// again for the next number, and a claimed name that can't be used is given up again.
+pub fn make<P: AsRef<std::path::Path>>(
+    p: P,
+    backup: &Backup,
+    mode: Mode,
+) -> Result<std::path::PathBuf, (std::path::PathBuf, std::io::Error)> {
+    let mut attempts = 0;
+    let next = || next_numbered_backup(p.as_ref()).map_err(|e| (p.as_ref().to_path_buf(), e));
+
+    loop {
+        let (name, numbered) = match backup {
+            Backup::Simple(suffix) => (add_suffix(p.as_ref(), suffix), false),
+            Backup::Numbered => (next()?.0, true),
+            Backup::Existing(suffix) => match next()? {
+                (_, true) => (add_suffix(p.as_ref(), suffix), false),
+                (numbered, false) => (numbered, true),
+            },
+        };
+
+        if numbered {
+            match std::fs::File::create_new(&name) {
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && attempts < 100 => {
+                    attempts += 1;
+                    continue;
+                }
+                Err(e) => return Err((name, e)),
+            }
+        }
+
+        return match preserve(p.as_ref(), &name, mode) {
+            Ok(_) => Ok(name),
+            Err(e) => {
+                if numbered {
+                    _ = std::fs::remove_file(&name);
+                }
+
+                Err((name, e))
+            }
+        };
+    }
+}
+
+// Moves or copies from to its backup to. A rename that can't be done because the backup would be
+// on another volume (as when the destination is reached through a junction) falls back to copying
+// and then removing the original. Either way the original is only ever gone once the backup is
+// complete, so an error means that the destination must not be overwritten.
+pub fn preserve<F: AsRef<std::path::Path>, T: AsRef<std::path::Path>>(
+    from: F,
+    to: T,
+    mode: Mode,
+) -> std::io::Result<()> {
+    preserve_with(from, to, mode, |from, to| std::fs::rename(from, to))
+}
+
+// As preserve, with the rename passed in so that the tests can have it fail as it would across
+// volumes.
+fn preserve_with<F, T, R>(from: F, to: T, mode: Mode, rename: R) -> std::io::Result<()>
+where
+    F: AsRef<std::path::Path>,
+    T: AsRef<std::path::Path>,
+    R: FnOnce(&std::path::Path, &std::path::Path) -> std::io::Result<()>,
+{
+    match mode {
+        Mode::Rename => match rename(from.as_ref(), to.as_ref()) {
+            Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+                std::fs::copy(from.as_ref(), to.as_ref())?;
+
+                std::fs::remove_file(from.as_ref()).inspect_err(|_| {
+                    _ = std::fs::remove_file(to.as_ref());
+                })
+            }
+            result => result,
+        },
+        Mode::Copy => std::fs::copy(from.as_ref(), to.as_ref()).map(|_| ()),
+    }
+}
+
+// The name of the next numbered backup of p, and whether it is the first. A path that doesn't end
+// in a name, such as a root or one ending in '..', has nothing to number and is refused.
+pub fn next_numbered_backup<P: AsRef<std::path::Path>>(
+    p: P,
+) -> std::io::Result<(std::path::PathBuf, bool)> {
+    let parent = p
+        .as_ref()
+        .parent()
+        .and_then(|parent| {
+            if parent == std::path::Path::new("") {
+                None
+            } else {
+                Some(parent)
+            }
+        })
+        .unwrap_or(std::path::Path::new("."));
+
+    let Some(file_name) = p.as_ref().file_name() else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "path does not end in a file name",
+        ));
+    };
+
+    let file_name = file_name.to_string_lossy().to_string();
+
+    let next = std::fs::read_dir(parent)
+        .map(|entries| {
+            let mut max = 0;
+
+            for entry in entries {
+                _ = entry.map(|e| {
+                    let entry_name = e.file_name().to_string_lossy().to_string();
+                    if entry_name.starts_with(&file_name) && entry_name.ends_with("~") {
+                        let num = entry_name
+                            .strip_prefix(&file_name)
+                            .and_then(|s| s.strip_prefix(".~"))
+                            .and_then(|s| s.strip_suffix("~"))
+                            .and_then(|s| s.parse::<u32>().ok());
+
+                        if let Some(n) = num {
+                            max = n.max(max);
+                        }
+                    }
+                });
+            }
+
+            (add_suffix(p.as_ref(), &format!(".~{}~", max + 1)), max == 0)
+        })
+        .unwrap_or((add_suffix(p.as_ref(), ".~1~"), true));
+
+    Ok(next)
+}
+
+pub fn add_suffix<P: AsRef<std::path::Path>>(p: P, suffix: &str) -> std::path::PathBuf {
+    let mut name = p.as_ref().file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+
+    p.as_ref().with_file_name(name)
+}
+
+// What a name found in a directory is a backup of, if anything.
+#[derive(Debug, PartialEq)]
+enum Found<'a> {
+    // A numbered backup, as name.~N~.
+    Numbered(&'a str),
+    // A name that ends with the simple backup suffix, which is only a backup if the name without it
+    // is there too.
+    Simple(&'a str),
+}
+
+fn original_name<'a>(name: &'a str, suffix: &str) -> Option<Found<'a>> {
+    let numbered = name
+        .strip_suffix('~')
+        .and_then(|s| s.rsplit_once(".~"))
+        .filter(|(original, n)| {
+            !original.is_empty() && !n.is_empty() && n.chars().all(|c| c.is_ascii_digit())
+        })
+        .map(|(original, _)| Found::Numbered(original));
+
+    numbered.or_else(|| {
+        name.strip_suffix(suffix)
+            .filter(|original| !original.is_empty())
+            .map(Found::Simple)
+    })
+}
+
+// Removes backups in the directory that fall outside the retention policy. The backups of each
+// file are ordered newest first by modification time, anything after the first `keep` or older
+// than `older_than` is removed.
+pub fn prune<P: AsRef<std::path::Path>>(
+    p: P,
+    suffix: &str,
+    keep: Option<usize>,
+    older_than: Option<std::time::Duration>,
+    verbose: Option<crate::messages::Router>,
+) -> bool {
+    // Every name ends with an empty suffix, so every file would be taken for a backup.
+    if suffix.is_empty() {
+        eprintln!("winstall: cannot prune backups with an empty suffix");
+        return false;
+    }
+
+    let entries = match std::fs::read_dir(p.as_ref()) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!(
+                "winstall: cannot read directory {}: {}",
+                crate::quoting::quote(p.as_ref()),
+                e
+            );
+
+            return false;
+        }
+    };
+
+    let mut backups = std::collections::BTreeMap::<String, Vec<_>>::new();
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        // Anything else can end with the suffix too (with '~', an editor's own backups, or with
+        // '.bak', a file that was simply named that way), so a name is only taken for a simple
+        // backup when the file it would be a backup of is beside it.
+        let original = match original_name(&name, suffix) {
+            Some(Found::Numbered(original)) => original,
+            Some(Found::Simple(original))
+                if std::fs::symlink_metadata(p.as_ref().join(original)).is_ok() =>
+            {
+                original
+            }
+            _ => continue,
+        };
+
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+
+        if !metadata.is_file() {
+            continue;
+        }
+
+        let modified = metadata.modified().unwrap_or(std::time::UNIX_EPOCH);
+
+        backups.entry(original.to_owned()).or_default().push((
+            entry.path(),
+            modified,
+            metadata.len(),
+        ));
+    }
+
+    let cutoff = older_than.and_then(|age| std::time::SystemTime::now().checked_sub(age));
+
+    let mut success = true;
+    let mut removed = 0;
+    let mut freed = 0;
+
+    for (_, mut files) in backups {
+        files.sort_by_key(|(_, modified, _)| std::cmp::Reverse(*modified));
+
+        for (i, (path, modified, len)) in files.into_iter().enumerate() {
+            let beyond_keep = keep.is_some_and(|keep| i >= keep);
+            let expired = cutoff.is_some_and(|cutoff| modified < cutoff);
+
+            if !beyond_keep && !expired {
+                continue;
+            }
+
+            match std::fs::remove_file(&path) {
+                Ok(_) => {
+                    if let Some(router) = verbose {
+                        router.send(crate::messages::removed(&path));
+                    }
+
+                    removed += 1;
+                    freed += len;
+                }
+                Err(e) => {
+                    eprintln!(
+                        "winstall: cannot remove {}: {}",
+                        crate::quoting::quote(path),
+                        e
+                    );
+                    success = false;
+                }
+            }
+        }
+    }
+
+    println!(
+        "winstall: removed {} backup(s) from {}, freeing {} bytes",
+        removed,
+        crate::quoting::quote(p.as_ref()),
+        freed
+    );
+
+    success
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{method, original_name, prune, resolve, Backup, Found};
+    use crate::scratch::{Element, Scratch};
+
+    #[test]
+    fn original_names_of_backups() {
+        assert_eq!(original_name("a.~1~", "~"), Some(Found::Numbered("a")));
+        assert_eq!(original_name("a.b.~12~", "~"), Some(Found::Numbered("a.b")));
+        assert_eq!(original_name("a~", "~"), Some(Found::Simple("a")));
+        assert_eq!(original_name("a.bak", ".bak"), Some(Found::Simple("a")));
+
+        assert_eq!(original_name("a.~~", ".bak"), None);
+        assert_eq!(original_name("a.~x~", ".bak"), None);
+        assert_eq!(original_name(".~1~", ".bak"), None);
+        assert_eq!(original_name("~", "~"), None);
+        assert_eq!(original_name("a", "~"), None);
+    }
+
+    // Installs running at once each claim a number of their own, so no backup is overwritten and
+    // none of the numbers are skipped.
+    #[test]
+    fn makes_distinct_numbered_backups_at_once() {
+        let scratch = Scratch::unique("concurrent-backups");
+        scratch.declare(&[("dest", Element::File("content"))]);
+
+        let dest = scratch.join("dest");
+        let made = std::thread::scope(|scope| {
+            let threads = (0..16)
+                .map(|_| scope.spawn(|| super::make(&dest, &Backup::Numbered, super::Mode::Copy)))
+                .collect::<Vec<_>>();
+
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap().unwrap())
+                .collect::<std::collections::BTreeSet<_>>()
+        });
+
+        let expected = (1..=16)
+            .map(|n| scratch.join(format!("dest.~{}~", n)))
+            .collect::<std::collections::BTreeSet<_>>();
+
+        assert_eq!(made, expected);
+
+        for backup in made {
+            assert_eq!(std::fs::read_to_string(backup).unwrap(), "content");
+        }
+    }
+
+    // A path with no name at its end has no numbered backup, which is an error rather than a
+    // panic.
+    #[test]
+    fn refuses_to_number_backups_of_paths_without_a_name() {
+        let scratch = Scratch::unique("nameless");
+        scratch.declare(&[("dir", Element::Directory(vec![]))]);
+
+        let root = if cfg!(windows) { "C:\\" } else { "/" };
+
+        for p in [scratch.join("dir/.."), std::path::PathBuf::from(root)] {
+            for backup in [Backup::Numbered, Backup::Existing("~".to_string())] {
+                let Err((name, e)) = super::make(&p, &backup, super::Mode::Copy) else {
+                    panic!("{} was numbered", p.display());
+                };
+
+                assert_eq!(name, p);
+                assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+            }
+        }
+    }
+
+    fn names(scratch: &Scratch) -> Vec<String> {
+        scratch.tree().into_keys().collect()
+    }
+
+    #[test]
+    fn prunes_the_oldest_backups_beyond_those_kept() {
+        let scratch = Scratch::unique("prune");
+        scratch.declare(&[
+            ("a", Element::File("current")),
+            ("a.~1~", Element::File("1")),
+            ("a.~2~", Element::File("2")),
+            ("a.~3~", Element::File("3")),
+        ]);
+
+        let now = std::time::SystemTime::now();
+        for (n, age) in [(1, 3), (2, 2), (3, 1)] {
+            let file = std::fs::File::options()
+                .write(true)
+                .open(scratch.join(format!("a.~{}~", n)))
+                .unwrap();
+
+            file.set_modified(now - std::time::Duration::from_secs(age * 60))
+                .unwrap();
+        }
+
+        assert!(prune(scratch.path(), "~", Some(1), None, None));
+        assert_eq!(names(&scratch), ["a", "a.~3~"]);
+    }
+
+    #[test]
+    fn only_prunes_simple_backups_of_files_that_are_there() {
+        let scratch = Scratch::unique("prune-simple");
+        scratch.declare(&[
+            ("a", Element::File("current")),
+            ("a.bak", Element::File("backup")),
+            ("notes.bak", Element::File("not a backup")),
+            ("b.~1~", Element::File("numbered")),
+        ]);
+
+        assert!(prune(scratch.path(), ".bak", Some(0), None, None));
+        assert_eq!(names(&scratch), ["a", "notes.bak"]);
+    }
+
+    #[test]
+    fn refuses_an_empty_suffix() {
+        let scratch = Scratch::unique("prune-empty");
+        scratch.declare(&[
+            ("a", Element::File("current")),
+            ("b", Element::File("other")),
+        ]);
+
+        assert!(!prune(scratch.path(), "", Some(0), None, None));
+        assert_eq!(names(&scratch), ["a", "b"]);
+    }
+
+    // The backup resolved from the flags and variables given, as text to compare.
+    fn resolved(
+        control: Option<Option<&str>>,
+        suffix: Option<&str>,
+        variables: &[(&str, &str)],
+    ) -> String {
+        let env = |name: &str| {
+            variables
+                .iter()
+                .find(|(variable, _)| *variable == name)
+                .map(|(_, value)| value.to_string())
+        };
+
+        match resolve(
+            control.map(|c| c.map(str::to_string)),
+            suffix.map(str::to_string),
+            env,
+        ) {
+            Ok(None) => "none".to_string(),
+            Ok(Some(Backup::Numbered)) => "numbered".to_string(),
+            Ok(Some(Backup::Simple(suffix))) => format!("simple {}", suffix),
+            Ok(Some(Backup::Existing(suffix))) => format!("existing {}", suffix),
+            Err(e) => format!("invalid {}", e.control),
+        }
+    }
+
+    #[test]
+    fn flags_take_precedence_over_the_environment_over_the_defaults() {
+        let env = [
+            ("VERSION_CONTROL", "numbered"),
+            ("SIMPLE_BACKUP_SUFFIX", ".env"),
+        ];
+
+        for (control, suffix, variables, expected) in [
+            // Without -b or --backup there is no backup, whatever the environment says.
+            (None, None, &env[..], "none"),
+            (None, Some(".flag"), &env[..], "none"),
+            // The defaults.
+            (Some(None), None, &[][..], "existing ~"),
+            (Some(Some("simple")), None, &[][..], "simple ~"),
+            // The environment fills in what the flags leave out.
+            (Some(None), None, &env[..], "numbered"),
+            (Some(Some("simple")), None, &env[..], "simple .env"),
+            (Some(None), None, &env[1..], "existing .env"),
+            // The flags take precedence.
+            (Some(Some("never")), Some(".flag"), &env[..], "simple .flag"),
+            (Some(Some("off")), None, &env[..], "none"),
+            (Some(Some("t")), Some(".flag"), &[][..], "numbered"),
+            // A CONTROL from the environment is checked like one from a flag.
+            (
+                Some(None),
+                None,
+                &[("VERSION_CONTROL", "bogus")][..],
+                "invalid bogus",
+            ),
+        ] {
+            assert_eq!(
+                resolved(control, suffix, variables),
+                expected,
+                "{:?} {:?} {:?}",
+                control,
+                suffix,
+                variables
+            );
+        }
+    }
+
+    #[test]
+    fn controls_can_be_abbreviated_to_any_unambiguous_prefix() {
+        for (control, expected) in [
+            ("numbered", "numbered"),
+            ("t", "numbered"),
+            ("nil", "existing"),
+            ("num", "numbered"),
+            ("ne", "simple"),
+            ("no", "none"),
+            ("o", "none"),
+            ("s", "simple"),
+            ("e", "existing"),
+        ] {
+            assert_eq!(method(control).ok(), Some(expected), "{}", control);
+        }
+    }
+
+    #[test]
+    fn controls_that_name_no_method_or_several_are_refused() {
+        for (control, ambiguous) in [
+            // 'n' begins none, never, nil and numbered, and '' begins everything.
+            ("n", true),
+            ("", true),
+            ("bogus", false),
+            ("numbers", false),
+        ] {
+            match method(control) {
+                Err(e) => {
+                    assert_eq!(e.control, control);
+                    assert_eq!(e.ambiguous, ambiguous, "{}", control);
+                }
+                Ok(method) => panic!("{:?} was taken for {}", control, method),
+            }
+        }
+    }
+
+    fn crosses_devices(_: &std::path::Path, _: &std::path::Path) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::CrossesDevices.into())
+    }
+
+    // A rename refused because the backup would be on another volume is done as a copy, after
+    // which the original is removed.
+    #[test]
+    fn copies_backups_that_cannot_be_renamed_across_volumes() {
+        let scratch = Scratch::unique("preserve-across");
+        scratch.declare(&[("dest", Element::File("old"))]);
+
+        let (from, to) = (scratch.join("dest"), scratch.join("dest~"));
+        super::preserve_with(&from, &to, super::Mode::Rename, crosses_devices).unwrap();
+
+        assert!(!from.exists());
+        assert_eq!(std::fs::read(&to).unwrap(), b"old");
+    }
+
+    // When the copy can't be made either, the original is still there and the error is returned,
+    // so that the destination isn't overwritten.
+    #[test]
+    fn keeps_the_original_when_the_fallback_fails() {
+        let scratch = Scratch::unique("preserve-across-failed");
+        scratch.declare(&[("dest", Element::File("old"))]);
+
+        let (from, to) = (scratch.join("dest"), scratch.join("missing/dest~"));
+        let result = super::preserve_with(&from, &to, super::Mode::Rename, crosses_devices);
+
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+        assert_eq!(std::fs::read(&from).unwrap(), b"old");
+    }
+
+    // Only a rename across volumes falls back, any other failure is returned as it is.
+    #[test]
+    fn returns_other_rename_failures() {
+        let scratch = Scratch::unique("preserve-denied");
+        scratch.declare(&[("dest", Element::File("old"))]);
+
+        let (from, to) = (scratch.join("dest"), scratch.join("dest~"));
+        let denied = |_: &std::path::Path, _: &std::path::Path| {
+            Err(std::io::ErrorKind::PermissionDenied.into())
+        };
+
+        let result = super::preserve_with(&from, &to, super::Mode::Rename, denied);
+
+        assert_eq!(
+            result.unwrap_err().kind(),
+            std::io::ErrorKind::PermissionDenied
+        );
+        assert_eq!(std::fs::read(&from).unwrap(), b"old");
+        assert!(!to.exists());
+    }
+
+    #[test]
+    fn copies_without_renaming_in_copy_mode() {
+        let scratch = Scratch::unique("preserve-copy");
+        scratch.declare(&[("dest", Element::File("old"))]);
+
+        let (from, to) = (scratch.join("dest"), scratch.join("dest~"));
+        let rename = |_: &std::path::Path, _: &std::path::Path| panic!("renamed in copy mode");
+        super::preserve_with(&from, &to, super::Mode::Copy, rename).unwrap();
+
+        assert_eq!(std::fs::read(&from).unwrap(), b"old");
+        assert_eq!(std::fs::read(&to).unwrap(), b"old");
+    }
+}
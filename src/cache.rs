@@ -0,0 +1,363 @@
+// What was installed by earlier runs, kept with --state-file so that a destination can be left
+// alone without reading either file when neither has changed since. For each destination the
+// source it was installed from is recorded with its size, modification time and SHA-256 hash, as
+// are the size and modification time the destination was left with, as observed straight after it
+// was installed. With --mtime-slack a time that has drifted by no more than the slack since, as it
+// can when an antivirus scanner touches a file it has just looked at, is taken to be unchanged,
+// while a change of size never is.
+//
+// The file is a cache, so one that can't be read or that has been damaged is never an error: lines
+// that don't parse are dropped, and at worst every file is installed again.
+const HEADER: &str = "winstall-state 1";
+
+struct Stamp {
+    size: u64,
+    modified: u128,
+}
+
+impl Stamp {
+    fn of<P: AsRef<std::path::Path>>(p: P) -> Option<Stamp> {
+        let metadata = std::fs::metadata(p.as_ref()).ok()?;
+        let modified = metadata
+            .modified()
+            .ok()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()?;
+
+        Some(Stamp {
+            size: metadata.len(),
+            modified: modified.as_nanos(),
+        })
+    }
+
+    fn matches(&self, other: &Stamp, slack: std::time::Duration) -> bool {
+        self.size == other.size && self.modified.abs_diff(other.modified) <= slack.as_nanos()
+    }
+}
+
+struct Entry {
+    source: String,
+    source_stamp: Stamp,
+    hash: String,
+    destination_stamp: Stamp,
+}
+
+#[derive(Default)]
+pub struct Cache {
+    entries: std::collections::BTreeMap<String, Entry>,
+}
+
+impl Cache {
+    pub fn load<P: AsRef<std::path::Path>>(p: P) -> Cache {
+        let mut cache = Cache::default();
+
+        let Ok(content) = std::fs::read_to_string(p.as_ref()) else {
+            return cache;
+        };
+
+        let mut lines = content.lines();
+        if lines.next() != Some(HEADER) {
+            return cache;
+        }
+
+        for line in lines {
+            if let Some((destination, entry)) = parse(line) {
+                cache.entries.insert(destination, entry);
+            }
+        }
+
+        cache
+    }
+
+    // Whether to is as it was left when it was last installed from from, and from hasn't changed
+    // since, allowing either time to be out by up to slack. A source whose time has changed but not
+    // its size is hashed, so that one rebuilt with the same content still counts as unchanged.
+    pub fn unchanged<F: AsRef<std::path::Path>, T: AsRef<std::path::Path>>(
+        &self,
+        from: F,
+        to: T,
+        slack: std::time::Duration,
+    ) -> bool {
+        let (Some(source), Some(destination)) = (key(from.as_ref()), key(to.as_ref())) else {
+            return false;
+        };
+
+        let Some(entry) = self.entries.get(&destination) else {
+            return false;
+        };
+
+        let destination_stamp = Stamp::of(to.as_ref());
+        let destination_unchanged =
+            destination_stamp.is_some_and(|stamp| stamp.matches(&entry.destination_stamp, slack));
+
+        if entry.source != source || !destination_unchanged {
+            return false;
+        }
+
+        match Stamp::of(from.as_ref()) {
+            Some(stamp) if stamp.matches(&entry.source_stamp, slack) => true,
+            Some(stamp) if stamp.size == entry.source_stamp.size => {
+                crate::sha256::file(from.as_ref()).is_ok_and(|hash| hash == entry.hash)
+            }
+            _ => false,
+        }
+    }
+
+    // Records that from has just been installed to to. Files whose names can't be written to the
+    // state file, or whose details can't be read, are left out and so installed every time.
+    pub fn record<F: AsRef<std::path::Path>, T: AsRef<std::path::Path>>(&mut self, from: F, to: T) {
+        let (Some(source), Some(destination)) = (key(from.as_ref()), key(to.as_ref())) else {
+            return;
+        };
+
+        let (Some(source_stamp), Some(destination_stamp), Ok(hash)) = (
+            Stamp::of(from.as_ref()),
+            Stamp::of(to.as_ref()),
+            crate::sha256::file(from.as_ref()),
+        ) else {
+            self.entries.remove(&destination);
+            return;
+        };
+
+        self.entries.insert(
+            destination,
+            Entry {
+                source,
+                source_stamp,
+                hash,
+                destination_stamp,
+            },
+        );
+    }
+
+    // Writes the state under a temporary name first, so that a run that is interrupted leaves the
+    // previous state in place rather than a partial one.
+    pub fn save<P: AsRef<std::path::Path>>(&self, p: P) -> std::io::Result<()> {
+        let mut content = format!("{}\n", HEADER);
+
+        for (destination, entry) in &self.entries {
+            content.push_str(&format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                entry.source_stamp.size,
+                entry.source_stamp.modified,
+                entry.hash,
+                entry.destination_stamp.size,
+                entry.destination_stamp.modified,
+                entry.source,
+                destination
+            ));
+        }
+
+        let temporary =
+            crate::backup::add_suffix(p.as_ref(), &format!(".winstall-{}", std::process::id()));
+
+        std::fs::write(&temporary, content)?;
+        std::fs::rename(&temporary, p.as_ref()).inspect_err(|_| {
+            _ = std::fs::remove_file(&temporary);
+        })
+    }
+}
+
+// Files are recorded by their absolute paths, which have to be valid Unicode and free of tabs and
+// line breaks to fit in the state file.
+fn key(p: &std::path::Path) -> Option<String> {
+    let absolute = std::path::absolute(p).ok()?;
+    let key = absolute.to_str()?;
+
+    match key.contains(['\t', '\n', '\r']) {
+        true => None,
+        false => Some(key.to_string()),
+    }
+}
+
+fn parse(line: &str) -> Option<(String, Entry)> {
+    let fields = line.split('\t').collect::<Vec<_>>();
+
+    let [size, modified, hash, destination_size, destination_modified, source, destination] =
+        fields[..]
+    else {
+        return None;
+    };
+
+    if !crate::sha256::is_hex_digest(hash) || source.is_empty() || destination.is_empty() {
+        return None;
+    }
+
+    Some((
+        destination.to_string(),
+        Entry {
+            source: source.to_string(),
+            source_stamp: Stamp {
+                size: size.parse().ok()?,
+                modified: modified.parse().ok()?,
+            },
+            hash: hash.to_string(),
+            destination_stamp: Stamp {
+                size: destination_size.parse().ok()?,
+                modified: destination_modified.parse().ok()?,
+            },
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cache;
+    use crate::scratch::{Element, Scratch};
+
+    // A scratch directory where a and b have been installed to a-dest and b-dest, and recorded.
+    fn installed(prefix: &str) -> (Scratch, Cache) {
+        let scratch = Scratch::unique(prefix);
+        scratch.declare(&[
+            ("a", Element::File("first")),
+            ("a-dest", Element::File("first")),
+            ("b", Element::File("second")),
+            ("b-dest", Element::File("second")),
+        ]);
+
+        let mut cache = Cache::default();
+        cache.record(scratch.join("a"), scratch.join("a-dest"));
+        cache.record(scratch.join("b"), scratch.join("b-dest"));
+
+        (scratch, cache)
+    }
+
+    fn unchanged(scratch: &Scratch, cache: &Cache, name: &str) -> bool {
+        let (from, to) = (scratch.join(name), scratch.join(format!("{}-dest", name)));
+        cache.unchanged(from, to, std::time::Duration::ZERO)
+    }
+
+    fn touch(p: std::path::PathBuf, seconds: u64) {
+        let file = std::fs::OpenOptions::new().write(true).open(p).unwrap();
+        let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(seconds);
+        file.set_modified(time).unwrap();
+    }
+
+    #[test]
+    fn saves_and_loads_what_was_recorded() {
+        let (scratch, cache) = installed("cache-round-trip");
+        assert!(unchanged(&scratch, &cache, "a"));
+
+        cache.save(scratch.join("state")).unwrap();
+        let loaded = Cache::load(scratch.join("state"));
+
+        assert!(unchanged(&scratch, &loaded, "a"));
+        assert!(unchanged(&scratch, &loaded, "b"));
+
+        // Only the state itself is left, not the file it was written to first.
+        assert_eq!(
+            scratch.tree().into_keys().collect::<Vec<_>>(),
+            ["a", "a-dest", "b", "b-dest", "state"]
+        );
+    }
+
+    #[test]
+    fn loads_nothing_from_a_missing_file() {
+        let (scratch, _) = installed("cache-missing");
+        let loaded = Cache::load(scratch.join("state"));
+
+        assert!(!unchanged(&scratch, &loaded, "a"));
+    }
+
+    #[test]
+    fn loads_nothing_after_a_wrong_header() {
+        let (scratch, cache) = installed("cache-header");
+        cache.save(scratch.join("state")).unwrap();
+
+        let content = std::fs::read_to_string(scratch.join("state")).unwrap();
+        let content = content.replacen(super::HEADER, "winstall-state 2", 1);
+        std::fs::write(scratch.join("state"), content).unwrap();
+
+        let loaded = Cache::load(scratch.join("state"));
+        assert!(!unchanged(&scratch, &loaded, "a"));
+        assert!(!unchanged(&scratch, &loaded, "b"));
+    }
+
+    #[test]
+    fn drops_lines_that_are_damaged() {
+        let (scratch, cache) = installed("cache-damaged");
+        cache.save(scratch.join("state")).unwrap();
+
+        let content = std::fs::read_to_string(scratch.join("state")).unwrap();
+        let mut lines = content.lines().map(str::to_string).collect::<Vec<_>>();
+
+        // The entry for a is cut short, and after the one for b comes a copy of it with a size that
+        // isn't a number, and a line that is nonsense.
+        let a = lines.iter().position(|l| l.ends_with("a-dest")).unwrap();
+        let half = lines[a].len() / 2;
+        lines[a].truncate(half);
+
+        let b = lines.iter().position(|l| l.ends_with("b-dest")).unwrap();
+        lines.push(lines[b].replacen(char::is_numeric, "x", 1));
+        lines.push("garbled\tline".to_string());
+
+        std::fs::write(scratch.join("state"), lines.join("\n")).unwrap();
+
+        let loaded = Cache::load(scratch.join("state"));
+        assert!(!unchanged(&scratch, &loaded, "a"));
+        assert!(unchanged(&scratch, &loaded, "b"));
+    }
+
+    // A source that has been touched but whose content is the same is found to be unchanged by
+    // its hash, while one of the same size with different content is not.
+    #[test]
+    fn hashes_sources_whose_time_has_changed() {
+        let (scratch, cache) = installed("cache-hash");
+
+        touch(scratch.join("a"), 1_000_000);
+        assert!(unchanged(&scratch, &cache, "a"));
+
+        std::fs::write(scratch.join("b"), "SECOND").unwrap();
+        touch(scratch.join("b"), 1_000_000);
+        assert!(!unchanged(&scratch, &cache, "b"));
+    }
+
+    #[test]
+    fn notices_destinations_that_have_changed() {
+        let (scratch, cache) = installed("cache-destination");
+
+        touch(scratch.join("a-dest"), 1_000_000);
+        assert!(!unchanged(&scratch, &cache, "a"));
+
+        std::fs::write(scratch.join("b-dest"), "changed").unwrap();
+        assert!(!unchanged(&scratch, &cache, "b"));
+    }
+
+    #[test]
+    fn only_matches_the_source_that_was_installed() {
+        let (scratch, cache) = installed("cache-source");
+
+        let slack = std::time::Duration::ZERO;
+        assert!(!cache.unchanged(scratch.join("b"), scratch.join("a-dest"), slack));
+        assert!(!cache.unchanged(scratch.join("a"), scratch.join("missing"), slack));
+    }
+
+    // A destination whose time has drifted by no more than the slack since it was recorded is
+    // unchanged, but not one that has changed size within it, or drifted further.
+    #[test]
+    fn allows_destination_times_some_slack() {
+        let scratch = Scratch::unique("cache-slack");
+        scratch.declare(&[
+            ("a", Element::File("first")),
+            ("a-dest", Element::File("first")),
+        ]);
+
+        touch(scratch.join("a-dest"), 1_000_000);
+
+        let mut cache = Cache::default();
+        cache.record(scratch.join("a"), scratch.join("a-dest"));
+
+        let unchanged = |seconds| {
+            let slack = std::time::Duration::from_secs(seconds);
+            cache.unchanged(scratch.join("a"), scratch.join("a-dest"), slack)
+        };
+
+        touch(scratch.join("a-dest"), 1_000_003);
+        assert!(unchanged(3));
+        assert!(!unchanged(2));
+
+        std::fs::write(scratch.join("a-dest"), "first!").unwrap();
+        touch(scratch.join("a-dest"), 1_000_000);
+        assert!(!unchanged(3));
+    }
+}
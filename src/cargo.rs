@@ -0,0 +1,78 @@
+// Resolves NAME[:PROFILE] to the executable cargo builds for that binary, in the target directory
+// of the workspace containing dir. The profile defaults to release, and dev is built into debug.
+pub fn artifact<P: AsRef<std::path::Path>>(
+    spec: &str,
+    dir: P,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<std::path::PathBuf, String> {
+    let (name, profile) = match spec.split_once(':') {
+        Some((name, profile)) => (name, profile),
+        None => (spec, "release"),
+    };
+
+    if name.is_empty() || profile.is_empty() {
+        return Err(format!("invalid cargo artifact '{}'", spec));
+    }
+
+    let target = match env("CARGO_TARGET_DIR") {
+        Some(target) => dir.as_ref().join(target),
+        None => target_directory(dir.as_ref())?,
+    };
+
+    let path = target
+        .join(if profile == "dev" { "debug" } else { profile })
+        .join(format!("{}{}", name, std::env::consts::EXE_SUFFIX));
+
+    match path.is_file() {
+        true => Ok(path),
+        false => Err(format!(
+            "cargo artifact '{}' has not been built, expected {}",
+            spec,
+            crate::quoting::quote(path)
+        )),
+    }
+}
+
+fn target_directory(dir: &std::path::Path) -> Result<std::path::PathBuf, String> {
+    let output = std::process::Command::new("cargo")
+        .args(["metadata", "--format-version", "1", "--no-deps"])
+        .current_dir(dir)
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .map_err(|e| format!("unable to run cargo metadata: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!("cargo metadata failed ({})", output.status));
+    }
+
+    let metadata = String::from_utf8_lossy(&output.stdout);
+
+    json_field(&metadata, "target_directory")
+        .map(std::path::PathBuf::from)
+        .ok_or_else(|| "cargo metadata did not report a target directory".to_string())
+}
+
+// Finds a string field in cargo's (compact) JSON output without parsing the whole document.
+fn json_field(json: &str, name: &str) -> Option<String> {
+    let key = format!("\"{}\":\"", name);
+    let start = json.find(&key)? + key.len();
+
+    let mut value = String::new();
+    let mut chars = json[start..].chars();
+
+    loop {
+        match chars.next()? {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'u' => {
+                    let code = chars.by_ref().take(4).collect::<String>();
+                    value.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                }
+                c => value.push(c),
+            },
+            c => value.push(c),
+        }
+    }
+}
@@ -0,0 +1,1488 @@
+// The options that a run is given, and the checks that can be made on them before anything on disk
+// is looked at. Parsing fills in an Options, which then decides what the run is to do.
+pub struct Options {
+    pub help: bool,
+    pub version: bool,
+    pub backup: Option<Option<String>>,
+    pub suffix: Option<String>,
+    pub verbose: Option<crate::messages::Router>,
+    pub preserve_timestamps: bool,
+    pub make_all_directories: bool,
+    pub no_target_directory: bool,
+    pub target_directories: Vec<std::path::PathBuf>,
+    pub directory_arguments: bool,
+    pub reference: Option<std::path::PathBuf>,
+    pub backup_mode: crate::backup::Mode,
+    pub chdir: Option<std::path::PathBuf>,
+    pub buffer_size: Option<usize>,
+    pub stats_file: Option<std::path::PathBuf>,
+    pub interactive: bool,
+    pub force: bool,
+    pub self_test: bool,
+    pub expectations: Vec<(std::path::PathBuf, String)>,
+    pub link: Option<crate::Link>,
+    pub prune_backups: Option<std::path::PathBuf>,
+    pub keep: Option<usize>,
+    pub older_than: Option<u64>,
+    pub relative: bool,
+    pub skip_if_unchanged: Option<std::path::PathBuf>,
+    pub no_clobber: bool,
+    pub post_install: Vec<String>,
+    pub halt_on_hook_failure: bool,
+    pub sign: bool,
+    pub signtool: Option<std::ffi::OsString>,
+    pub sign_thumbprint: Option<String>,
+    pub no_env: bool,
+    pub show_config: bool,
+    pub verify_tree: Option<std::path::PathBuf>,
+    pub root: Option<std::path::PathBuf>,
+    pub stop_at_first_error: bool,
+    pub versioned: bool,
+    pub version_tag: Option<String>,
+    pub cargo_artifacts: Vec<String>,
+    pub preserve_creation_time: bool,
+    pub write_through: bool,
+    pub flavor: crate::Flavor,
+    pub compare: Option<crate::compare::Policy>,
+    pub resume: bool,
+    pub debug: bool,
+    pub quoting_style: crate::quoting::Style,
+    pub excludes: Vec<String>,
+    pub include_from: Vec<std::path::PathBuf>,
+    pub exclude_from: Vec<std::path::PathBuf>,
+    pub summary: bool,
+    pub mode: Option<String>,
+    pub owner: Option<String>,
+    pub group: Option<String>,
+    pub strip: bool,
+    pub strip_program: Option<std::ffi::OsString>,
+    pub strict: bool,
+    pub sync: Option<crate::Flush>,
+    pub show_effective_access: Option<std::path::PathBuf>,
+    pub tmp_dir: Option<std::path::PathBuf>,
+    pub touch_only: Option<crate::Touch>,
+    pub line_endings: Option<crate::transform::LineEndings>,
+    pub expand_vars: bool,
+    pub defines: Vec<String>,
+    pub state_file: Option<std::path::PathBuf>,
+    pub av_grace_ms: u64,
+    pub mtime_slack: u64,
+    pub operands: Vec<std::ffi::OsString>,
+    pub used: std::collections::BTreeSet<String>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            help: false,
+            version: false,
+            backup: None,
+            suffix: None,
+            verbose: None,
+            preserve_timestamps: false,
+            make_all_directories: false,
+            no_target_directory: false,
+            target_directories: Vec::new(),
+            directory_arguments: false,
+            reference: None,
+            backup_mode: crate::backup::Mode::Rename,
+            chdir: None,
+            buffer_size: None,
+            stats_file: None,
+            interactive: false,
+            force: false,
+            self_test: false,
+            expectations: Vec::new(),
+            link: None,
+            prune_backups: None,
+            keep: None,
+            older_than: None,
+            relative: false,
+            skip_if_unchanged: None,
+            no_clobber: false,
+            post_install: Vec::new(),
+            halt_on_hook_failure: false,
+            sign: false,
+            signtool: None,
+            sign_thumbprint: None,
+            no_env: false,
+            show_config: false,
+            verify_tree: None,
+            root: None,
+            stop_at_first_error: false,
+            versioned: false,
+            version_tag: None,
+            cargo_artifacts: Vec::new(),
+            preserve_creation_time: false,
+            write_through: false,
+            flavor: crate::Flavor::Gnu,
+            compare: None,
+            resume: false,
+            debug: false,
+            quoting_style: crate::quoting::Style::Shell,
+            excludes: Vec::new(),
+            include_from: Vec::new(),
+            exclude_from: Vec::new(),
+            summary: false,
+            mode: None,
+            owner: None,
+            group: None,
+            strip: false,
+            strip_program: None,
+            strict: false,
+            sync: None,
+            show_effective_access: None,
+            tmp_dir: None,
+            touch_only: None,
+            line_endings: None,
+            expand_vars: false,
+            defines: Vec::new(),
+            state_file: None,
+            av_grace_ms: 0,
+            mtime_slack: 0,
+            operands: Vec::new(),
+            used: std::collections::BTreeSet::new(),
+        }
+    }
+}
+
+// What a run does, decided by the options given. Each of the forms in the usage other than the
+// first three, which all install files, is an action of its own.
+pub enum Action {
+    Help,
+    Version,
+    ShowConfig,
+    SelfTest,
+    ShowEffectiveAccess(std::path::PathBuf),
+    VerifyTree(std::path::PathBuf),
+    PruneBackups(std::path::PathBuf),
+    Directories,
+    Install,
+}
+
+// A problem with the options or operands that stops a run before it starts.
+pub enum ArgumentError {
+    MissingArgument(&'static str),
+    MissingUnixArgument(String),
+    UnrecognizedOption(String),
+    InvalidChoice {
+        value: String,
+        context: &'static str,
+        choices: Vec<&'static str>,
+    },
+    InvalidGracePeriod(String),
+    InvalidMtimeSlack(String),
+    InvalidBufferSize(String),
+    InvalidKeep(String),
+    InvalidDays(String),
+    InvalidExpectation(String),
+    NotUnicode(String),
+    BsdOnly(&'static str),
+    PruneWithoutPolicy,
+    MissingFileOperand,
+    MissingDestination(std::ffi::OsString),
+    ConflictingTargets,
+    ExtraOperand(std::ffi::OsString),
+    InvalidBackup(crate::backup::InvalidControl),
+    InvalidVersionTag(String),
+    MissingVersionTag,
+    MissingThumbprint,
+    DefineWithoutExpandVars,
+    InvalidDefinition(String),
+    TmpDirNotDirectory(std::path::PathBuf),
+}
+
+impl ArgumentError {
+    // Prints the error as install would, followed by a pointer to --help where that is likely to
+    // be what is needed.
+    pub fn report(&self) {
+        let message = match self {
+            ArgumentError::MissingArgument(option) => {
+                format!("option {} requires an argument", option)
+            }
+            ArgumentError::MissingUnixArgument(option) => {
+                format!(
+                    "unix compatability option '{}' requires an argument",
+                    option
+                )
+            }
+            ArgumentError::UnrecognizedOption(option) => {
+                format!("unrecognized option '{}'", option)
+            }
+            ArgumentError::InvalidChoice {
+                value,
+                context,
+                choices,
+            } => {
+                let mut message = format!(
+                    "invalid argument '{}' for '{}'\nValid arguments are:",
+                    value, context
+                );
+
+                for choice in choices {
+                    message.push_str(&format!("\n  - '{}'", choice));
+                }
+
+                message
+            }
+            ArgumentError::InvalidGracePeriod(ms) => format!("invalid grace period '{}'", ms),
+            ArgumentError::InvalidMtimeSlack(seconds) => {
+                format!("invalid modification time slack '{}'", seconds)
+            }
+            ArgumentError::InvalidBufferSize(size) => format!("invalid buffer size '{}'", size),
+            ArgumentError::InvalidKeep(n) => format!("invalid number of backups to keep '{}'", n),
+            ArgumentError::InvalidDays(days) => format!("invalid number of days '{}'", days),
+            ArgumentError::InvalidExpectation(expectation) => format!(
+                "invalid argument '{}' for '--expect-sha256', expected DEST=HASH",
+                expectation
+            ),
+            ArgumentError::NotUnicode(option) => {
+                format!("the argument for {} is not valid Unicode", option)
+            }
+            ArgumentError::BsdOnly(option) => {
+                format!("option {} is only available with --flavor=bsd", option)
+            }
+            ArgumentError::PruneWithoutPolicy => {
+                "--prune-backups requires --keep and/or --older-than".to_string()
+            }
+            ArgumentError::MissingFileOperand => "missing file operand".to_string(),
+            ArgumentError::MissingDestination(source) => format!(
+                "missing destination file operand after {}",
+                crate::quoting::quote(source)
+            ),
+            ArgumentError::ConflictingTargets => {
+                "cannot combine --target-directory (-t) and no-target-directory (-T)".to_string()
+            }
+            ArgumentError::ExtraOperand(operand) => {
+                format!("extra operand {}", crate::quoting::quote(operand))
+            }
+            ArgumentError::InvalidBackup(e) => {
+                // Worded exactly as GNU install words it, program name included.
+                eprintln!(
+                    concat!(
+                        "install: {} argument ‘{}’ for ‘backup type’\n",
+                        "Valid arguments are:\n",
+                        "  - ‘none’, ‘off’\n",
+                        "  - ‘simple’, ‘never’\n",
+                        "  - ‘existing’, ‘nil’\n",
+                        "  - ‘numbered’, ‘t’\n",
+                        "Try 'install --help' for more information.",
+                    ),
+                    if e.ambiguous { "ambiguous" } else { "invalid" },
+                    e.control
+                );
+
+                return;
+            }
+            ArgumentError::InvalidVersionTag(tag) => format!("invalid version tag '{}'", tag),
+            ArgumentError::MissingVersionTag => "--versioned requires --version-tag".to_string(),
+            ArgumentError::MissingThumbprint => concat!(
+                "--sign requires a certificate thumbprint from --sign-thumbprint ",
+                "or WINSTALL_SIGN_THUMBPRINT",
+            )
+            .to_string(),
+            ArgumentError::DefineWithoutExpandVars => "--define requires --expand-vars".to_string(),
+            ArgumentError::InvalidDefinition(definition) => {
+                format!("invalid definition '{}'", definition)
+            }
+            ArgumentError::TmpDirNotDirectory(dir) => format!(
+                "temporary directory {} is not a directory",
+                crate::quoting::quote(dir)
+            ),
+        };
+
+        eprintln!("winstall: {}", message);
+
+        if !matches!(
+            self,
+            ArgumentError::MissingUnixArgument(_)
+                | ArgumentError::ConflictingTargets
+                | ArgumentError::InvalidVersionTag(_)
+                | ArgumentError::MissingThumbprint
+                | ArgumentError::TmpDirNotDirectory(_)
+        ) {
+            eprintln!("Try 'winstall --help' for more information.");
+        }
+    }
+}
+
+impl Options {
+    // Decides what the run does. The forms that don't install anything are checked for first, in
+    // the order they take precedence when more than one is asked for, starting with --help and
+    // --version which make any other problem irrelevant.
+    pub fn action(&self) -> Result<Action, ArgumentError> {
+        if self.help {
+            return Ok(Action::Help);
+        }
+
+        if self.version {
+            return Ok(Action::Version);
+        }
+
+        if self.used.contains("-B") && self.flavor != crate::Flavor::Bsd {
+            return Err(ArgumentError::BsdOnly("-B"));
+        }
+
+        if self.show_config {
+            return Ok(Action::ShowConfig);
+        }
+
+        if self.self_test {
+            return Ok(Action::SelfTest);
+        }
+
+        if let Some(destination) = &self.show_effective_access {
+            return Ok(Action::ShowEffectiveAccess(destination.clone()));
+        }
+
+        if let Some(checksums) = &self.verify_tree {
+            return Ok(Action::VerifyTree(checksums.clone()));
+        }
+
+        if let Some(dir) = &self.prune_backups {
+            if self.keep.is_none() && self.older_than.is_none() {
+                return Err(ArgumentError::PruneWithoutPolicy);
+            }
+
+            return Ok(Action::PruneBackups(dir.clone()));
+        }
+
+        match self.directory_arguments {
+            true => Ok(Action::Directories),
+            false => Ok(Action::Install),
+        }
+    }
+
+    // Checks that there are as many operands as the options call for. Whether the last one is a
+    // directory can only be told by looking at it, so that is left to the install itself.
+    pub fn check_operands(&self, operands: &[std::ffi::OsString]) -> Result<(), ArgumentError> {
+        if operands.is_empty() {
+            return Err(ArgumentError::MissingFileOperand);
+        }
+
+        if self.no_target_directory && !self.target_directories.is_empty() {
+            return Err(ArgumentError::ConflictingTargets);
+        }
+
+        if self.directory_arguments {
+            return Ok(());
+        }
+
+        if operands.len() < 2 && self.target_directories.is_empty() {
+            return Err(ArgumentError::MissingDestination(operands[0].clone()));
+        }
+
+        // Neither a file nor -T can take more than one source, so that is reported before anything
+        // is installed rather than failing part way through.
+        if self.no_target_directory && operands.len() > 2 {
+            return Err(ArgumentError::ExtraOperand(operands[2].clone()));
+        }
+
+        Ok(())
+    }
+
+    // The version to install under with --versioned. It can't yet be read from the file's own
+    // version resource, so it has to be given.
+    pub fn version(&self) -> Result<Option<String>, ArgumentError> {
+        match (self.versioned, &self.version_tag) {
+            (false, _) => Ok(None),
+            (true, Some(tag)) if !tag.is_empty() && !tag.contains(['/', '\\', ':']) => {
+                Ok(Some(tag.clone()))
+            }
+            (true, Some(tag)) => Err(ArgumentError::InvalidVersionTag(tag.clone())),
+            (true, None) => Err(ArgumentError::MissingVersionTag),
+        }
+    }
+
+    // How installed files are signed with --sign, where the thumbprint can also come from the
+    // environment.
+    pub fn signing(
+        &self,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Option<crate::hooks::Signing>, ArgumentError> {
+        if !self.sign {
+            return Ok(None);
+        }
+
+        let thumbprint = self
+            .sign_thumbprint
+            .clone()
+            .or_else(|| env("WINSTALL_SIGN_THUMBPRINT"))
+            .ok_or(ArgumentError::MissingThumbprint)?;
+
+        Ok(Some(crate::hooks::Signing {
+            signtool: self.signtool.clone().unwrap_or("signtool".into()),
+            thumbprint,
+        }))
+    }
+
+    // The variables placeholders are expanded from with --expand-vars, which are those of the
+    // environment (only read if they are needed) with each --define applied over them.
+    pub fn variables(
+        &self,
+        environment: impl FnOnce() -> std::collections::BTreeMap<String, String>,
+    ) -> Result<Option<std::collections::BTreeMap<String, String>>, ArgumentError> {
+        if !self.expand_vars {
+            return match self.defines.is_empty() {
+                true => Ok(None),
+                false => Err(ArgumentError::DefineWithoutExpandVars),
+            };
+        }
+
+        let mut variables = environment();
+
+        for definition in &self.defines {
+            match definition.split_once('=') {
+                Some((name, value)) if crate::transform::is_variable_name(name) => {
+                    variables.insert(name.to_string(), value.to_string());
+                }
+                _ => return Err(ArgumentError::InvalidDefinition(definition.clone())),
+            }
+        }
+
+        Ok(Some(variables))
+    }
+
+    // The directory given with --tmp-dir, which has to exist.
+    pub fn staging_directory(&self) -> Result<Option<std::path::PathBuf>, ArgumentError> {
+        match self.tmp_dir.clone() {
+            Some(dir) if !dir.is_dir() => Err(ArgumentError::TmpDirNotDirectory(dir)),
+            dir => Ok(dir),
+        }
+    }
+}
+
+impl Options {
+    // Parses the arguments, with any bundled short options already split up by unbundle. As with
+    // GNU install, nothing after --help or --version is looked at, so that they work whatever else
+    // is on the command line, but an option that isn't recognized before them is still an error.
+    // A file whose name starts with '-' can be given after '--'.
+    //
+    // Paths and programs are kept as they were given, so that any name the system allows can be
+    // used. Everything else is text, and a value that isn't valid Unicode is an error.
+    pub fn parse<I: IntoIterator<Item = std::ffi::OsString>>(
+        args: I,
+    ) -> Result<Options, ArgumentError> {
+        use crate::messages::Router;
+        use crate::{Flavor, Flush, Link, Touch};
+
+        let mut opts = Options::default();
+        let mut args = args.into_iter();
+
+        'arguments: while let Some(arg) = args.next() {
+            // Everything after '--' is an operand, however much it looks like an option.
+            if arg == "--" {
+                opts.operands.extend(args.by_ref());
+                break;
+            }
+
+            // Only a long option has its value joined with '=' (--suffix=.bak). A short option's
+            // value is attached to it or follows it (-S.bak, -S .bak), so '-S=.bak' gives a suffix
+            // of '=.bak' as with GNU install, and an operand such as 'a=b' is kept whole.
+            let bytes = arg.as_encoded_bytes();
+            let (argument, attached) = match bytes.iter().position(|b| *b == b'=') {
+                Some(i) if bytes.starts_with(b"--") => {
+                    let (argument, value) = split_at_ascii(&arg, i);
+                    (argument, Some(value))
+                }
+                _ => (arg.as_os_str(), None),
+            };
+
+            // Every option is valid Unicode, so an argument that isn't can only be an operand,
+            // which is kept exactly as it was given.
+            let Some(argument) = argument.to_str() else {
+                opts.operands.push(arg);
+                continue;
+            };
+
+            let attached_text = |option: &'static str| {
+                attached
+                    .map(|value| text(value.to_os_string(), option))
+                    .transpose()
+            };
+
+            let mut required = |option: &'static str| {
+                attached
+                    .map(std::ffi::OsStr::to_os_string)
+                    .or_else(|| args.next())
+                    .ok_or(ArgumentError::MissingArgument(option))
+            };
+
+            match argument {
+                "-v" => opts.verbose = Some(Router::Stdout),
+                "--verbose" => {
+                    opts.verbose = Some(choice(
+                        attached_text("--verbose")?.as_deref().unwrap_or("stdout"),
+                        "--verbose",
+                        &[("stdout", Router::Stdout), ("stderr", Router::Stderr)],
+                    )?)
+                }
+                "-p" | "--preserve-timestamps" => opts.preserve_timestamps = true,
+                "--preserve-creation-time" => {
+                    opts.preserve_timestamps = true;
+                    opts.preserve_creation_time = true;
+                }
+                "-T" | "--no-target-directory" => opts.no_target_directory = true,
+                "-D" => opts.make_all_directories = true,
+                "-i" | "--interactive" => opts.interactive = true,
+                "-n" | "--no-clobber" => opts.no_clobber = true,
+                "--halt-on-hook-failure" => opts.halt_on_hook_failure = true,
+                "--stop-at-first-error" => opts.stop_at_first_error = true,
+                "--write-through" => opts.write_through = true,
+                "--resume" => opts.resume = true,
+                "--sign" => opts.sign = true,
+                "--no-env" => opts.no_env = true,
+                "--show-config" => opts.show_config = true,
+                "--signtool" => opts.signtool = Some(required("--signtool")?),
+                "--sign-thumbprint" => {
+                    opts.sign_thumbprint =
+                        Some(text(required("--sign-thumbprint")?, "--sign-thumbprint")?)
+                }
+                "--post-install" => opts
+                    .post_install
+                    .push(text(required("--post-install")?, "--post-install")?),
+                "--define" => opts.defines.push(text(required("--define")?, "--define")?),
+                "--expand-vars" => opts.expand_vars = true,
+                "--exclude" => opts
+                    .excludes
+                    .push(text(required("--exclude")?, "--exclude")?),
+                "--exclude-from" => opts.exclude_from.push(required("--exclude-from")?.into()),
+                "--include-from" => opts.include_from.push(required("--include-from")?.into()),
+                "--tmp-dir" => opts.tmp_dir = Some(required("--tmp-dir")?.into()),
+                "--relative" => opts.relative = true,
+                "--strict" => opts.strict = true,
+                "--summary" => opts.summary = true,
+                "--sync" => {
+                    opts.sync = Some(match attached_text("--sync")? {
+                        None => Flush::Each,
+                        Some(when) => choice(&when, "--sync", &[("final", Flush::Final)])?,
+                    })
+                }
+                "--touch-only" => {
+                    opts.touch_only = Some(choice(
+                        attached_text("--touch-only")?
+                            .as_deref()
+                            .unwrap_or("source"),
+                        "--touch-only",
+                        &[("source", Touch::Source), ("now", Touch::Now)],
+                    )?)
+                }
+                "--versioned" => opts.versioned = true,
+                "--cargo-artifact" => opts
+                    .cargo_artifacts
+                    .push(text(required("--cargo-artifact")?, "--cargo-artifact")?),
+                "--version-tag" => {
+                    opts.version_tag = Some(text(required("--version-tag")?, "--version-tag")?)
+                }
+                "--force" => opts.force = true,
+                "--self-test" => opts.self_test = true,
+                "--show-effective-access" => {
+                    opts.show_effective_access = Some(required("--show-effective-access")?.into())
+                }
+                "-d" | "--directory" => opts.directory_arguments = true,
+                "-b" => opts.backup = Some(None),
+                "--backup" => opts.backup = Some(attached_text("--backup")?),
+                "--backup-mode" => {
+                    opts.backup_mode = choice(
+                        &text(required("--backup-mode")?, "--backup-mode")?,
+                        "backup mode",
+                        &[
+                            ("rename", crate::backup::Mode::Rename),
+                            ("copy", crate::backup::Mode::Copy),
+                        ],
+                    )?
+                }
+                "--line-endings" => {
+                    opts.line_endings = choice(
+                        &text(required("--line-endings")?, "--line-endings")?,
+                        "line endings",
+                        &[
+                            ("lf", Some(crate::transform::LineEndings::Lf)),
+                            ("crlf", Some(crate::transform::LineEndings::Crlf)),
+                            ("preserve", None),
+                        ],
+                    )?
+                }
+                "-C" => opts.compare = Some(crate::compare::Policy::Content),
+                "--compare" => {
+                    opts.compare = Some(choice(
+                        attached_text("--compare")?.as_deref().unwrap_or("content"),
+                        "--compare",
+                        &[
+                            ("content", crate::compare::Policy::Content),
+                            ("hash", crate::compare::Policy::Hash),
+                            ("mtime", crate::compare::Policy::Mtime),
+                            ("size", crate::compare::Policy::Size),
+                        ],
+                    )?)
+                }
+                "--debug" => opts.debug = true,
+                "--flavor" => {
+                    opts.flavor = choice(
+                        &text(required("--flavor")?, "--flavor")?,
+                        "--flavor",
+                        &[("gnu", Flavor::Gnu), ("bsd", Flavor::Bsd)],
+                    )?
+                }
+                "--quoting-style" => {
+                    opts.quoting_style = choice(
+                        &text(required("--quoting-style")?, "--quoting-style")?,
+                        "--quoting-style",
+                        &[
+                            ("literal", crate::quoting::Style::Literal),
+                            ("shell", crate::quoting::Style::Shell),
+                            ("c", crate::quoting::Style::C),
+                        ],
+                    )?
+                }
+                "-B" => opts.suffix = Some(text(required("-B")?, "-B")?),
+                "-S" | "--suffix" => {
+                    opts.suffix = Some(text(required("--suffix (-S)")?, "--suffix (-S)")?)
+                }
+                "-t" | "--target-directory" => opts
+                    .target_directories
+                    .push(required("--target-directory (-t)")?.into()),
+                "--chdir" => opts.chdir = Some(required("--chdir")?.into()),
+                "--av-grace-ms" => {
+                    let ms = text(required("--av-grace-ms")?, "--av-grace-ms")?;
+                    opts.av_grace_ms = ms
+                        .parse()
+                        .map_err(|_| ArgumentError::InvalidGracePeriod(ms))?;
+                }
+                "--mtime-slack" => {
+                    let seconds = text(required("--mtime-slack")?, "--mtime-slack")?;
+                    opts.mtime_slack = seconds
+                        .parse()
+                        .map_err(|_| ArgumentError::InvalidMtimeSlack(seconds))?;
+                }
+                "--buffer-size" => {
+                    let size = text(required("--buffer-size")?, "--buffer-size")?;
+                    match parse_size(&size) {
+                        Some(n) if n > 0 => opts.buffer_size = Some(n),
+                        _ => return Err(ArgumentError::InvalidBufferSize(size)),
+                    }
+                }
+                "--skip-if-unchanged" => {
+                    opts.skip_if_unchanged = Some(required("--skip-if-unchanged")?.into())
+                }
+                "--state-file" => opts.state_file = Some(required("--state-file")?.into()),
+                "--stats-file" => opts.stats_file = Some(required("--stats-file")?.into()),
+                "--expect-sha256" => {
+                    let expectation = required("--expect-sha256")?;
+                    opts.expectations
+                        .push(self::expectation(&expectation).ok_or_else(|| {
+                            ArgumentError::InvalidExpectation(
+                                expectation.to_string_lossy().into_owned(),
+                            )
+                        })?);
+                }
+                "--link" => {
+                    opts.link = Some(choice(
+                        &text(required("--link")?, "--link")?,
+                        "link type",
+                        &[
+                            ("hard", Link::Hard),
+                            ("hard-or-fail", Link::HardOrFail),
+                            ("symbolic", Link::Symbolic { relative: false }),
+                        ],
+                    )?)
+                }
+                "--prune-backups" => opts.prune_backups = Some(required("--prune-backups")?.into()),
+                "--verify-tree" => opts.verify_tree = Some(required("--verify-tree")?.into()),
+                "--root" => opts.root = Some(required("--root")?.into()),
+                "--keep" => {
+                    let n = text(required("--keep")?, "--keep")?;
+                    opts.keep = Some(n.parse().map_err(|_| ArgumentError::InvalidKeep(n))?);
+                }
+                "--older-than" => {
+                    let days = text(required("--older-than")?, "--older-than")?;
+                    opts.older_than =
+                        Some(days.parse().map_err(|_| ArgumentError::InvalidDays(days))?);
+                }
+                "--reference" => opts.reference = Some(required("--reference")?.into()),
+                "--help" => {
+                    opts.help = true;
+                    break 'arguments;
+                }
+                "--version" => {
+                    opts.version = true;
+                    break 'arguments;
+                }
+
+                // Ignored UNIX specific options that don't expect a value (or expect an equals
+                // separated one).
+                "-c" | "--preserve-context" => (),
+                "-Z" | "--context" => (),
+
+                // UNIX specific options, which are only applied on unix.
+                "-s" | "--strip" => opts.strip = true,
+                "-g" | "--group" | "-m" | "--mode" | "-o" | "--owner" | "--strip-program" => {
+                    let value = attached
+                        .map(std::ffi::OsStr::to_os_string)
+                        .or_else(|| args.next())
+                        .ok_or_else(|| ArgumentError::MissingUnixArgument(argument.to_owned()))?;
+
+                    let text = |value: std::ffi::OsString| {
+                        value
+                            .into_string()
+                            .map_err(|_| ArgumentError::NotUnicode(argument.to_owned()))
+                    };
+
+                    match argument {
+                        "-g" | "--group" => opts.group = Some(text(value)?),
+                        "-m" | "--mode" => opts.mode = Some(text(value)?),
+                        "-o" | "--owner" => opts.owner = Some(text(value)?),
+                        _ => opts.strip_program = Some(value),
+                    }
+                }
+                _ if argument.starts_with('-') && argument != "-" => {
+                    return Err(ArgumentError::UnrecognizedOption(argument.to_owned()));
+                }
+                _ => {
+                    opts.operands.push(arg);
+                    continue;
+                }
+            }
+
+            opts.used.insert(argument.to_owned());
+        }
+
+        Ok(opts)
+    }
+
+    // Parses the command line with the options given in the environment before it, so that the
+    // command line takes precedence. Whether --no-env was given is decided by parsing the command
+    // line alone first, so that an argument that only looks like it (the value of -S, or an operand
+    // after '--') doesn't count.
+    pub fn parse_with_environment(
+        command_line: Vec<std::ffi::OsString>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Options, ArgumentError> {
+        let given = Options::parse(command_line.clone())?;
+        if given.no_env {
+            return Ok(given);
+        }
+
+        let mut opts = Options::parse(
+            environment_arguments(env)
+                .into_iter()
+                .map(std::ffi::OsString::from)
+                .chain(command_line),
+        )?;
+
+        // An option that can be given more than once collects every value, so a list from the
+        // environment would otherwise be added to by the command line rather than replaced, as a
+        // single value is.
+        replace_list(&mut opts.excludes, given.excludes);
+        replace_list(&mut opts.include_from, given.include_from);
+        replace_list(&mut opts.exclude_from, given.exclude_from);
+        replace_list(&mut opts.post_install, given.post_install);
+
+        Ok(opts)
+    }
+}
+
+fn replace_list<T>(list: &mut Vec<T>, given: Vec<T>) {
+    if !given.is_empty() {
+        *list = given;
+    }
+}
+
+// Finds the value that name stands for among the choices an option has.
+fn choice<T: Copy>(
+    name: &str,
+    context: &'static str,
+    choices: &[(&'static str, T)],
+) -> Result<T, ArgumentError> {
+    match choices.iter().find(|(choice, _)| *choice == name) {
+        Some((_, value)) => Ok(*value),
+        None => Err(ArgumentError::InvalidChoice {
+            value: name.to_string(),
+            context,
+            choices: choices.iter().map(|(choice, _)| *choice).collect(),
+        }),
+    }
+}
+
+// The value of an option that can only be text.
+fn text(value: std::ffi::OsString, option: &'static str) -> Result<String, ArgumentError> {
+    value
+        .into_string()
+        .map_err(|_| ArgumentError::NotUnicode(option.to_string()))
+}
+
+// Splits an --expect-sha256 value of DEST=HASH at its last '=', as the hash can't contain one but
+// the destination can.
+fn expectation(value: &std::ffi::OsStr) -> Option<(std::path::PathBuf, String)> {
+    let i = value.as_encoded_bytes().iter().rposition(|b| *b == b'=')?;
+    let (path, hash) = split_at_ascii(value, i);
+    let hash = hash.to_str()?;
+
+    match !path.is_empty() && crate::sha256::is_hex_digest(hash) {
+        true => Some((path.into(), hash.to_ascii_lowercase())),
+        false => None,
+    }
+}
+
+// Splits s into what comes before and after the ASCII character at i, which is how an option is
+// parted from its value.
+fn split_at_ascii(s: &std::ffi::OsStr, i: usize) -> (&std::ffi::OsStr, &std::ffi::OsStr) {
+    let bytes = s.as_encoded_bytes();
+    assert!(bytes[i].is_ascii());
+
+    // SAFETY: an ASCII character is never part of a longer encoded sequence, so both halves start
+    // and end either at an end of s or beside one, and are each made of whole characters of it as
+    // from_encoded_bytes_unchecked requires.
+    unsafe {
+        (
+            std::ffi::OsStr::from_encoded_bytes_unchecked(&bytes[..i]),
+            std::ffi::OsStr::from_encoded_bytes_unchecked(&bytes[i + 1..]),
+        )
+    }
+}
+
+pub fn parse_size(s: &str) -> Option<usize> {
+    let (digits, multiplier) = match s.char_indices().last()? {
+        (i, 'K' | 'k') => (&s[..i], 1 << 10),
+        (i, 'M' | 'm') => (&s[..i], 1 << 20),
+        (i, 'G' | 'g') => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+
+    digits.parse::<usize>().ok()?.checked_mul(multiplier)
+}
+
+// Whether an option given in the environment stands on its own, can be given a value, or has to be.
+#[derive(Clone, Copy)]
+enum Kind {
+    Flag,
+    OptionalValue,
+    Value,
+}
+
+// The options that can also be given in the environment, as WINSTALL_ followed by the name in upper
+// case with '_' for '-' (WINSTALL_BACKUP_MODE for --backup-mode). Options that choose what winstall
+// does, rather than how, are left out.
+const ENVIRONMENT_OPTIONS: [(&str, Kind); 38] = [
+    ("av-grace-ms", Kind::Value),
+    ("backup", Kind::OptionalValue),
+    ("backup-mode", Kind::Value),
+    ("buffer-size", Kind::Value),
+    ("compare", Kind::OptionalValue),
+    ("debug", Kind::Flag),
+    ("exclude", Kind::Value),
+    ("exclude-from", Kind::Value),
+    ("flavor", Kind::Value),
+    ("force", Kind::Flag),
+    ("group", Kind::Value),
+    ("halt-on-hook-failure", Kind::Flag),
+    ("include-from", Kind::Value),
+    ("interactive", Kind::Flag),
+    ("line-endings", Kind::Value),
+    ("link", Kind::Value),
+    ("mode", Kind::Value),
+    ("mtime-slack", Kind::Value),
+    ("no-clobber", Kind::Flag),
+    ("owner", Kind::Value),
+    ("post-install", Kind::Value),
+    ("preserve-creation-time", Kind::Flag),
+    ("preserve-timestamps", Kind::Flag),
+    ("quoting-style", Kind::Value),
+    ("relative", Kind::Flag),
+    ("resume", Kind::Flag),
+    ("sign", Kind::Flag),
+    ("signtool", Kind::Value),
+    ("state-file", Kind::Value),
+    ("stats-file", Kind::Value),
+    ("stop-at-first-error", Kind::Flag),
+    ("strict", Kind::Flag),
+    ("strip", Kind::Flag),
+    ("strip-program", Kind::Value),
+    ("summary", Kind::Flag),
+    ("sync", Kind::OptionalValue),
+    ("tmp-dir", Kind::Value),
+    ("verbose", Kind::OptionalValue),
+];
+
+// Turns the WINSTALL_* variables that are set into the options they stand for. For an option that
+// can stand on its own a value of 1, true or yes gives it without a value (WINSTALL_VERBOSE=1 is
+// --verbose), and one of 0, false or no leaves it out. Any other value is the option's value
+// (WINSTALL_BACKUP=numbered is --backup=numbered), as is every value of an option that has to have
+// one, so WINSTALL_AV_GRACE_MS=0 is --av-grace-ms=0. An empty value always leaves the option out.
+pub fn environment_arguments(env: impl Fn(&str) -> Option<String>) -> Vec<String> {
+    let mut arguments = Vec::new();
+
+    for (option, kind) in ENVIRONMENT_OPTIONS {
+        match (kind, env(&variable(option)).as_deref()) {
+            (_, None) | (_, Some("")) => (),
+            (Kind::Value, Some(value)) => arguments.push(format!("--{}={}", option, value)),
+            (_, Some("0" | "false" | "no")) => (),
+            (_, Some("1" | "true" | "yes")) => arguments.push(format!("--{}", option)),
+            (_, Some(value)) => arguments.push(format!("--{}={}", option, value)),
+        }
+    }
+
+    arguments
+}
+
+// The variable that stands for option.
+fn variable(option: &str) -> String {
+    format!("WINSTALL_{}", option.to_uppercase().replace('-', "_"))
+}
+
+// Every variable that changes what a run does: those standing for options, and the ones read
+// directly.
+pub fn environment_variables() -> Vec<String> {
+    let options = ENVIRONMENT_OPTIONS
+        .iter()
+        .map(|(option, _)| variable(option));
+
+    let direct = [
+        "WINSTALL_SIGN_THUMBPRINT",
+        "VERSION_CONTROL",
+        "SIMPLE_BACKUP_SUFFIX",
+    ];
+
+    options.chain(direct.map(str::to_string)).collect()
+}
+
+// Like GNU install, short options can be bundled together (-vpD), with the value of one that
+// takes a value either attached to it (-S.bak, -tdir) or in the following argument. They are split
+// up before parsing so that each argument holds a single option. Anything containing a letter that
+// isn't a short option is left alone, for parsing to refuse as an option it doesn't recognize.
+//
+// The tests check that, whatever the arguments:
+//   - everything after '--' comes out as it went in;
+//   - the pieces a bundle is split into join back up into it ('-', each letter, then the value);
+//   - unbundling again changes nothing, unless an attached value itself looks like a bundle (the
+//     suffix in -S-vp is '-vp', which a second pass would split);
+//   - parsing what comes out never panics, and leaves the operands in the order they were given.
+pub fn unbundle<I: Iterator<Item = std::ffi::OsString>>(args: I) -> Vec<std::ffi::OsString> {
+    const FLAGS: &str = "bCcDdinpsTvZ";
+    const TAKES_VALUE: &str = "BgmoSt";
+
+    let mut unbundled = Vec::new();
+
+    let mut args = args;
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            unbundled.push(arg);
+            unbundled.extend(args);
+            break;
+        }
+
+        let bytes = arg.as_encoded_bytes();
+        if bytes.len() < 3 || bytes[0] != b'-' || bytes[1] == b'-' {
+            unbundled.push(arg);
+            continue;
+        }
+
+        let mut options = Vec::new();
+        let mut valid = true;
+
+        // The letters are all ASCII, so the bundle can be read a byte at a time, and the value of
+        // an option (which is kept as it was given) starts right after its letter.
+        for (i, c) in bytes.iter().map(|b| *b as char).enumerate().skip(1) {
+            if c.is_ascii() && FLAGS.contains(c) {
+                options.push(format!("-{}", c).into());
+            } else if c.is_ascii() && TAKES_VALUE.contains(c) {
+                options.push(format!("-{}", c).into());
+
+                let (_, value) = split_at_ascii(&arg, i);
+                if !value.is_empty() {
+                    options.push(value.to_os_string());
+                }
+
+                break;
+            } else {
+                valid = false;
+                break;
+            }
+        }
+
+        match valid {
+            true => unbundled.extend(options),
+            false => unbundled.push(arg),
+        }
+    }
+
+    unbundled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{environment_arguments, unbundle, ArgumentError, Options};
+
+    fn parse(args: Vec<std::ffi::OsString>) -> Result<Options, ArgumentError> {
+        Options::parse(unbundle(args.into_iter()))
+    }
+
+    fn options(args: Vec<std::ffi::OsString>) -> Options {
+        match parse(args) {
+            Ok(options) => options,
+            Err(e) => {
+                e.report();
+                panic!("arguments were refused");
+            }
+        }
+    }
+
+    // A name that isn't valid Unicode: a lone continuation byte on unix, and an unpaired surrogate
+    // on Windows.
+    #[cfg(unix)]
+    fn invalid(prefix: &str) -> std::ffi::OsString {
+        use std::os::unix::ffi::OsStringExt;
+
+        let mut bytes = prefix.as_bytes().to_vec();
+        bytes.push(0xff);
+        std::ffi::OsString::from_vec(bytes)
+    }
+
+    #[cfg(windows)]
+    fn invalid(prefix: &str) -> std::ffi::OsString {
+        use std::os::windows::ffi::OsStringExt;
+
+        let mut wide = prefix.encode_utf16().collect::<Vec<_>>();
+        wide.push(0xd800);
+        std::ffi::OsString::from_wide(&wide)
+    }
+
+    #[test]
+    fn paths_that_are_not_unicode_are_kept_as_given() {
+        let name = invalid("d");
+
+        for args in [
+            vec!["-t".into(), name.clone()],
+            vec![invalid("-td")],
+            vec![invalid("--target-directory=d")],
+        ] {
+            let opts = options(args);
+            assert_eq!(
+                opts.target_directories,
+                vec![std::path::PathBuf::from(&name)]
+            );
+        }
+
+        let opts = options(vec![invalid("--tmp-dir=d"), invalid("--chdir=d")]);
+        assert_eq!(opts.tmp_dir.as_deref(), Some(name.as_ref()));
+        assert_eq!(opts.chdir.as_deref(), Some(name.as_ref()));
+    }
+
+    #[test]
+    fn operands_that_are_not_unicode_are_kept_as_given() {
+        let name = invalid("source");
+        let opts = options(vec![name.clone(), "-v".into(), "dest".into()]);
+
+        assert_eq!(opts.operands, vec![name, "dest".into()]);
+        assert!(opts.verbose.is_some());
+    }
+
+    #[test]
+    fn unbundles_values_that_are_not_unicode() {
+        assert_eq!(
+            unbundle(vec![invalid("-vtd")].into_iter()),
+            vec!["-v".into(), "-t".into(), invalid("d")]
+        );
+
+        // A byte that can't be an option letter means the argument isn't a bundle at all.
+        assert_eq!(
+            unbundle(vec![invalid("-v")].into_iter()),
+            vec![invalid("-v")]
+        );
+    }
+
+    #[test]
+    fn expectations_keep_the_destination_as_given() {
+        let hash = "A".repeat(64);
+        let mut value = invalid("d");
+        value.push(format!("={}", hash));
+
+        let opts = options(vec!["--expect-sha256".into(), value]);
+        assert_eq!(
+            opts.expectations,
+            vec![(invalid("d").into(), hash.to_ascii_lowercase())]
+        );
+    }
+
+    #[test]
+    fn text_that_is_not_unicode_is_refused() {
+        for (args, option) in [
+            (vec![invalid("--suffix=.")], "--suffix (-S)"),
+            (vec![invalid("-S.")], "--suffix (-S)"),
+            (vec!["--backup-mode".into(), invalid("c")], "--backup-mode"),
+            (vec![invalid("--verbose=")], "--verbose"),
+            (vec!["-m".into(), invalid("7")], "-m"),
+        ] {
+            match parse(args) {
+                Err(ArgumentError::NotUnicode(refused)) => assert_eq!(refused, option),
+                _ => panic!("{} was not refused", option),
+            }
+        }
+    }
+
+    fn from_environment(variables: &[(&str, &str)]) -> Vec<String> {
+        environment_arguments(|name| {
+            variables
+                .iter()
+                .find(|(variable, _)| *variable == name)
+                .map(|(_, value)| value.to_string())
+        })
+    }
+
+    #[test]
+    fn flags_in_the_environment_are_turned_on_and_off() {
+        for value in ["1", "true", "yes"] {
+            assert_eq!(from_environment(&[("WINSTALL_DEBUG", value)]), ["--debug"]);
+        }
+
+        for value in ["0", "false", "no", ""] {
+            assert!(from_environment(&[("WINSTALL_DEBUG", value)]).is_empty());
+        }
+    }
+
+    #[test]
+    fn optional_values_in_the_environment_can_be_left_off() {
+        assert_eq!(
+            from_environment(&[("WINSTALL_VERBOSE", "1")]),
+            ["--verbose"]
+        );
+        assert!(from_environment(&[("WINSTALL_VERBOSE", "no")]).is_empty());
+        assert_eq!(
+            from_environment(&[("WINSTALL_VERBOSE", "stderr")]),
+            ["--verbose=stderr"]
+        );
+        assert_eq!(
+            from_environment(&[("WINSTALL_BACKUP", "numbered")]),
+            ["--backup=numbered"]
+        );
+    }
+
+    #[test]
+    fn values_in_the_environment_are_always_given() {
+        for value in ["0", "1", "true", "no"] {
+            assert_eq!(
+                from_environment(&[("WINSTALL_AV_GRACE_MS", value)]),
+                [format!("--av-grace-ms={}", value)]
+            );
+        }
+
+        assert_eq!(from_environment(&[("WINSTALL_MODE", "1")]), ["--mode=1"]);
+        assert!(from_environment(&[("WINSTALL_TMP_DIR", "")]).is_empty());
+    }
+
+    #[test]
+    fn environment_options_come_out_in_a_fixed_order() {
+        assert_eq!(
+            from_environment(&[
+                ("WINSTALL_VERBOSE", "1"),
+                ("WINSTALL_BACKUP_MODE", "copy"),
+                ("WINSTALL_UNKNOWN", "1"),
+            ]),
+            ["--backup-mode=copy", "--verbose"]
+        );
+    }
+
+    #[test]
+    fn no_env_is_only_an_option_where_it_is_parsed_as_one() {
+        let env = |name: &str| (name == "WINSTALL_VERBOSE").then(|| "1".to_string());
+
+        let opts = Options::parse_with_environment(
+            vec!["-S".into(), "--no-env".into(), "a".into(), "b".into()],
+            env,
+        );
+
+        let Ok(opts) = opts else {
+            panic!("arguments were refused");
+        };
+
+        assert_eq!(opts.suffix.as_deref(), Some("--no-env"));
+        assert!(!opts.no_env);
+        assert!(opts.verbose.is_some());
+
+        let Ok(opts) = Options::parse_with_environment(vec!["--".into(), "--no-env".into()], env)
+        else {
+            panic!("arguments were refused");
+        };
+
+        assert!(opts.verbose.is_some());
+        assert_eq!(opts.operands, vec![std::ffi::OsString::from("--no-env")]);
+
+        let Ok(opts) = Options::parse_with_environment(vec!["--no-env".into()], env) else {
+            panic!("arguments were refused");
+        };
+
+        assert!(opts.no_env);
+        assert!(opts.verbose.is_none());
+    }
+
+    #[test]
+    fn lists_on_the_command_line_replace_those_in_the_environment() {
+        let env = |name: &str| match name {
+            "WINSTALL_EXCLUDE" => Some("*.pdb".to_string()),
+            "WINSTALL_POST_INSTALL" => Some("hook".to_string()),
+            _ => None,
+        };
+
+        let Ok(opts) = Options::parse_with_environment(vec!["--exclude=*.txt".into()], env) else {
+            panic!("arguments were refused");
+        };
+
+        assert_eq!(opts.excludes, ["*.txt"]);
+        assert_eq!(opts.post_install, ["hook"]);
+
+        let args = vec!["--exclude=*.txt".into(), "--exclude=*.md".into()];
+        let Ok(opts) = Options::parse_with_environment(args, env) else {
+            panic!("arguments were refused");
+        };
+
+        assert_eq!(opts.excludes, ["*.txt", "*.md"]);
+    }
+
+    #[test]
+    fn options_that_are_not_recognized_are_refused() {
+        for args in [
+            vec!["--badopt", "--help"],
+            vec!["--badopt=1", "a", "b"],
+            vec!["-q", "--version"],
+            vec!["-vx", "a", "b"],
+        ] {
+            let args = args.into_iter().map(std::ffi::OsString::from).collect();
+            match parse(args) {
+                Err(ArgumentError::UnrecognizedOption(_)) => (),
+                _ => panic!("an unrecognized option was not refused"),
+            }
+        }
+
+        // Nothing after --help is looked at, and a name after '--' is never an option.
+        assert!(options(vec!["--help".into(), "--badopt".into()]).help);
+        assert_eq!(
+            options(vec!["--".into(), "-q".into(), "-".into()]).operands,
+            ["-q", "-"]
+        );
+    }
+
+    #[test]
+    fn only_long_options_are_split_on_equals() {
+        let Ok(opts) = parse(vec!["-S=.bak".into(), "a=b".into(), "c".into()]) else {
+            panic!("arguments were refused");
+        };
+
+        assert_eq!(opts.suffix.as_deref(), Some("=.bak"));
+        assert_eq!(
+            opts.operands,
+            vec![std::ffi::OsString::from("a=b"), "c".into()]
+        );
+
+        let Ok(opts) = parse(vec!["--suffix=.bak".into()]) else {
+            panic!("arguments were refused");
+        };
+
+        assert_eq!(opts.suffix.as_deref(), Some(".bak"));
+
+        // Only the first '=' parts an option from its value.
+        let Ok(opts) = parse(vec!["--suffix==.bak".into()]) else {
+            panic!("arguments were refused");
+        };
+
+        assert_eq!(opts.suffix.as_deref(), Some("=.bak"));
+    }
+
+    #[test]
+    fn splits_bundled_short_options() {
+        let unbundled = |arg: &str| unbundle(vec![arg.into()].into_iter());
+
+        assert_eq!(unbundled("-vpD"), ["-v", "-p", "-D"]);
+        assert_eq!(unbundled("-S.bak"), ["-S", ".bak"]);
+        assert_eq!(unbundled("-tdir"), ["-t", "dir"]);
+        assert_eq!(unbundled("-vbS.bak"), ["-v", "-b", "-S", ".bak"]);
+        assert_eq!(unbundled("-vS"), ["-v", "-S"]);
+
+        // Anything with a letter that isn't a short option is left alone.
+        assert_eq!(unbundled("-vx"), ["-vx"]);
+        assert_eq!(unbundled("--verbose"), ["--verbose"]);
+        assert_eq!(unbundled("-v"), ["-v"]);
+    }
+
+    #[test]
+    fn leaves_bundles_after_double_dash_alone() {
+        assert_eq!(
+            unbundle(vec!["-vp".into(), "--".into(), "-vp".into()].into_iter()),
+            ["-v", "-p", "--", "-vp"]
+        );
+    }
+
+    // A xorshift generator, so that the property tests below are repeatable without needing a
+    // crate for random numbers.
+    struct Xorshift(u64);
+
+    impl Xorshift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn pick<'a, T>(&mut self, choices: &'a [T]) -> &'a T {
+            &choices[self.below(choices.len())]
+        }
+    }
+
+    const PIECES: [&str; 16] = [
+        "-", "--", "-v", "-S", "-t", "v", "p", "D", "S", "t", "x", "=", ".bak", "dir", "a=b", "é",
+    ];
+
+    // An argument made of a few pieces, which now and then isn't valid Unicode.
+    fn argument(rng: &mut Xorshift) -> std::ffi::OsString {
+        let mut arg = std::ffi::OsString::new();
+
+        for _ in 0..=rng.below(4) {
+            arg.push(rng.pick(&PIECES));
+        }
+
+        match rng.below(8) {
+            0 => invalid(&arg.to_string_lossy()),
+            _ => arg,
+        }
+    }
+
+    fn arguments(rng: &mut Xorshift) -> Vec<std::ffi::OsString> {
+        (0..rng.below(8)).map(|_| argument(rng)).collect()
+    }
+
+    const ROUNDS: u64 = 2000;
+
+    #[test]
+    fn unbundling_passes_everything_after_double_dash_through() {
+        let mut rng = Xorshift(0x2545f4914f6cdd1d);
+
+        for _ in 0..ROUNDS {
+            let (before, after) = (arguments(&mut rng), arguments(&mut rng));
+            let args = [before, vec!["--".into()], after].concat();
+
+            let unbundled = unbundle(args.clone().into_iter());
+
+            // A bundle such as -t-- can give a '--' of its own, so the output is only checked to
+            // end with everything from the first '--' that was given.
+            let first = args.iter().position(|a| a == "--").unwrap();
+            assert!(unbundled.ends_with(&args[first..]), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn unbundled_pieces_join_back_up() {
+        let mut rng = Xorshift(0x9e3779b97f4a7c15);
+
+        for _ in 0..ROUNDS {
+            let arg = argument(&mut rng);
+            if arg == "--" {
+                continue;
+            }
+
+            let pieces = unbundle(vec![arg.clone()].into_iter());
+
+            let mut joined = std::ffi::OsString::from("-");
+            let mut value = false;
+
+            for piece in &pieces {
+                match piece.to_str().and_then(|p| p.strip_prefix('-')) {
+                    Some(letter) if !value && letter.len() == 1 => {
+                        joined.push(letter);
+                        value = "BgmoSt".contains(letter);
+                    }
+                    _ => joined.push(piece),
+                }
+            }
+
+            match pieces.len() {
+                1 => assert_eq!(pieces[0], arg),
+                _ => assert_eq!(joined, arg, "{:?}", pieces),
+            }
+        }
+    }
+
+    #[test]
+    fn unbundling_twice_changes_nothing() {
+        let mut rng = Xorshift(0xdeadbeefcafef00d);
+
+        for _ in 0..ROUNDS {
+            let args = arguments(&mut rng);
+            let once = unbundle(args.clone().into_iter());
+
+            // A value attached in a bundle is taken as it is, even when it looks like a bundle.
+            let looks_like_a_bundle = once.windows(2).any(|pair| {
+                let takes_value =
+                    ["-B", "-g", "-m", "-o", "-S", "-t"].map(std::ffi::OsString::from);
+                takes_value.contains(&pair[0])
+                    && pair[1].as_encoded_bytes().starts_with(b"-")
+                    && !args.contains(&pair[1])
+            });
+
+            if !looks_like_a_bundle {
+                assert_eq!(unbundle(once.clone().into_iter()), once, "{:?}", args);
+            }
+        }
+
+        assert_eq!(unbundle(vec!["-S-vp".into()].into_iter()), ["-S", "-vp"]);
+    }
+
+    #[test]
+    fn parsing_never_panics() {
+        let mut rng = Xorshift(0x0123456789abcdef);
+
+        for _ in 0..ROUNDS {
+            _ = parse(arguments(&mut rng));
+        }
+    }
+
+    #[test]
+    fn operands_come_out_in_order() {
+        const OPTIONS: [&str; 8] = [
+            "-v",
+            "-pD",
+            "--suffix=.bak",
+            "-S.old",
+            "--backup",
+            "--verbose=stderr",
+            "-tdir",
+            "--no-env",
+        ];
+
+        let mut rng = Xorshift(0xfeedfacefeedface);
+
+        for _ in 0..ROUNDS {
+            let mut args = Vec::<std::ffi::OsString>::new();
+            let mut operands = Vec::<std::ffi::OsString>::new();
+
+            for i in 0..rng.below(12) {
+                match rng.below(3) {
+                    0 => args.push(rng.pick(&OPTIONS).into()),
+                    _ => {
+                        let operand = format!("operand{}", i);
+                        args.push(operand.clone().into());
+                        operands.push(operand.into());
+                    }
+                }
+            }
+
+            // Anything at all can follow '--', and all of it is an operand.
+            if rng.below(2) == 0 {
+                let after = arguments(&mut rng);
+                args.push("--".into());
+                args.extend(after.iter().cloned());
+                operands.extend(after);
+            }
+
+            let Ok(opts) = parse(args.clone()) else {
+                panic!("{:?} was refused", args);
+            };
+
+            assert_eq!(opts.operands, operands, "{:?}", args);
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use std::io::Read;
+
+// How a destination is judged to be the same as its source, trading accuracy for speed.
+#[derive(Clone, Copy)]
+pub enum Policy {
+    Content,
+    Hash,
+    Mtime,
+    Size,
+}
+
+impl Policy {
+    pub fn name(self) -> &'static str {
+        match self {
+            Policy::Content => "content",
+            Policy::Hash => "hash",
+            Policy::Mtime => "mtime",
+            Policy::Size => "size",
+        }
+    }
+
+    // Files of different lengths are never the same, whatever the policy. Beyond that, mtime
+    // trusts modification times no more than slack apart and size trusts the length alone, while
+    // content and hash read both files in full.
+    pub fn same<A: AsRef<std::path::Path>, B: AsRef<std::path::Path>>(
+        self,
+        a: A,
+        b: B,
+        slack: std::time::Duration,
+    ) -> std::io::Result<bool> {
+        let metadata_a = std::fs::metadata(a.as_ref())?;
+        let metadata_b = std::fs::metadata(b.as_ref())?;
+
+        if metadata_a.len() != metadata_b.len() {
+            return Ok(false);
+        }
+
+        match self {
+            Policy::Content => same_content(a, b),
+            Policy::Hash => {
+                Ok(crate::sha256::file(a.as_ref())? == crate::sha256::file(b.as_ref())?)
+            }
+            Policy::Mtime => {
+                let (a, b) = (metadata_a.modified()?, metadata_b.modified()?);
+                Ok(a.duration_since(b).unwrap_or_else(|e| e.duration()) <= slack)
+            }
+            Policy::Size => Ok(true),
+        }
+    }
+}
+
+// Whether the two files have identical content. Files of different lengths are told apart without
+// reading either of them.
+pub fn same_content<A: AsRef<std::path::Path>, B: AsRef<std::path::Path>>(
+    a: A,
+    b: B,
+) -> std::io::Result<bool> {
+    let mut a = std::fs::File::open(a.as_ref())?;
+    let mut b = std::fs::File::open(b.as_ref())?;
+
+    if a.metadata()?.len() != b.metadata()?.len() {
+        return Ok(false);
+    }
+
+    let mut buffer_a = vec![0; 64 * 1024];
+    let mut buffer_b = vec![0; 64 * 1024];
+
+    loop {
+        let n = read_full(&mut a, &mut buffer_a)?;
+        if n != read_full(&mut b, &mut buffer_b)? || buffer_a[..n] != buffer_b[..n] {
+            return Ok(false);
+        }
+
+        if n == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+// Whether the content of partial is the start of the content of full, as is left behind by a copy
+// that was interrupted. Returns the length of partial when it is.
+pub fn is_prefix<P: AsRef<std::path::Path>, F: AsRef<std::path::Path>>(
+    partial: P,
+    full: F,
+) -> std::io::Result<Option<u64>> {
+    let partial = std::fs::File::open(partial.as_ref())?;
+    let full = std::fs::File::open(full.as_ref())?;
+
+    let length = partial.metadata()?.len();
+    if length == 0 || length >= full.metadata()?.len() {
+        return Ok(None);
+    }
+
+    let mut partial = partial.take(length);
+    let mut full = full.take(length);
+
+    let mut buffer_partial = vec![0; 64 * 1024];
+    let mut buffer_full = vec![0; 64 * 1024];
+
+    loop {
+        let n = read_full(&mut partial, &mut buffer_partial)?;
+        if n != read_full(&mut full, &mut buffer_full)? || buffer_partial[..n] != buffer_full[..n] {
+            return Ok(None);
+        }
+
+        if n == 0 {
+            return Ok(Some(length));
+        }
+    }
+}
+
+// Reads until the buffer is full or the end of the file, so that both files are always compared
+// in the same sized pieces.
+fn read_full<R: Read>(file: &mut R, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+
+    while filled < buffer.len() {
+        match file.read(&mut buffer[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Policy;
+    use crate::scratch::{Element, Scratch};
+
+    const POLICIES: [Policy; 4] = [Policy::Content, Policy::Hash, Policy::Mtime, Policy::Size];
+    const ZERO: std::time::Duration = std::time::Duration::ZERO;
+
+    fn set_modified(p: std::path::PathBuf, seconds: u64) {
+        let file = std::fs::OpenOptions::new().write(true).open(p).unwrap();
+        let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(seconds);
+        file.set_modified(time).unwrap();
+    }
+
+    // Content longer than the buffers that files are compared through.
+    fn long(change: Option<usize>) -> Vec<u8> {
+        let mut content = (0..200_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+
+        if let Some(i) = change {
+            content[i] ^= 1;
+        }
+
+        content
+    }
+
+    #[test]
+    fn finds_identical_files_the_same_by_every_policy() {
+        let scratch = Scratch::unique("compare-same");
+        scratch.declare(&[
+            ("a", Element::File("content")),
+            ("b", Element::File("content")),
+        ]);
+
+        set_modified(scratch.join("a"), 1_000_000);
+        set_modified(scratch.join("b"), 1_000_000);
+
+        for policy in POLICIES {
+            let same = policy
+                .same(scratch.join("a"), scratch.join("b"), ZERO)
+                .unwrap();
+            assert!(same, "{}", policy.name());
+        }
+    }
+
+    // Files of the same length that differ in content and time are told apart by every policy
+    // except size, which only looks at the length.
+    #[test]
+    fn tells_different_files_apart() {
+        let scratch = Scratch::unique("compare-different");
+        scratch.declare(&[
+            ("a", Element::File("content")),
+            ("b", Element::File("CONTENT")),
+        ]);
+
+        set_modified(scratch.join("a"), 1_000_000);
+        set_modified(scratch.join("b"), 2_000_000);
+
+        for policy in POLICIES {
+            let same = policy
+                .same(scratch.join("a"), scratch.join("b"), ZERO)
+                .unwrap();
+            assert_eq!(same, matches!(policy, Policy::Size), "{}", policy.name());
+        }
+    }
+
+    // Matching times don't make files of different lengths the same.
+    #[test]
+    fn tells_files_of_different_lengths_apart() {
+        let scratch = Scratch::unique("compare-length");
+        scratch.declare(&[
+            ("a", Element::File("content")),
+            ("b", Element::File("content!")),
+        ]);
+
+        set_modified(scratch.join("a"), 1_000_000);
+        set_modified(scratch.join("b"), 1_000_000);
+
+        for policy in POLICIES {
+            let same = policy
+                .same(scratch.join("a"), scratch.join("b"), ZERO)
+                .unwrap();
+            assert!(!same, "{}", policy.name());
+        }
+
+        assert!(!super::same_content(scratch.join("a"), scratch.join("b")).unwrap());
+    }
+
+    // Times that are no more than the slack apart, either way round, are the same to mtime.
+    #[test]
+    fn allows_mtime_some_slack() {
+        let scratch = Scratch::unique("compare-slack");
+        scratch.declare(&[
+            ("a", Element::File("content")),
+            ("b", Element::File("content")),
+        ]);
+
+        set_modified(scratch.join("a"), 1_000_000);
+        set_modified(scratch.join("b"), 1_000_002);
+
+        let same = |a: &str, b: &str, seconds| {
+            let slack = std::time::Duration::from_secs(seconds);
+            Policy::Mtime
+                .same(scratch.join(a), scratch.join(b), slack)
+                .unwrap()
+        };
+
+        assert!(same("a", "b", 2));
+        assert!(same("b", "a", 2));
+        assert!(!same("a", "b", 1));
+        assert!(!same("b", "a", 1));
+    }
+
+    #[test]
+    fn compares_content_longer_than_a_buffer() {
+        let scratch = Scratch::unique("compare-long");
+
+        for (name, change) in [("a", None), ("b", None), ("c", Some(150_000))] {
+            std::fs::write(scratch.join(name), long(change)).unwrap();
+        }
+
+        assert!(super::same_content(scratch.join("a"), scratch.join("b")).unwrap());
+        assert!(!super::same_content(scratch.join("a"), scratch.join("c")).unwrap());
+    }
+
+    #[test]
+    fn finds_the_start_of_a_file() {
+        let scratch = Scratch::unique("compare-prefix");
+        std::fs::write(scratch.join("full"), long(None)).unwrap();
+        std::fs::write(scratch.join("partial"), &long(None)[..100_000]).unwrap();
+
+        let mut changed = long(Some(70_000));
+        changed.truncate(100_000);
+        std::fs::write(scratch.join("changed"), changed).unwrap();
+
+        let is_prefix = |partial| super::is_prefix(scratch.join(partial), scratch.join("full"));
+        assert_eq!(is_prefix("partial").unwrap(), Some(100_000));
+        assert_eq!(is_prefix("changed").unwrap(), None);
+    }
+
+    // Neither nothing at all nor the whole file (or more) is left behind by an interrupted copy.
+    #[test]
+    fn finds_no_start_in_empty_or_complete_files() {
+        let scratch = Scratch::unique("compare-not-prefix");
+        scratch.declare(&[
+            ("full", Element::File("content")),
+            ("empty", Element::File("")),
+            ("equal", Element::File("content")),
+            ("longer", Element::File("content and more")),
+        ]);
+
+        for partial in ["empty", "equal", "longer"] {
+            let found = super::is_prefix(scratch.join(partial), scratch.join("full")).unwrap();
+            assert_eq!(found, None, "{}", partial);
+        }
+    }
+}
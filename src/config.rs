@@ -0,0 +1,150 @@
+// Defaults read from a config file, which is %APPDATA%\winstall\config.toml on Windows and
+// $XDG_CONFIG_HOME/winstall/config.toml (or ~/.config/winstall/config.toml) elsewhere. Settings in
+// the file are overridden by the environment, which is overridden by the command line.
+//
+// Only the part of TOML that the settings need is understood: 'key = value' lines at the top
+// level, where the value is a string, an integer or a boolean, and '#' comments.
+
+const KEYS: [&str; 5] = ["backup", "backup-mode", "buffer-size", "suffix", "verbose"];
+
+#[derive(Default)]
+pub struct Config {
+    path: std::path::PathBuf,
+    values: std::collections::BTreeMap<String, String>,
+}
+
+impl Config {
+    // Every setting in the file, in order of key.
+    pub fn settings(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    // The setting that stands in for an environment variable when it isn't set.
+    pub fn variable(&self, name: &str) -> Option<String> {
+        match name {
+            "SIMPLE_BACKUP_SUFFIX" => self.get("suffix").map(str::to_owned),
+            "VERSION_CONTROL" => self.get("backup").map(str::to_owned),
+            _ => None,
+        }
+    }
+
+    pub fn invalid(&self, key: &str) -> String {
+        format!(
+            "invalid value '{}' for '{}' in {}",
+            self.get(key).unwrap_or_default(),
+            key,
+            crate::quoting::quote(&self.path)
+        )
+    }
+}
+
+pub fn path(env: impl Fn(&str) -> Option<String>) -> Option<std::path::PathBuf> {
+    let directory = match cfg!(windows) {
+        true => std::path::PathBuf::from(env("APPDATA")?),
+        false => match env("XDG_CONFIG_HOME") {
+            Some(config) => std::path::PathBuf::from(config),
+            None => std::path::Path::new(&env("HOME")?).join(".config"),
+        },
+    };
+
+    Some(directory.join("winstall").join("config.toml"))
+}
+
+// Reads the config file at p, which doesn't have to exist. Anything that isn't understood is an
+// error, so that a mistyped setting isn't silently ignored.
+pub fn load<P: AsRef<std::path::Path>>(p: P) -> Result<Config, String> {
+    let mut config = Config {
+        path: p.as_ref().to_path_buf(),
+        values: Default::default(),
+    };
+
+    let content = match std::fs::read_to_string(p.as_ref()) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(config),
+        Err(e) => {
+            return Err(format!(
+                "unable to read {}: {}",
+                crate::quoting::quote(p.as_ref()),
+                e
+            ))
+        }
+    };
+
+    for (number, line) in content.lines().enumerate() {
+        let error = |message: &str| {
+            format!(
+                "{}:{}: {}",
+                crate::quoting::quote(p.as_ref()),
+                number + 1,
+                message
+            )
+        };
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(error("expected KEY = VALUE"));
+        };
+
+        let key = key.trim().trim_matches('"');
+        if !KEYS.contains(&key) {
+            return Err(error(&format!("unknown setting '{}'", key)));
+        }
+
+        let value = parse_value(value.trim()).ok_or_else(|| error("invalid value"))?;
+        config.values.insert(key.to_string(), value);
+    }
+
+    Ok(config)
+}
+
+// Reads a string, integer or boolean, with any comment after it.
+fn parse_value(text: &str) -> Option<String> {
+    let mut chars = text.chars();
+
+    let (value, rest) = match chars.next()? {
+        '\'' => {
+            let end = text[1..].find('\'')? + 1;
+            (text[1..end].to_string(), &text[end + 1..])
+        }
+        '"' => {
+            let mut value = String::new();
+
+            loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => match chars.next()? {
+                        'n' => value.push('\n'),
+                        't' => value.push('\t'),
+                        c @ ('"' | '\\') => value.push(c),
+                        _ => return None,
+                    },
+                    c => value.push(c),
+                }
+            }
+
+            (value, chars.as_str())
+        }
+        _ => {
+            let value = text.split('#').next().unwrap_or_default().trim();
+
+            return match value == "true" || value == "false" || value.parse::<u64>().is_ok() {
+                true => Some(value.to_string()),
+                false => None,
+            };
+        }
+    };
+
+    let rest = rest.trim();
+    match rest.is_empty() || rest.starts_with('#') {
+        true => Some(value),
+        false => None,
+    }
+}
@@ -0,0 +1,406 @@
+enum Capability {
+    Supported,
+    Unsupported(std::io::Error),
+    Unknown(&'static str),
+}
+
+enum Check {
+    Pass,
+    Fail(String),
+    Skipped(&'static str),
+}
+
+// Probes what the file system (and the privileges of the current process) allow at the given
+// location by actually attempting each operation inside a scratch directory, then prints a report.
+pub fn self_test<P: AsRef<std::path::Path>>(p: P) -> bool {
+    let sandbox = p
+        .as_ref()
+        .join(format!(".winstall-self-test-{}", std::process::id()));
+
+    if let Err(e) = std::fs::create_dir(&sandbox) {
+        eprintln!(
+            "winstall: cannot create self test directory {}: {}",
+            crate::quoting::quote(sandbox),
+            e
+        );
+
+        return false;
+    }
+
+    let source = sandbox.join("source");
+    if let Err(e) = std::fs::write(&source, b"winstall") {
+        eprintln!(
+            "winstall: cannot create self test file {}: {}",
+            crate::quoting::quote(source),
+            e
+        );
+
+        _ = std::fs::remove_dir_all(&sandbox);
+        return false;
+    }
+
+    println!(
+        "winstall self test in {}",
+        crate::quoting::quote(p.as_ref())
+    );
+
+    report("long paths", long_paths(&sandbox));
+    report("symbolic links", symbolic_links(&sandbox, &source));
+    report("hard links", hard_links(&sandbox, &source));
+    report(
+        "block cloning",
+        Capability::Unknown("not detectable by this build"),
+    );
+    report(
+        "read-only directories prevent file creation",
+        readonly_directories(&sandbox),
+    );
+
+    let mut passed = true;
+
+    let scenarios = sandbox.join("scenarios");
+    if let Err(e) = std::fs::create_dir(&scenarios) {
+        eprintln!(
+            "winstall: cannot create self test directory {}: {}",
+            crate::quoting::quote(scenarios),
+            e
+        );
+
+        passed = false;
+    } else {
+        passed &= check("copy a file", copy(&scenarios));
+        passed &= check(
+            "replace with a simple backup (rename)",
+            simple_backup(&scenarios),
+        );
+        passed &= check(
+            "replace with a numbered backup (copy)",
+            numbered_backup(&scenarios),
+        );
+        passed &= check("-d creates nested directories", directories(&scenarios));
+        passed &= check("-D creates missing parents", parents(&scenarios));
+        passed &= check(
+            "installing into a read-only directory fails",
+            readonly_target(&scenarios),
+        );
+    }
+
+    if let Err(e) = std::fs::remove_dir_all(&sandbox) {
+        eprintln!(
+            "winstall: unable to remove self test directory {}: {}",
+            crate::quoting::quote(sandbox),
+            e
+        );
+    }
+
+    passed
+}
+
+// Reports what the current process is allowed to do to p, and to the directory it would be
+// installed into, so that a permission problem is found before an install fails part way. No ACL
+// is read: like the self test, each access is found by attempting it. p is opened to read and to
+// write but never written to, and an empty file is created in the directory and removed again. So
+// a right that none of the attempts needs, such as deleting p when it is replaced, isn't reported
+// on. Returns whether an install to p would be allowed.
+pub fn effective_access<P: AsRef<std::path::Path>>(p: P) -> bool {
+    let p = p.as_ref();
+    let parent = match p.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => std::path::Path::new("."),
+    };
+
+    println!("winstall effective access to {}", crate::quoting::quote(p));
+
+    let exists = match std::fs::metadata(p) {
+        Ok(metadata) => {
+            report("exists", Capability::Supported);
+            report(
+                "read-only attribute clear",
+                match metadata.permissions().readonly() {
+                    true => Capability::Unsupported(std::io::Error::other("the attribute is set")),
+                    false => Capability::Supported,
+                },
+            );
+            true
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            report("exists", Capability::Unsupported(e));
+            false
+        }
+        Err(e) => {
+            report("exists", Capability::Unsupported(e));
+            return false;
+        }
+    };
+
+    let write = match exists {
+        true => {
+            report("read", opened(p, std::fs::OpenOptions::new().read(true)));
+
+            // Opening to write without truncating leaves the content as it is.
+            let write = opened(p, std::fs::OpenOptions::new().write(true));
+            let allowed = matches!(write, Capability::Supported);
+            report("write", write);
+            allowed
+        }
+        false => false,
+    };
+
+    let probe = parent.join(format!(".winstall-access-{}", std::process::id()));
+    let create = match std::fs::File::create_new(&probe) {
+        Ok(_) => {
+            _ = std::fs::remove_file(&probe);
+            Capability::Supported
+        }
+        Err(e) => Capability::Unsupported(e),
+    };
+
+    let allowed = matches!(create, Capability::Supported);
+    report(
+        &format!("create files in {}", crate::quoting::quote(parent)),
+        create,
+    );
+
+    // Replacing the destination goes through a new file in its directory, but with the copy backup
+    // mode it is written in place.
+    write || allowed
+}
+
+fn opened(p: &std::path::Path, options: &std::fs::OpenOptions) -> Capability {
+    match options.open(p) {
+        Ok(_) => Capability::Supported,
+        Err(e) => Capability::Unsupported(e),
+    }
+}
+
+fn report(name: &str, capability: Capability) {
+    match capability {
+        Capability::Supported => println!("  {}: yes", name),
+        Capability::Unsupported(e) => println!("  {}: no ({})", name, e),
+        Capability::Unknown(reason) => println!("  {}: unknown ({})", name, reason),
+    }
+}
+
+// Returns false only for a failure, a skipped scenario doesn't fail the self test.
+fn check(name: &str, check: Check) -> bool {
+    match check {
+        Check::Pass => println!("  {}: pass", name),
+        Check::Fail(reason) => {
+            println!("  {}: FAIL ({})", name, reason);
+            return false;
+        }
+        Check::Skipped(reason) => println!("  {}: skipped ({})", name, reason),
+    }
+
+    true
+}
+
+fn installs(from: &std::path::Path, to: &std::path::Path, install: &crate::Install) -> bool {
+    let mut stats = crate::stats::Stats::default();
+    crate::all_succeeded(&crate::file_target(from, to, install, &mut stats))
+}
+
+fn contains(p: &std::path::Path, expected: &[u8]) -> Result<(), String> {
+    match std::fs::read(p) {
+        Ok(content) if content == expected => Ok(()),
+        Ok(_) => Err(format!(
+            "{} has the wrong content",
+            crate::quoting::quote(p)
+        )),
+        Err(e) => Err(format!("cannot read {}: {}", crate::quoting::quote(p), e)),
+    }
+}
+
+fn write(p: &std::path::Path, content: &[u8]) -> Result<(), String> {
+    std::fs::write(p, content)
+        .map_err(|e| format!("cannot write {}: {}", crate::quoting::quote(p), e))
+}
+
+fn into_check(result: Result<(), String>) -> Check {
+    match result {
+        Ok(_) => Check::Pass,
+        Err(reason) => Check::Fail(reason),
+    }
+}
+
+fn copy(scenarios: &std::path::Path) -> Check {
+    let (from, to) = (scenarios.join("copy-from"), scenarios.join("copy-to"));
+
+    into_check(write(&from, b"new").and_then(|_| {
+        match installs(
+            &from,
+            &to,
+            &crate::Install::new(None, crate::backup::Mode::Rename, false),
+        ) {
+            true => contains(&to, b"new"),
+            false => Err("the install failed".to_string()),
+        }
+    }))
+}
+
+fn simple_backup(scenarios: &std::path::Path) -> Check {
+    let (from, to) = (scenarios.join("simple-from"), scenarios.join("simple-to"));
+    let backup = crate::Backup::Simple("~".to_string());
+
+    into_check(
+        write(&from, b"new")
+            .and_then(|_| write(&to, b"old"))
+            .and_then(|_| {
+                match installs(
+                    &from,
+                    &to,
+                    &crate::Install::new(Some(backup), crate::backup::Mode::Rename, false),
+                ) {
+                    true => contains(&to, b"new"),
+                    false => Err("the install failed".to_string()),
+                }
+            })
+            .and_then(|_| contains(&scenarios.join("simple-to~"), b"old")),
+    )
+}
+
+fn numbered_backup(scenarios: &std::path::Path) -> Check {
+    let (from, to) = (
+        scenarios.join("numbered-from"),
+        scenarios.join("numbered-to"),
+    );
+    let backup = crate::Backup::Numbered;
+
+    into_check(
+        write(&from, b"new")
+            .and_then(|_| write(&to, b"old"))
+            .and_then(|_| {
+                match installs(
+                    &from,
+                    &to,
+                    &crate::Install::new(Some(backup), crate::backup::Mode::Copy, false),
+                ) {
+                    true => contains(&to, b"new"),
+                    false => Err("the install failed".to_string()),
+                }
+            })
+            .and_then(|_| contains(&scenarios.join("numbered-to.~1~"), b"old")),
+    )
+}
+
+fn directories(scenarios: &std::path::Path) -> Check {
+    let directory = scenarios.join("d").join("e").join("f");
+
+    match crate::create_directory(&directory, true, None).is_some() && directory.is_dir() {
+        true => Check::Pass,
+        false => Check::Fail(format!(
+            "{} was not created",
+            crate::quoting::quote(directory)
+        )),
+    }
+}
+
+fn parents(scenarios: &std::path::Path) -> Check {
+    let from = scenarios.join("parents-from");
+    let to = scenarios.join("p").join("q").join("parents-to");
+
+    into_check(write(&from, b"new").and_then(|_| {
+        match installs(
+            &from,
+            &to,
+            &crate::Install::new(None, crate::backup::Mode::Rename, true),
+        ) {
+            true => contains(&to, b"new"),
+            false => Err("the install failed".to_string()),
+        }
+    }))
+}
+
+fn readonly_target(scenarios: &std::path::Path) -> Check {
+    let from = scenarios.join("readonly-from");
+    let directory = scenarios.join("readonly-target");
+
+    if let Err(reason) = write(&from, b"new") {
+        return Check::Fail(reason);
+    }
+
+    // Only meaningful where read-only directories are enforced, which isn't the case on Windows
+    // or for a privileged user.
+    if !matches!(readonly_directories(scenarios), Capability::Supported) {
+        return Check::Skipped("read-only directories are not enforced here");
+    }
+
+    let readonly =
+        std::fs::create_dir(&directory).and_then(|_| crate::set_readonly(&directory, true));
+    if readonly.is_err() {
+        return Check::Skipped("unable to set up the scenario");
+    }
+
+    let installed = installs(
+        &from,
+        &directory.join("file"),
+        &crate::Install::new(None, crate::backup::Mode::Rename, false),
+    );
+
+    _ = crate::set_readonly(&directory, false);
+
+    match installed {
+        true => Check::Fail("the install succeeded".to_string()),
+        false => Check::Pass,
+    }
+}
+
+fn long_paths(sandbox: &std::path::Path) -> Capability {
+    // Each component is well within the usual 255 character limit, but together they exceed the
+    // legacy MAX_PATH of 260 characters.
+    let component = "l".repeat(100);
+    let deep = sandbox.join(&component).join(&component).join(&component);
+
+    match std::fs::create_dir_all(&deep).and_then(|_| std::fs::write(deep.join("file"), b"")) {
+        Ok(_) => Capability::Supported,
+        Err(e) => Capability::Unsupported(e),
+    }
+}
+
+fn symbolic_links(sandbox: &std::path::Path, source: &std::path::Path) -> Capability {
+    let link = sandbox.join("symlink");
+
+    #[cfg(windows)]
+    let result = std::os::windows::fs::symlink_file(source, &link);
+
+    #[cfg(unix)]
+    let result = std::os::unix::fs::symlink(source, &link);
+
+    #[cfg(not(any(windows, unix)))]
+    let result = Err::<(), _>(std::io::Error::from(std::io::ErrorKind::Unsupported));
+
+    match result {
+        Ok(_) => Capability::Supported,
+        Err(e) => Capability::Unsupported(e),
+    }
+}
+
+fn hard_links(sandbox: &std::path::Path, source: &std::path::Path) -> Capability {
+    match std::fs::hard_link(source, sandbox.join("hardlink")) {
+        Ok(_) => Capability::Supported,
+        Err(e) => Capability::Unsupported(e),
+    }
+}
+
+fn readonly_directories(sandbox: &std::path::Path) -> Capability {
+    let directory = sandbox.join("readonly");
+
+    if let Err(e) =
+        std::fs::create_dir(&directory).and_then(|_| crate::set_readonly(&directory, true))
+    {
+        return Capability::Unknown(match e.kind() {
+            std::io::ErrorKind::PermissionDenied => "permission denied setting up the probe",
+            _ => "unable to set up the probe",
+        });
+    }
+
+    let created = std::fs::write(directory.join("file"), b"");
+
+    // The attribute has to be cleared again so the sandbox can be removed.
+    _ = crate::set_readonly(&directory, false);
+
+    match created {
+        Ok(_) => Capability::Unsupported(std::io::Error::other("files were created")),
+        Err(_) => Capability::Supported,
+    }
+}
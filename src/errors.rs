@@ -0,0 +1,64 @@
+// Describes an error from operating on p, adding an explanation for the network errors that
+// Windows otherwise reports in terms that don't say what to do about them.
+pub fn describe<P: AsRef<std::path::Path>>(e: &std::io::Error, p: P) -> String {
+    let hint = match (is_unc(p.as_ref()), e.raw_os_error()) {
+        (true, Some(5)) => Some("the share does not allow this account to write there"),
+        (true, Some(53)) => Some("the server could not be found or is not reachable"),
+        (true, Some(67)) => Some("the server does not have a share with that name"),
+        (true, Some(86)) | (true, Some(1326)) => Some(concat!(
+            "the credentials for the share were rejected, connect to it with 'net use' and ",
+            "valid credentials first"
+        )),
+        (true, Some(1219)) => Some(concat!(
+            "there is already a connection to the server with different credentials, ",
+            "disconnect it with 'net use /delete' first"
+        )),
+        _ => None,
+    };
+
+    match hint {
+        Some(hint) => format!("{} ({})", e, hint),
+        None => e.to_string(),
+    }
+}
+
+// Reports a problem that doesn't stop a file from being installed, such as its times not being
+// preserved exactly. The run still succeeds unless --strict is given.
+pub fn warn(stats: &mut crate::stats::Stats, message: &str) {
+    eprintln!("winstall: warning: {}", message);
+    stats.warnings += 1;
+}
+
+pub fn is_unc<P: AsRef<std::path::Path>>(p: P) -> bool {
+    match p.as_ref().components().next() {
+        Some(std::path::Component::Prefix(prefix)) => matches!(
+            prefix.kind(),
+            std::path::Prefix::UNC(..) | std::path::Prefix::VerbatimUNC(..)
+        ),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(windows)]
+    #[test]
+    fn recognises_unc_paths() {
+        assert!(super::is_unc(r"\\server\share"));
+        assert!(super::is_unc(r"\\server\share\a"));
+        assert!(super::is_unc(r"\\?\UNC\server\share\a"));
+
+        assert!(!super::is_unc(r"C:\a"));
+        assert!(!super::is_unc(r"\\?\C:\a"));
+        assert!(!super::is_unc(r"\a"));
+        assert!(!super::is_unc("a"));
+    }
+
+    // There are no shares to name outside Windows, where '//server/share' is an ordinary path.
+    #[cfg(unix)]
+    #[test]
+    fn never_finds_unc_paths() {
+        assert!(!super::is_unc("//server/share"));
+        assert!(!super::is_unc(r"\\server\share"));
+    }
+}
@@ -0,0 +1,260 @@
+// Decides which sources are left out of an install by --exclude and --include-from. Patterns are
+// globs matched against the path of a source relative to where it is installed from, using '/' as
+// the separator on every platform. A pattern without a separator matches the file name alone, so
+// '*.pdb' excludes a .pdb file at any depth. A pattern ending in '/' matches directories, and so
+// everything in them, so 'obj/' excludes every source with a directory named obj in its path. When
+// there are include patterns, only sources matching one of them are installed, and an exclude
+// pattern leaves a source out even if it is included.
+pub struct Filter {
+    includes: Vec<String>,
+    excludes: Vec<String>,
+}
+
+impl Filter {
+    pub fn new(includes: &[String], excludes: &[String]) -> Filter {
+        Filter {
+            includes: includes.to_vec(),
+            excludes: excludes.to_vec(),
+        }
+    }
+
+    pub fn leaves_out<P: AsRef<std::path::Path>>(&self, relative: P) -> bool {
+        let names = relative
+            .as_ref()
+            .components()
+            .filter_map(|c| match c {
+                std::path::Component::Normal(name) => Some(name.to_string_lossy()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let path = names.join("/");
+        let name = names.last().map(|n| n.as_ref()).unwrap_or_default();
+
+        // The directories the source is in, and the source itself if it is one, for patterns that
+        // end in '/'. Each is given both by its path and by its name.
+        let directories = match relative.as_ref().is_dir() {
+            true => names.len(),
+            false => names.len().saturating_sub(1),
+        };
+
+        let directories = (1..=directories)
+            .map(|n| (names[..n].join("/"), &names[n - 1]))
+            .collect::<Vec<_>>();
+
+        let matched = |pattern: &String| match pattern.strip_suffix('/') {
+            Some(directory) if directory.contains('/') => directories
+                .iter()
+                .any(|(path, _)| matches(directory.trim_start_matches('/'), path)),
+            Some(directory) => directories.iter().any(|(_, name)| matches(directory, name)),
+            None if pattern.contains('/') => matches(pattern.trim_start_matches('/'), &path),
+            None => matches(pattern, name),
+        };
+
+        let included = self.includes.is_empty() || self.includes.iter().any(matched);
+        !included || self.excludes.iter().any(matched)
+    }
+}
+
+// Reads the patterns in a list file, one to a line. Blank lines and lines starting with '#' are
+// ignored, and line endings may be either LF or CRLF.
+pub fn read_patterns<P: AsRef<std::path::Path>>(p: P) -> std::io::Result<Vec<String>> {
+    let content = std::fs::read_to_string(p.as_ref())?;
+
+    Ok(content
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect())
+}
+
+// Matches text against a glob, where '*' matches any run of characters and '?' any single
+// character, neither of them crossing a '/', and '[...]' matches one of a set of characters (or
+// any character not in it when the set starts with '!' or '^'). File names compare without regard
+// to case on Windows, as they do in the file system.
+fn matches(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+
+    glob(&pattern, &text)
+}
+
+fn glob(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') => (0..=text.len())
+            .take_while(|&n| n == 0 || text[n - 1] != '/')
+            .any(|n| glob(&pattern[1..], &text[n..])),
+        Some('?') => match text.first() {
+            Some(&c) if c != '/' => glob(&pattern[1..], &text[1..]),
+            _ => false,
+        },
+        Some('[') => match (class(&pattern[1..]), text.first()) {
+            (Some((set, rest)), Some(&c)) if c != '/' && set(c) => glob(rest, &text[1..]),
+            (Some(_), _) => false,
+            (None, Some(&c)) if same('[', c) => glob(&pattern[1..], &text[1..]),
+            (None, _) => false,
+        },
+        Some(&p) => match text.first() {
+            Some(&c) if same(p, c) => glob(&pattern[1..], &text[1..]),
+            _ => false,
+        },
+    }
+}
+
+// Reads the set of characters after a '[', returning a test for membership and the rest of the
+// pattern, or None when there is no closing ']' and the '[' is an ordinary character.
+fn class(pattern: &[char]) -> Option<(impl Fn(char) -> bool + '_, &[char])> {
+    let negated = matches!(pattern.first(), Some('!') | Some('^'));
+    let start = negated as usize;
+
+    // A ']' straight after the '[' is a member of the set rather than its end.
+    let end = start + 1 + pattern.get(start + 1..)?.iter().position(|&c| c == ']')?;
+    let members = &pattern[start..end];
+
+    let set = move |c: char| {
+        let mut found = false;
+        let mut i = 0;
+
+        while i < members.len() {
+            if i + 2 < members.len() && members[i + 1] == '-' {
+                let (low, high) = (fold(members[i]), fold(members[i + 2]));
+                found |= (low..=high).contains(&fold(c));
+                i += 3;
+            } else {
+                found |= same(members[i], c);
+                i += 1;
+            }
+        }
+
+        found != negated
+    };
+
+    Some((set, &pattern[end + 1..]))
+}
+
+fn same(a: char, b: char) -> bool {
+    fold(a) == fold(b)
+}
+
+fn fold(c: char) -> char {
+    match cfg!(windows) {
+        true => c.to_ascii_lowercase(),
+        false => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{matches, Filter};
+    use crate::scratch::{Element, Scratch};
+
+    fn excluding(patterns: &[&str]) -> Filter {
+        let patterns = patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        Filter::new(&[], &patterns)
+    }
+
+    #[test]
+    fn matches_stars_and_question_marks() {
+        assert!(matches("*.pdb", "app.pdb"));
+        assert!(matches("*.pdb", ".pdb"));
+        assert!(matches("test_*", "test_app"));
+        assert!(matches("a*b*c", "abbbc"));
+        assert!(!matches("*.pdb", "app.pdb.bak"));
+
+        assert!(matches("app.?xe", "app.exe"));
+        assert!(!matches("app.?xe", "app.xe"));
+        assert!(!matches("?", ""));
+    }
+
+    #[test]
+    fn matches_character_classes() {
+        assert!(matches("file[0-9]", "file7"));
+        assert!(!matches("file[0-9]", "filex"));
+        assert!(matches("file[!0-9]", "filex"));
+        assert!(matches("file[^0-9]", "filex"));
+        assert!(!matches("file[!0-9]", "file7"));
+        assert!(matches("[ab]c", "bc"));
+
+        // A ']' straight after the '[' is in the set, and a '[' that isn't closed is itself.
+        assert!(matches("[]]", "]"));
+        assert!(matches("a[b", "a[b"));
+    }
+
+    #[test]
+    fn never_crosses_a_separator_with_a_wildcard() {
+        assert!(!matches("*", "a/b"));
+        assert!(!matches("a?b", "a/b"));
+        assert!(!matches("a[/]b", "a/b"));
+        assert!(matches("a/*", "a/b"));
+        assert!(!matches("a/*", "a/b/c"));
+    }
+
+    #[test]
+    fn compares_names_as_the_file_system_does() {
+        assert_eq!(matches("*.PDB", "app.pdb"), cfg!(windows));
+        assert_eq!(matches("[A-C].txt", "b.txt"), cfg!(windows));
+    }
+
+    // A pattern without a '/' is matched against the file name alone, at any depth, and one with
+    // a '/' against the whole path.
+    #[test]
+    fn matches_names_or_paths() {
+        let filter = excluding(&["*.pdb", "/bin/debug.exe"]);
+
+        assert!(filter.leaves_out("app.pdb"));
+        assert!(filter.leaves_out("target/release/app.pdb"));
+        assert!(filter.leaves_out("bin/debug.exe"));
+        assert!(!filter.leaves_out("other/bin/debug.exe"));
+        assert!(!filter.leaves_out("app.exe"));
+    }
+
+    #[test]
+    fn matches_directories_with_a_trailing_separator() {
+        let scratch = Scratch::unique("filter-directories");
+        scratch.declare(&[("obj", Element::Directory(vec![]))]);
+
+        let anywhere = excluding(&["obj/"]);
+        assert!(anywhere.leaves_out("obj/a.o"));
+        assert!(anywhere.leaves_out("src/obj/deep/a.o"));
+        assert!(anywhere.leaves_out(scratch.join("obj")));
+        assert!(!anywhere.leaves_out("obj"));
+        assert!(!anywhere.leaves_out("objects/a.o"));
+
+        let anchored = excluding(&["/src/obj/"]);
+        assert!(anchored.leaves_out("src/obj/a.o"));
+        assert!(!anchored.leaves_out("lib/src/obj/a.o"));
+    }
+
+    #[test]
+    fn installs_only_what_is_included_and_not_excluded() {
+        let includes = ["*.exe".to_string(), "*.dll".to_string()];
+        let filter = Filter::new(&includes, &["test_*".to_string()]);
+
+        assert!(!filter.leaves_out("app.exe"));
+        assert!(!filter.leaves_out("lib.dll"));
+        assert!(filter.leaves_out("app.pdb"));
+        assert!(filter.leaves_out("test_app.exe"));
+    }
+
+    #[test]
+    fn reads_patterns_one_to_a_line() {
+        let scratch = Scratch::unique("filter-patterns");
+        scratch.declare(&[(
+            "patterns",
+            Element::File("# build output\r\n*.pdb\r\n\n   \nobj/\n #not a comment\ntest_*"),
+        )]);
+
+        assert_eq!(
+            super::read_patterns(scratch.join("patterns")).unwrap(),
+            ["*.pdb", "obj/", " #not a comment", "test_*"]
+        );
+    }
+
+    #[test]
+    fn fails_to_read_patterns_from_a_missing_file() {
+        let scratch = Scratch::unique("filter-missing");
+        assert!(super::read_patterns(scratch.join("patterns")).is_err());
+    }
+}
@@ -0,0 +1,308 @@
+pub struct Hooks {
+    pub signing: Option<Signing>,
+    pub post_install: Vec<String>,
+    pub halt_on_failure: bool,
+}
+
+pub struct Signing {
+    pub signtool: std::ffi::OsString,
+    pub thumbprint: String,
+}
+
+pub enum SignError {
+    Launch(std::io::Error),
+    Sign(std::process::ExitStatus),
+    Verify(std::process::ExitStatus),
+}
+
+impl std::fmt::Display for SignError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignError::Launch(e) => write!(f, "unable to run signtool: {}", e),
+            SignError::Sign(status) => write!(f, "signing failed ({})", status),
+            SignError::Verify(status) => write!(f, "signature verification failed ({})", status),
+        }
+    }
+}
+
+impl Signing {
+    pub fn applies_to<P: AsRef<std::path::Path>>(p: P) -> bool {
+        p.as_ref()
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("exe") || e.eq_ignore_ascii_case("dll"))
+    }
+
+    pub fn sign<P: AsRef<std::path::Path>>(&self, p: P) -> Result<(), SignError> {
+        let status = std::process::Command::new(&self.signtool)
+            .args(["sign", "/sha1", &self.thumbprint, "/fd", "SHA256"])
+            .arg(p.as_ref())
+            .status()
+            .map_err(SignError::Launch)?;
+
+        if !status.success() {
+            return Err(SignError::Sign(status));
+        }
+
+        let status = std::process::Command::new(&self.signtool)
+            .args(["verify", "/pa"])
+            .arg(p.as_ref())
+            .status()
+            .map_err(SignError::Launch)?;
+
+        if !status.success() {
+            return Err(SignError::Verify(status));
+        }
+
+        Ok(())
+    }
+}
+
+impl Hooks {
+    // Runs each post-install command for a file that was just installed, returning whether they
+    // all succeeded. Failures are counted so that they can be reported together at the end.
+    pub fn run<F: AsRef<std::path::Path>, T: AsRef<std::path::Path>>(
+        &self,
+        from: F,
+        to: T,
+        verbose: Option<crate::messages::Router>,
+        stats: &mut crate::stats::Stats,
+    ) -> bool {
+        if let Some(signing) = &self.signing {
+            if Signing::applies_to(to.as_ref()) {
+                if let Some(router) = verbose {
+                    router.send(crate::messages::signing(to.as_ref()));
+                }
+
+                if let Err(e) = signing.sign(to.as_ref()) {
+                    eprintln!(
+                        "winstall: cannot sign {}: {}",
+                        crate::quoting::quote(to.as_ref()),
+                        e
+                    );
+
+                    return false;
+                }
+            }
+        }
+
+        for template in &self.post_install {
+            let command = expand(template, from.as_ref(), to.as_ref());
+
+            if let Some(router) = verbose {
+                router.send(crate::messages::running(&command));
+            }
+
+            let failure = match shell(&command).status() {
+                Ok(status) if status.success() => continue,
+                Ok(status) => status.to_string(),
+                Err(e) => e.to_string(),
+            };
+
+            eprintln!(
+                "winstall: post-install command for {} failed: {}",
+                crate::quoting::quote(to.as_ref()),
+                failure
+            );
+
+            stats.hook_failures += 1;
+            return false;
+        }
+
+        true
+    }
+}
+
+// Replaces {src} and {dest} with the quoted paths, so that they are passed to the command as
+// single arguments whatever they contain.
+pub fn expand<F: AsRef<std::path::Path>, T: AsRef<std::path::Path>>(
+    template: &str,
+    from: F,
+    to: T,
+) -> String {
+    template
+        .replace("{src}", &quote(&from.as_ref().to_string_lossy()))
+        .replace("{dest}", &quote(&to.as_ref().to_string_lossy()))
+}
+
+// Windows paths can't contain double quotes, so surrounding them with quotes is enough for cmd.
+#[cfg(windows)]
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s)
+}
+
+#[cfg(not(windows))]
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+#[cfg(windows)]
+pub fn shell(command: &str) -> std::process::Command {
+    use std::os::windows::process::CommandExt;
+
+    // The command line is handed to cmd untouched, as std's argument quoting would otherwise
+    // escape the quotes around the substituted paths. With /S only the outermost quotes are
+    // removed by cmd, leaving any within the command intact.
+    let mut cmd = std::process::Command::new("cmd");
+    cmd.raw_arg(format!("/S /C \"{}\"", command));
+    cmd
+}
+
+#[cfg(not(windows))]
+pub fn shell(command: &str) -> std::process::Command {
+    let mut cmd = std::process::Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Hooks, SignError, Signing};
+    use crate::scratch::{Element, Scratch};
+
+    fn hooks(post_install: &[&str]) -> Hooks {
+        Hooks {
+            signing: None,
+            post_install: post_install.iter().map(|c| c.to_string()).collect(),
+            halt_on_failure: false,
+        }
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn quotes_the_paths_it_substitutes() {
+        let expanded = super::expand("cp {src} {dest}.bak {src}", "a b", "it's");
+        assert_eq!(expanded, r#"cp 'a b' 'it'\''s'.bak 'a b'"#);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn quotes_the_paths_it_substitutes() {
+        let expanded = super::expand("copy {src} {dest}.bak {src}", "a b", "it's");
+        assert_eq!(expanded, r#"copy "a b" "it's".bak "a b""#);
+    }
+
+    // Each substituted path reaches the command as one argument, spaces and quotes and all.
+    #[cfg(not(windows))]
+    #[test]
+    fn passes_each_path_as_a_single_argument() {
+        for (from, to) in [("a b", "c  d"), ("it's", "\"quoted\""), ("$HOME", "`x`;y")] {
+            let command = super::expand("printf '%s\\n' {src} {dest}", from, to);
+            let output = super::shell(&command).output().unwrap();
+            assert!(output.status.success(), "{}", command);
+            assert_eq!(
+                String::from_utf8_lossy(&output.stdout),
+                format!("{}\n{}\n", from, to)
+            );
+        }
+    }
+
+    #[test]
+    fn runs_each_command_in_turn() {
+        let scratch = Scratch::unique("hooks-run");
+        scratch.declare(&[("installed file", Element::File("content"))]);
+        let to = scratch.join("installed file");
+
+        let mut stats = crate::stats::Stats::default();
+        let commands = match cfg!(windows) {
+            true => ["copy {dest} {dest}.1", "copy {dest}.1 {dest}.2"],
+            false => ["cp {dest} {dest}.1", "cp {dest}.1 {dest}.2"],
+        };
+
+        assert!(hooks(&commands).run("source", &to, None, &mut stats));
+        assert_eq!(stats.hook_failures, 0);
+        assert_eq!(
+            std::fs::read(scratch.join("installed file.2")).unwrap(),
+            b"content"
+        );
+    }
+
+    // The first command to fail stops the rest, and is counted.
+    #[test]
+    fn stops_at_a_failed_command() {
+        let scratch = Scratch::unique("hooks-fail");
+        scratch.declare(&[("dest", Element::File("content"))]);
+
+        let mut stats = crate::stats::Stats::default();
+        let commands = match cfg!(windows) {
+            true => ["exit 3", "copy {dest} {dest}.1"],
+            false => ["exit 3", "cp {dest} {dest}.1"],
+        };
+
+        assert!(!hooks(&commands).run("source", scratch.join("dest"), None, &mut stats));
+        assert_eq!(stats.hook_failures, 1);
+        assert!(!scratch.join("dest.1").exists());
+    }
+
+    #[test]
+    fn signs_only_executables_and_libraries() {
+        for (name, applies) in [
+            ("tool.exe", true),
+            ("TOOL.EXE", true),
+            ("lib.dll", true),
+            ("lib.Dll", true),
+            ("script.ps1", false),
+            ("exe", false),
+            ("archive.exe.zip", false),
+        ] {
+            assert_eq!(Signing::applies_to(name), applies, "{}", name);
+        }
+    }
+
+    // A stand-in for signtool that records each call, and fails when asked to carry out the step
+    // named by fails.
+    #[cfg(unix)]
+    fn signtool(scratch: &Scratch, fails: &str) -> Signing {
+        let signtool = scratch.join(format!("signtool-{}", fails));
+        let script = format!(
+            "#!/bin/sh\necho \"$@\" >> '{}'\n[ \"$1\" != {} ]\n",
+            scratch.join("calls").display(),
+            fails
+        );
+
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::write(&signtool, script).unwrap();
+        std::fs::set_permissions(&signtool, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        Signing {
+            signtool: signtool.into_os_string(),
+            thumbprint: "0123abcd".to_string(),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn signs_and_then_verifies() {
+        let scratch = Scratch::unique("hooks-sign");
+        let target = scratch.join("tool.exe");
+
+        assert!(signtool(&scratch, "none").sign(&target).is_ok());
+        assert_eq!(
+            std::fs::read_to_string(scratch.join("calls")).unwrap(),
+            format!(
+                "sign /sha1 0123abcd /fd SHA256 {0}\nverify /pa {0}\n",
+                target.display()
+            )
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reports_which_step_failed() {
+        let scratch = Scratch::unique("hooks-sign-failed");
+        let target = scratch.join("tool.exe");
+
+        let failed = signtool(&scratch, "sign").sign(&target);
+        assert!(matches!(failed, Err(SignError::Sign(_))));
+
+        let failed = signtool(&scratch, "verify").sign(&target);
+        assert!(matches!(failed, Err(SignError::Verify(_))));
+
+        let missing = Signing {
+            signtool: scratch.join("missing").into_os_string(),
+            thumbprint: "0123abcd".to_string(),
+        };
+
+        assert!(matches!(missing.sign(&target), Err(SignError::Launch(_))));
+    }
+}
@@ -1,529 +1,3122 @@
-enum Backup {
-    Numbered,
-    Simple(String),
-    Existing(String),
+mod backup;
+mod cache;
+#[cfg(feature = "cargo")]
+mod cargo;
+mod cli;
+mod compare;
+mod config;
+mod diagnostics;
+mod errors;
+mod filter;
+mod hooks;
+mod messages;
+mod paths;
+mod quoting;
+#[cfg(test)]
+mod scratch;
+mod sha256;
+mod stats;
+mod timestamps;
+mod transform;
+#[cfg(unix)]
+mod unix;
+mod verify;
+
+use backup::Backup;
+
+#[derive(Clone, Copy)]
+enum Link {
+    Hard,
+    HardOrFail,
+    Symbolic { relative: bool },
 }
 
-struct Options {
-    backup: Option<Option<String>>,
-    suffix: Option<String>,
-    verbose: bool,
-    preserve_timestamps: bool,
-    make_all_directories: bool,
-    no_target_directory: bool,
-    target_directory: Option<String>,
-    directory_arguments: bool,
+// When installed files are flushed to the device with --sync: after each one, or all together once
+// everything has been installed.
+#[derive(Clone, Copy, PartialEq)]
+enum Flush {
+    Each,
+    Final,
 }
 
-fn main() {
-    let mut opts = Options {
-        backup: None,
-        suffix: None,
-        verbose: false,
-        preserve_timestamps: false,
-        make_all_directories: false,
-        no_target_directory: false,
-        target_directory: None,
-        directory_arguments: false,
-    };
-
-    let mut args = Vec::<String>::new();
-
-    let mut peekable = std::env::args().skip(1).peekable();
-    'arguments: while let Some(arg) = peekable.next() {
-        let mut split = arg.split('=');
-        let argument = split.next().unwrap();
-
-        let mut try_capture =
-            || -> Option<String> { split.next().map(str::to_owned).or_else(|| peekable.next()) };
-
-        'recognized: {
-            match argument {
-                "-v" | "--verbose" => opts.verbose = true,
-                "-p" | "--preserve-timestamps" => opts.preserve_timestamps = true,
-                "-T" | "--no-target-directory" => opts.no_target_directory = true,
-                "-D" => opts.make_all_directories = true,
-                "-d" | "--directory" => opts.directory_arguments = true,
-                "-b" => opts.backup = Some(None),
-                "--backup" => opts.backup = Some(split.next().map(str::to_owned)),
-                "-S" | "--suffix" => match try_capture() {
-                    Some(s) => opts.suffix = Some(s),
-                    None => {
-                        eprintln!("winstall: option --suffix (-S) requires an argument");
-                        eprintln!("Try 'winstall --help' for more information.");
-                        std::process::exit(1);
-                    }
-                },
-                "-t" | "--target-directory" => match try_capture() {
-                    Some(s) => opts.target_directory = Some(s),
-                    None => {
-                        eprintln!("winstall: option --target-directory (-t) requires an argument");
-                        eprintln!("Try 'winstall --help' for more information.");
-                        std::process::exit(1);
-                    }
-                },
-                "--help" => {
-                    println!(include_str!("usage.txt"));
-                    std::process::exit(0);
-                }
-                "--version" => {
-                    println!(include_str!("version.txt"));
-                    std::process::exit(0);
-                }
+// Where --touch-only takes the times it sets from: the source, as -p would, or the current time.
+#[derive(Clone, Copy)]
+enum Touch {
+    Source,
+    Now,
+}
 
-                // Ignored UNIX specific options that don't expect a value (or expect an equals
-                // separated one).
-                "-C" | "--compare" | "--debug" | "-g" | "-m" | "-o" | "--preserve-context"
-                | "-s" | "--strip" | "-Z" | "--context" => (),
+// The behaviour of the install program being stood in for, where GNU and BSD install differ.
+#[derive(Clone, Copy, PartialEq)]
+enum Flavor {
+    Gnu,
+    Bsd,
+}
 
-                // Ignored UNIX specific options that do expect a value
-                "--group" | "--mode" | "--owner" => {
-                    if try_capture().is_none() {
-                        eprintln!(
-                            "winstall: unix compatability option '{}' requires an argument",
-                            argument
-                        );
+enum Outcome {
+    Installed {
+        backup: Option<std::path::PathBuf>,
+        bytes: u64,
+    },
+    Skipped,
+    Failed(Option<std::io::ErrorKind>),
+}
 
-                        std::process::exit(1);
-                    }
+// What happened to a single source file, kept so that a run can be reported on without having to
+// re-derive anything from the messages printed along the way.
+struct FileResult {
+    source: std::path::PathBuf,
+    destination: std::path::PathBuf,
+    outcome: Outcome,
+    hooks_succeeded: bool,
+}
 
-                    ()
-                }
-                _ => break 'recognized,
-            }
+impl FileResult {
+    fn succeeded(&self) -> bool {
+        !matches!(self.outcome, Outcome::Failed(_)) && self.hooks_succeeded
+    }
+}
 
-            continue 'arguments;
-        }
+fn all_succeeded(results: &[FileResult]) -> bool {
+    results.iter().all(FileResult::succeeded)
+}
 
-        args.push(argument.to_owned());
-    }
+// The unix specific options, which are applied to each installed file on unix and accepted but
+// ignored elsewhere. The mode is kept as given because what it means depends on whether it is
+// applied to a file or a directory.
+#[derive(Default)]
+#[cfg_attr(not(unix), allow(dead_code))]
+struct Attributes {
+    mode: Option<String>,
+    owner: Option<u32>,
+    group: Option<u32>,
+    strip: Option<std::ffi::OsString>,
+}
 
-    if args.is_empty() {
-        eprintln!("winstall: missing file operand");
-        eprintln!("Try 'winstall --help' for more information.");
-        std::process::exit(1);
+struct Install {
+    backup_method: Option<Backup>,
+    backup_mode: backup::Mode,
+    make_all_directories: bool,
+    preserve_timestamps: bool,
+    verbose: Option<messages::Router>,
+    buffer_size: Option<usize>,
+    interactive: bool,
+    // Asks whether an existing destination should be replaced, when interactive.
+    confirm: Box<dyn Fn(&std::path::Path) -> bool>,
+    force: bool,
+    no_clobber: bool,
+    expectations: Vec<(std::path::PathBuf, String)>,
+    link: Option<Link>,
+    hooks: hooks::Hooks,
+    stop_at_first_error: bool,
+    version: Option<String>,
+    preserve_creation_time: bool,
+    write_through: bool,
+    compare: Option<compare::Policy>,
+    resume: bool,
+    debug: bool,
+    filter: filter::Filter,
+    attributes: Attributes,
+    sync: Option<Flush>,
+    staging_directory: Option<std::path::PathBuf>,
+    touch: Option<Touch>,
+    transform: transform::Transform,
+    cache: Option<cache::Cache>,
+    av_grace: std::time::Duration,
+    mtime_slack: std::time::Duration,
+}
+
+impl Install {
+    // An install with everything but the settings given left at its default, as the self-test
+    // scenarios and the tests use.
+    fn new(
+        backup_method: Option<Backup>,
+        backup_mode: backup::Mode,
+        make_all_directories: bool,
+    ) -> Install {
+        Install {
+            backup_method,
+            backup_mode,
+            make_all_directories,
+            preserve_timestamps: false,
+            verbose: None,
+            buffer_size: None,
+            interactive: false,
+            confirm: Box::new(confirm_overwrite),
+            force: false,
+            no_clobber: false,
+            expectations: Vec::new(),
+            link: None,
+            hooks: hooks::Hooks {
+                signing: None,
+                post_install: Vec::new(),
+                halt_on_failure: false,
+            },
+            stop_at_first_error: false,
+            version: None,
+            preserve_creation_time: false,
+            write_through: false,
+            compare: None,
+            resume: false,
+            debug: false,
+            filter: filter::Filter::new(&[], &[]),
+            attributes: Attributes::default(),
+            sync: None,
+            staging_directory: None,
+            touch: None,
+            transform: Default::default(),
+            cache: None,
+            av_grace: std::time::Duration::ZERO,
+            mtime_slack: std::time::Duration::ZERO,
+        }
     }
 
-    if opts.no_target_directory && opts.target_directory.is_some() {
-        eprintln!("winstall: cannot combine --target-directory (-t) and no-target-directory (-T)");
-        std::process::exit(1);
+    // Whether a failure should stop any further files from being installed.
+    fn stops_after_failure(&self, stats: &stats::Stats) -> bool {
+        self.stop_at_first_error || (self.hooks.halt_on_failure && stats.hook_failures > 0)
     }
+}
 
-    if opts.directory_arguments {
-        let mut was_error = false;
+fn main() {
+    let started = std::time::Instant::now();
 
-        for directory in args.iter() {
-            if !create_directory(directory, true, opts.verbose) {
-                was_error = true;
+    let command_line = cli::unbundle(std::env::args_os().skip(1));
+
+    let mut opts =
+        match cli::Options::parse_with_environment(command_line, |name| std::env::var(name).ok()) {
+            Ok(opts) => opts,
+            Err(e) => {
+                e.report();
+                std::process::exit(1);
             }
-        }
+        };
 
-        std::process::exit(if was_error { 1 } else { 0 });
-    }
+    let mut args = std::mem::take(&mut opts.operands);
 
-    if args.len() < 2 {
-        eprintln!(
-            "winstall: missing destination file operand after '{}'",
-            args[0]
-        );
+    quoting::set(opts.quoting_style);
 
-        eprintln!("Try 'winstall --help' for more information.");
-        std::process::exit(1);
+    let action = match opts.action() {
+        Ok(action) => action,
+        Err(e) => {
+            e.report();
+            std::process::exit(1);
+        }
+    };
+
+    if let cli::Action::Help = action {
+        println!("{}", include_str!("usage.txt"));
+        std::process::exit(0);
     }
 
-    let backup_method = opts.backup.and_then(|o| {
-        let suffix = opts
-            .suffix
-            .or(std::env::var("SIMPLE_BACKUP_SUFFIX").ok())
-            .unwrap_or("~".to_string());
+    if let cli::Action::Version = action {
+        println!(include_str!("version.txt"));
+        std::process::exit(0);
+    }
 
-        o.and_then(|mode| match mode.as_str() {
-            "none" | "off" => None,
-            "numbered" | "t" => Some(Backup::Numbered),
-            "simple" | "never" => Some(Backup::Simple(suffix.clone())),
-            "existing" | "nil" => Some(Backup::Existing(suffix.clone())),
-            _ => {
-                eprintln!(
-                    concat!(
-                        "install: invalid argument ‘{}’ for ‘backup type’\n",
-                        "Valid arguments are:\n",
-                        "  - ‘none’, ‘off’\n",
-                        "  - ‘simple’, ‘never’\n",
-                        "  - ‘existing’, ‘nil’\n",
-                        "  - ‘numbered’, ‘t’\n",
-                        "Try 'install --help' for more information.",
-                    ),
-                    mode
-                );
+    // The config file is part of the environment, so --no-env ignores it too.
+    let config = match config::path(|name| std::env::var(name).ok()) {
+        Some(path) if !opts.no_env => match config::load(path) {
+            Ok(config) => config,
+            Err(message) => {
+                eprintln!("winstall: {}", message);
+                std::process::exit(1);
+            }
+        },
+        _ => config::Config::default(),
+    };
 
+    if !opts.used.contains("--backup-mode") {
+        match config.get("backup-mode") {
+            None => (),
+            Some("rename") => opts.backup_mode = backup::Mode::Rename,
+            Some("copy") => opts.backup_mode = backup::Mode::Copy,
+            Some(_) => {
+                eprintln!("winstall: {}", config.invalid("backup-mode"));
                 std::process::exit(1);
             }
-        })
-        .or(Some(Backup::Existing(suffix.clone())))
-    });
+        }
+    }
 
-    let is_file_target =
-        opts.no_target_directory || (args.len() == 2 && !std::path::Path::new(&args[1]).is_dir());
+    if opts.buffer_size.is_none() {
+        if let Some(size) = config.get("buffer-size") {
+            match cli::parse_size(size) {
+                Some(size) => opts.buffer_size = Some(size),
+                None => {
+                    eprintln!("winstall: {}", config.invalid("buffer-size"));
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
 
-    match is_file_target {
-        true => file_target(
-            &args[0],
-            &args[1],
-            backup_method,
-            opts.make_all_directories,
-            opts.preserve_timestamps,
-            opts.verbose,
-        ),
-        false => {
-            let target = opts.target_directory.unwrap_or_else(|| args.pop().unwrap());
-            directory_target(
-                args,
-                target,
-                backup_method,
-                opts.make_all_directories,
-                opts.preserve_timestamps,
-                opts.verbose,
-            );
+    if opts.verbose.is_none() {
+        match config.get("verbose") {
+            None | Some("false") => (),
+            Some("true") | Some("stdout") => opts.verbose = Some(messages::Router::Stdout),
+            Some("stderr") => opts.verbose = Some(messages::Router::Stderr),
+            Some(_) => {
+                eprintln!("winstall: {}", config.invalid("verbose"));
+                std::process::exit(1);
+            }
         }
     }
-}
 
-fn create_directory<P: AsRef<std::path::Path>>(
-    p: P,
-    make_all_directories: bool,
-    verbose: bool,
-) -> bool {
-    let result = match make_all_directories {
-        true => std::fs::create_dir_all(p.as_ref()),
-        false => std::fs::create_dir(p.as_ref()),
+    // As with GNU install, --debug implies --verbose.
+    if opts.debug && opts.verbose.is_none() {
+        opts.verbose = Some(messages::Router::Stdout);
+    }
+
+    // With --no-env the environment is treated as empty, so that a run is determined entirely by
+    // its command line. Otherwise the config file fills in for variables that aren't set.
+    let no_env = opts.no_env;
+    let env = |name: &str| match no_env {
+        true => None,
+        false => std::env::var(name).ok().or_else(|| config.variable(name)),
     };
 
-    match result {
-        Ok(_) => {
-            if verbose {
-                eprintln!("winstall: creating directory '{}'", p.as_ref().display());
+    if let cli::Action::ShowConfig = action {
+        let backup = match resolve_backup(&opts, env) {
+            Ok(None) => "none".to_string(),
+            Ok(Some(Backup::Numbered)) => "numbered".to_string(),
+            Ok(Some(Backup::Simple(suffix))) => format!("simple (suffix '{}')", suffix),
+            Ok(Some(Backup::Existing(suffix))) => format!("existing (suffix '{}')", suffix),
+            Err(e) => format!("invalid ('{}')", e.control),
+        };
+
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+
+        println!(
+            "flavor: {}",
+            match opts.flavor {
+                Flavor::Gnu => "gnu",
+                Flavor::Bsd => "bsd",
             }
-        }
-        Err(e) => match e.kind() {
-            std::io::ErrorKind::AlreadyExists => (),
-            _ => {
-                eprintln!(
-                    "winstall: cannot create directory '{}': {}",
-                    p.as_ref().display(),
-                    e
-                );
-
-                return false;
+        );
+        println!("backup: {}", backup);
+        println!(
+            "backup mode: {}",
+            match opts.backup_mode {
+                backup::Mode::Rename => "rename",
+                backup::Mode::Copy => "copy",
             }
-        },
+        );
+        println!(
+            "verbose: {}",
+            match opts.verbose {
+                None => "no",
+                Some(messages::Router::Stdout) => "yes (stdout)",
+                Some(messages::Router::Stderr) => "yes (stderr)",
+            }
+        );
+        println!("preserve timestamps: {}", yes_no(opts.preserve_timestamps));
+        println!(
+            "environment: {}",
+            if opts.no_env {
+                "ignored (--no-env)"
+            } else {
+                "used"
+            }
+        );
+
+        std::process::exit(0);
     }
 
-    true
-}
+    if let cli::Action::SelfTest = action {
+        let dir = match (&opts.chdir, args.first()) {
+            (Some(chdir), Some(arg)) => chdir.join(arg),
+            (Some(chdir), None) => chdir.clone(),
+            (None, Some(arg)) => std::path::PathBuf::from(arg),
+            (None, None) => std::path::PathBuf::from("."),
+        };
 
-fn file_target<F: AsRef<std::path::Path>, T: AsRef<std::path::Path>>(
-    from: F,
-    to: T,
-    backup_method: Option<Backup>,
-    make_all_directories: bool,
-    preserve_timestamps: bool,
-    verbose: bool,
-) {
-    if from.as_ref().is_dir() {
-        eprintln!("winstall: omitting directory '{}'", from.as_ref().display());
-        std::process::exit(1);
+        std::process::exit(if diagnostics::self_test(dir) { 0 } else { 1 });
     }
 
-    let parent = to
-        .as_ref()
-        .parent()
-        .and_then(|p| {
-            if p == std::path::Path::new("") {
-                return None;
-            }
+    if let cli::Action::ShowEffectiveAccess(destination) = &action {
+        let destination = match &opts.chdir {
+            Some(chdir) => chdir.join(destination),
+            None => destination.clone(),
+        };
+
+        std::process::exit(if diagnostics::effective_access(destination) {
+            0
+        } else {
+            1
+        });
+    }
 
-            Some(p)
-        })
-        .unwrap_or(std::path::Path::new("."));
+    if let cli::Action::VerifyTree(checksums) = &action {
+        let in_chdir = |p: &std::path::Path| match &opts.chdir {
+            Some(chdir) => chdir.join(p),
+            None => p.to_path_buf(),
+        };
 
-    if !create_directory(parent, make_all_directories, verbose) {
-        std::process::exit(1);
+        let root = in_chdir(opts.root.as_deref().unwrap_or(std::path::Path::new(".")));
+        std::process::exit(verify::tree(in_chdir(checksums), root));
     }
 
-    let success = copy_file(
-        from.as_ref(),
-        to.as_ref(),
-        &backup_method,
-        preserve_timestamps,
-        verbose,
-    );
+    if let cli::Action::PruneBackups(dir) = &action {
+        let dir = match &opts.chdir {
+            Some(chdir) => chdir.join(dir),
+            None => dir.clone(),
+        };
 
-    std::process::exit(if success { 0 } else { 1 });
-}
+        let suffix = backup::suffix(opts.suffix.clone(), env);
 
-fn directory_target<F: AsRef<std::path::Path>, T: AsRef<std::path::Path>>(
-    files: Vec<F>,
-    target: T,
-    backup_method: Option<Backup>,
-    make_all_directories: bool,
-    preserve_timestamps: bool,
-    verbose: bool,
-) {
-    if !create_directory(target.as_ref(), make_all_directories, verbose) {
-        std::process::exit(1);
+        let success = backup::prune(
+            dir,
+            &suffix,
+            opts.keep,
+            opts.older_than
+                .map(|days| std::time::Duration::from_secs(days * 24 * 60 * 60)),
+            opts.verbose,
+        );
+
+        std::process::exit(if success { 0 } else { 1 });
     }
 
-    let mut any_errors = false;
+    // Artifacts are resolved to absolute paths, and come before any other operands.
+    if !opts.cargo_artifacts.is_empty() {
+        #[cfg(feature = "cargo")]
+        {
+            let dir = opts.chdir.as_deref().unwrap_or(std::path::Path::new("."));
+            let mut artifacts = Vec::new();
+
+            for spec in &opts.cargo_artifacts {
+                match cargo::artifact(spec, dir, env) {
+                    Ok(path) => artifacts.push(path.into_os_string()),
+                    Err(e) => {
+                        eprintln!("winstall: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
 
-    for file in files {
-        if file.as_ref().is_dir() {
-            eprintln!("winstall: omitting directory '{}'", file.as_ref().display());
-            continue;
+            args.splice(0..0, artifacts);
         }
 
-        let source_name = file
-            .as_ref()
-            .file_name()
-            .expect("source file should have name");
+        #[cfg(not(feature = "cargo"))]
+        {
+            eprintln!("winstall: --cargo-artifact is not supported by this build");
+            std::process::exit(1);
+        }
+    }
 
-        let dest_path = target.as_ref().join(source_name);
+    // Rather than changing the working directory of the process, relative operands are resolved
+    // against the --chdir directory up front (joining leaves absolute paths untouched).
+    if let Some(dir) = &opts.chdir {
+        let resolve = |p: &std::path::PathBuf| dir.join(p);
 
-        let success = copy_file(
-            file.as_ref(),
-            dest_path,
-            &backup_method,
-            preserve_timestamps,
-            verbose,
-        );
+        args = args.iter().map(|p| dir.join(p).into_os_string()).collect();
+        opts.target_directories = opts.target_directories.iter().map(resolve).collect();
+        opts.reference = opts.reference.as_ref().map(resolve);
+        opts.tmp_dir = opts.tmp_dir.as_ref().map(resolve);
+        opts.state_file = opts.state_file.as_ref().map(resolve);
 
-        if !success {
-            any_errors = true;
+        for (path, _) in opts.expectations.iter_mut() {
+            *path = resolve(path);
         }
     }
 
-    std::process::exit(if !any_errors { 0 } else { 1 });
-}
+    if let Err(e) = opts.check_operands(&args) {
+        e.report();
+        std::process::exit(1);
+    }
 
-fn copy_file<F: AsRef<std::path::Path>, T: AsRef<std::path::Path>>(
-    from: F,
-    to: T,
-    backup_method: &Option<Backup>,
-    preserve_timestamps: bool,
-    verbose: bool,
-) -> bool {
-    let mut source = match std::fs::OpenOptions::new().read(true).open(from.as_ref()) {
-        Ok(f) => f,
-        Err(e) => {
-            eprintln!(
-                "winstall: cannot open file to read '{}': {}",
-                from.as_ref().display(),
-                e
-            );
+    let fingerprint = opts.skip_if_unchanged.as_ref().and_then(|state| {
+        let sources = match (opts.directory_arguments, opts.target_directories.is_empty()) {
+            (true, _) => &args[..0],
+            (false, false) => &args[..],
+            (false, true) => &args[..args.len() - 1],
+        };
+
+        let fingerprint = run_fingerprint(sources, &config, env).ok()?;
+        let previous = std::fs::read_to_string(state);
+
+        if previous.is_ok_and(|p| p.trim() == fingerprint) && destinations_exist(&opts, &args) {
+            if let Some(router) = opts.verbose {
+                router.send(messages::unchanged());
+            }
 
-            return false;
+            std::process::exit(0);
         }
-    };
 
-    let timestamps = if preserve_timestamps {
-        source
-            .metadata()
-            .and_then(|m| {
-                Ok(Option::zip(
-                    m.accessed()
-                        .map_err(|e| {
-                            eprintln!(
-                                "winstall: unable to get last accessed time for '{}': {}",
-                                from.as_ref().display(),
-                                e
-                            );
+        Some((state, fingerprint))
+    });
 
-                            e
-                        })
-                        .ok(),
-                    m.modified()
-                        .map_err(|e| {
-                            eprintln!(
-                                "winstall: unable to get last modified time for '{}': {}",
-                                from.as_ref().display(),
-                                e
-                            );
+    let mut stats = stats::Stats::default();
 
-                            e
-                        })
-                        .ok(),
-                )
-                .and_then(|(accessed, modified)| {
-                    Some(
-                        std::fs::FileTimes::new()
-                            .set_accessed(accessed)
-                            .set_modified(modified),
-                    )
-                }))
-            })
-            .unwrap_or(None)
-    } else {
-        None
-    };
+    let finish = |stats: &stats::Stats, code: i32| {
+        let code = match opts.strict && stats.warnings > 0 && code != 1 {
+            true => {
+                eprintln!(
+                    "winstall: {} warning(s) treated as errors (--strict)",
+                    stats.warnings
+                );
+                1
+            }
+            false => code,
+        };
 
-    let mut backup_path = None::<std::path::PathBuf>;
+        if opts.summary {
+            let router = opts.verbose.unwrap_or(messages::Router::Stdout);
+            router.send(messages::summary(stats, started.elapsed()));
+        }
 
-    let mut dest = match std::fs::OpenOptions::new()
-        .write(true)
-        .create_new(true)
-        .open(to.as_ref())
-    {
-        Ok(f) => f,
-        Err(e) => {
-            if e.kind() != std::io::ErrorKind::AlreadyExists {
+        if let (Some((state, fingerprint)), 0) = (&fingerprint, code) {
+            if let Err(e) = std::fs::write(state, fingerprint) {
                 eprintln!(
-                    "winstall: cannot open file to write '{}': {}",
-                    to.as_ref().display(),
+                    "winstall: unable to write state to {}: {}",
+                    quoting::quote(state),
                     e
                 );
+            }
+        }
 
-                return false;
+        if let Some(path) = &opts.stats_file {
+            if let Err(e) = stats.append_to(path, started.elapsed(), &opts.used, code) {
+                eprintln!(
+                    "winstall: unable to write statistics to {}: {}",
+                    quoting::quote(path),
+                    e
+                );
             }
+        }
 
-            let backup_file = match backup_method {
-                None => std::fs::OpenOptions::new()
-                    .write(true)
-                    .create(true)
-                    .truncate(true)
-                    .open(to.as_ref())
-                    .and_then(|f| {
-                        if verbose {
-                            eprintln!("removed '{}'", to.as_ref().display())
-                        }
+        std::process::exit(code);
+    };
 
-                        Ok(f)
-                    }),
-                Some(b) => {
-                    let name = match b {
-                        Backup::Simple(suffix) => add_suffix(to.as_ref(), suffix),
-                        Backup::Numbered => next_numbered_backup(to.as_ref()).0,
-                        Backup::Existing(suffix) => match next_numbered_backup(to.as_ref()) {
-                            (_, true) => add_suffix(to.as_ref(), suffix),
-                            (numbered, false) => numbered,
-                        },
-                    };
-
-                    _ = std::fs::rename(to.as_ref(), &name).map_err(|e| {
-                        eprintln!(
-                            "winstall: unable preserve '{}' as backup '{}': {}",
-                            to.as_ref().display(),
-                            name.display(),
-                            e
-                        )
-                    });
+    #[cfg(unix)]
+    let attributes = match unix::attributes(
+        opts.mode.as_deref(),
+        opts.owner.as_deref(),
+        opts.group.as_deref(),
+        opts.strip
+            .then(|| opts.strip_program.as_deref().unwrap_or("strip".as_ref())),
+    ) {
+        Ok(attributes) => attributes,
+        Err(message) => {
+            eprintln!("winstall: {}", message);
+            std::process::exit(1);
+        }
+    };
 
-                    backup_path = Some(name.clone());
+    #[cfg(not(unix))]
+    let attributes = Attributes::default();
 
-                    std::fs::OpenOptions::new()
-                        .write(true)
-                        .create_new(true)
-                        .open(to.as_ref())
-                }
-            };
+    if let cli::Action::Directories = action {
+        let mut was_error = false;
 
-            match backup_file {
-                Ok(f) => f,
+        let times = match (opts.preserve_timestamps, &opts.reference) {
+            (true, Some(reference)) => match std::fs::metadata(reference) {
+                Ok(m) => timestamps::Timestamps::read(&m, reference).map(|t| {
+                    match opts.preserve_creation_time {
+                        true => t.with_created(&m, reference),
+                        false => t,
+                    }
+                }),
                 Err(e) => {
                     eprintln!(
-                        "winstall: cannot open file to write '{}': {}",
-                        to.as_ref().display(),
+                        "winstall: cannot stat reference {}: {}",
+                        quoting::quote(reference),
                         e
                     );
+                    std::process::exit(1);
+                }
+            },
+            _ => None,
+        };
+
+        for directory in args.iter() {
+            let Some(created) = create_directory(directory, true, opts.verbose) else {
+                was_error = true;
+                stats.failed += 1;
+                continue;
+            };
 
-                    return false;
+            stats.directories += 1;
+
+            #[cfg(unix)]
+            if let Err(e) = unix::apply(directory, &attributes, true) {
+                eprintln!(
+                    "winstall: cannot set attributes of {}: {}",
+                    quoting::quote(directory),
+                    e
+                );
+
+                was_error = true;
+            }
+
+            // Directories that were already there keep their own times.
+            let Some(t) = &times else {
+                continue;
+            };
+
+            for directory in &created {
+                match timestamps::set_directory(directory, t) {
+                    Ok(_) => warn_imprecise(&mut stats, t.verify(directory), directory),
+                    Err(e) => errors::warn(
+                        &mut stats,
+                        &format!(
+                            "unable to set directory times for {}: {}",
+                            quoting::quote(directory),
+                            e
+                        ),
+                    ),
                 }
             }
         }
-    };
 
-    match std::io::copy(&mut source, &mut dest) {
-        Ok(_) => (),
+        finish(&stats, if was_error { 1 } else { 0 });
+    }
+
+    let backup_method = match resolve_backup(&opts, env) {
+        Ok(method) => method,
         Err(e) => {
-            eprintln!("winstall: cannot copy file: {}", e);
-            return false;
+            cli::ArgumentError::InvalidBackup(e).report();
+            std::process::exit(1);
         }
     };
 
-    if let Some(t) = timestamps {
-        if let Err(e) = dest.set_times(t) {
+    // Rebuilding the path from its components drops any trailing separators, so that a file named
+    // with one is found to be a file rather than failing to be examined at all.
+    let target = match opts.target_directories.is_empty() {
+        true => paths::classify(
+            std::path::Path::new(&args[args.len() - 1])
+                .components()
+                .collect::<std::path::PathBuf>(),
+        ),
+        false => paths::Target::NotFound,
+    };
+
+    // Without being able to examine the last operand there is no telling whether it is meant to be
+    // a directory, and guessing wrong would install to the wrong place.
+    if let paths::Target::Inaccessible(e) = &target {
+        eprintln!(
+            "winstall: cannot stat {}: {}",
+            quoting::quote(&args[args.len() - 1]),
+            e
+        );
+        finish(&stats, 1);
+    }
+
+    if args.len() > 2 && matches!(target, paths::Target::File) {
+        eprintln!(
+            "winstall: target {} is not a directory",
+            quoting::quote(&args[args.len() - 1])
+        );
+        finish(&stats, 1);
+    }
+
+    // A trailing separator says the last operand is a directory, so it is never installed to as a
+    // file. It has to exist already unless -D was given to create it.
+    let wants_directory = opts.target_directories.is_empty()
+        && !opts.no_target_directory
+        && paths::ends_with_separator(&args[args.len() - 1]);
+
+    if wants_directory {
+        let problem = match target {
+            paths::Target::File => Some("Not a directory"),
+            paths::Target::NotFound | paths::Target::DanglingSymlink
+                if !opts.make_all_directories =>
+            {
+                Some("No such file or directory")
+            }
+            _ => None,
+        };
+
+        if let Some(problem) = problem {
             eprintln!(
-                "winstall: unable to set file times for '{}': {}",
-                to.as_ref().display(),
-                e
+                "winstall: target {}: {}",
+                quoting::quote(&args[args.len() - 1]),
+                problem
             );
+            finish(&stats, 1);
         }
     }
 
-    if verbose {
-        print!(
-            "'{}' -> '{}'",
-            from.as_ref().display(),
-            to.as_ref().display()
-        );
+    let is_file_target = opts.no_target_directory
+        || (opts.target_directories.is_empty()
+            && args.len() == 2
+            && !target.is_directory()
+            && !wants_directory);
+
+    let signing = match opts.signing(env) {
+        Ok(signing) => signing,
+        Err(e) => {
+            e.report();
+            std::process::exit(1);
+        }
+    };
+
+    let version = match opts.version() {
+        Ok(version) => version,
+        Err(e) => {
+            e.report();
+            std::process::exit(1);
+        }
+    };
 
-        backup_path.map(|path| print!(" (backup: '{}')", path.display()));
+    let mut includes = Vec::new();
+    let mut excludes = opts.excludes.clone();
 
-        print!("\n");
+    for (list, patterns) in [
+        (&opts.include_from, &mut includes),
+        (&opts.exclude_from, &mut excludes),
+    ] {
+        for path in list {
+            match filter::read_patterns(path) {
+                Ok(read) => patterns.extend(read),
+                Err(e) => {
+                    eprintln!(
+                        "winstall: unable to read patterns from {}: {}",
+                        quoting::quote(path),
+                        e
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
     }
 
-    true
-}
+    let filter = filter::Filter::new(&includes, &excludes);
+
+    // Definitions are applied over the environment, which is read once up front rather than for
+    // each placeholder.
+    let variables = match opts.variables(|| match opts.no_env {
+        true => std::collections::BTreeMap::new(),
+        false => std::env::vars_os()
+            .filter_map(|(k, v)| Some((k.into_string().ok()?, v.to_string_lossy().into_owned())))
+            .collect(),
+    }) {
+        Ok(variables) => variables,
+        Err(e) => {
+            e.report();
+            std::process::exit(1);
+        }
+    };
 
-fn next_numbered_backup<P: AsRef<std::path::Path>>(p: P) -> (std::path::PathBuf, bool) {
-    let parent = p
-        .as_ref()
-        .parent()
-        .and_then(|parent| {
-            if parent == std::path::Path::new("") {
-                None
-            } else {
-                Some(parent)
-            }
-        })
-        .unwrap_or(std::path::Path::new("."));
+    let transform = transform::Transform {
+        line_endings: opts.line_endings,
+        variables,
+    };
 
-    let file_name = p
-        .as_ref()
-        .file_name()
-        .expect("file argument should have a name")
-        .to_string_lossy()
-        .to_string();
-
-    std::fs::read_dir(parent)
-        .and_then(|entries| {
-            let mut max = 0;
-
-            for entry in entries {
-                _ = entry.map(|e| {
-                    let entry_name = e.file_name().to_string_lossy().to_string();
-                    if entry_name.starts_with(&file_name) && entry_name.ends_with("~") {
-                        let num = entry_name
-                            .strip_prefix(&file_name)
-                            .and_then(|s| s.strip_prefix(".~"))
-                            .and_then(|s| s.strip_suffix("~"))
-                            .and_then(|s| s.parse::<u32>().ok());
-
-                        num.map(|n| max = n.max(max));
-                    }
-                });
-            }
+    let staging_directory = match opts.staging_directory() {
+        Ok(dir) => dir,
+        Err(e) => {
+            e.report();
+            std::process::exit(1);
+        }
+    };
 
-            Ok((add_suffix(p.as_ref(), &format!(".~{}~", max + 1)), max == 0))
-        })
-        .unwrap_or((add_suffix(p.as_ref(), ".~1~"), true))
-}
-
-fn add_suffix<P: AsRef<std::path::Path>>(p: P, suffix: &str) -> std::path::PathBuf {
-    p.as_ref().with_file_name(format!(
-        "{}{}",
-        p.as_ref()
-            .file_name()
-            .map(|s| s.to_string_lossy())
-            .unwrap_or("".into()),
-        suffix,
-    ))
+    let install = Install {
+        backup_method,
+        backup_mode: opts.backup_mode,
+        make_all_directories: opts.make_all_directories,
+        preserve_timestamps: opts.preserve_timestamps,
+        verbose: opts.verbose,
+        buffer_size: opts.buffer_size,
+        interactive: opts.interactive && !opts.force,
+        confirm: Box::new(confirm_overwrite),
+        force: opts.force,
+        no_clobber: opts.no_clobber,
+        expectations: opts
+            .expectations
+            .iter()
+            .map(|(path, hash)| {
+                let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.into());
+                (path, hash.clone())
+            })
+            .collect(),
+        link: match opts.link {
+            Some(Link::Symbolic { .. }) => Some(Link::Symbolic {
+                relative: opts.relative,
+            }),
+            link => link,
+        },
+        hooks: hooks::Hooks {
+            signing,
+            post_install: opts.post_install.clone(),
+            halt_on_failure: opts.halt_on_hook_failure,
+        },
+        stop_at_first_error: opts.stop_at_first_error,
+        version,
+        preserve_creation_time: opts.preserve_creation_time,
+        write_through: opts.write_through,
+        compare: opts.compare,
+        resume: opts.resume,
+        debug: opts.debug,
+        filter,
+        attributes,
+        sync: opts.sync,
+        staging_directory,
+        touch: opts.touch_only,
+        transform,
+        cache: opts.state_file.as_ref().map(cache::Cache::load),
+        av_grace: std::time::Duration::from_millis(opts.av_grace_ms),
+        mtime_slack: std::time::Duration::from_secs(opts.mtime_slack),
+    };
+
+    let results = match is_file_target {
+        true => file_target(&args[0], &args[1], &install, &mut stats),
+        false => {
+            let targets = match opts.target_directories.is_empty() {
+                true => vec![args.pop().unwrap()],
+                false => opts.target_directories.iter().map(Into::into).collect(),
+            };
+
+            let mut results = Vec::new();
+
+            // Each source is installed into every target in turn, with the outcome for each
+            // target reported separately when there is more than one.
+            for target in &targets {
+                let target_results = directory_target(&args, target, &install, &mut stats);
+                let target_success = all_succeeded(&target_results);
+
+                if let Some(router) = opts.verbose.filter(|_| targets.len() > 1) {
+                    let (mut files, mut bytes, mut backups) = (0, 0, 0);
+
+                    for result in &target_results {
+                        if let Outcome::Installed { backup, bytes: n } = &result.outcome {
+                            files += 1;
+                            bytes += n;
+                            backups += backup.is_some() as u64;
+                        }
+                    }
+
+                    router.send(messages::installed_into(target, files, bytes, backups));
+                }
+
+                results.extend(target_results);
+
+                if !target_success {
+                    if targets.len() > 1 {
+                        eprintln!(
+                            "winstall: errors occurred installing into {}",
+                            quoting::quote(target)
+                        );
+                    }
+
+                    if install.stops_after_failure(&stats) {
+                        break;
+                    }
+                }
+            }
+
+            results
+        }
+    };
+
+    let mut success = all_succeeded(&results);
+
+    if let (Some(path), Some(mut cache)) = (&opts.state_file, install.cache) {
+        // Only a copy leaves the destination with the content of its source (as transformed).
+        if install.touch.is_none() && install.link.is_none() {
+            for result in &results {
+                if matches!(result.outcome, Outcome::Installed { .. }) {
+                    cache.record(&result.source, &result.destination);
+                }
+            }
+        }
+
+        if let Err(e) = cache.save(path) {
+            errors::warn(
+                &mut stats,
+                &format!("unable to write state to {}: {}", quoting::quote(path), e),
+            );
+        }
+    }
+
+    // With --sync=final the files are only flushed once they have all been installed, and each
+    // directory they were installed into is flushed once rather than after every file.
+    if opts.sync == Some(Flush::Final) {
+        let installed = results
+            .iter()
+            .filter(|r| matches!(r.outcome, Outcome::Installed { .. }))
+            .map(|r| r.destination.as_path())
+            .collect::<Vec<_>>();
+
+        let mut directories = std::collections::BTreeSet::new();
+
+        for destination in installed {
+            let synced = sync_file(destination).and_then(|_| {
+                match directories.insert(destination.parent().unwrap_or(destination)) {
+                    true => sync_directory(destination),
+                    false => Ok(()),
+                }
+            });
+
+            if let Err(e) = synced {
+                eprintln!(
+                    "winstall: cannot sync {}: {}",
+                    quoting::quote(destination),
+                    e
+                );
+
+                stats.error(&e);
+                success = false;
+            }
+        }
+    }
+
+    if stats.hook_failures > 0 {
+        eprintln!(
+            "winstall: {} post-install command(s) failed",
+            stats.hook_failures
+        );
+    }
+
+    if let Some(router) = opts.verbose {
+        for result in results.iter().filter(|r| !r.succeeded()) {
+            let reason = match &result.outcome {
+                Outcome::Failed(Some(kind)) => Some(kind.to_string()),
+                Outcome::Installed { .. } => Some("post-install".to_string()),
+                _ => None,
+            };
+
+            router.send(messages::failed(
+                &result.source,
+                &result.destination,
+                reason.as_deref(),
+            ));
+        }
+    }
+
+    if !success && opts.stop_at_first_error {
+        eprintln!(
+            "winstall: stopped at the first error: {} file(s) installed, {} failed",
+            stats.files, stats.failed
+        );
+    } else if stats.failed > 0 {
+        eprintln!(
+            "winstall: {} file(s) installed, {} failed",
+            stats.files, stats.failed
+        );
+    }
+
+    let code = match success {
+        true if opts.no_clobber && stats.skipped > 0 => 2,
+        true => 0,
+        false => 1,
+    };
+
+    finish(&stats, code);
+}
+
+// BSD install's -b always makes a simple backup, with the suffix '.old' unless -B gives another,
+// and takes no notice of the environment.
+fn resolve_backup(
+    opts: &cli::Options,
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<Option<Backup>, backup::InvalidControl> {
+    match (opts.flavor, &opts.backup) {
+        (Flavor::Bsd, Some(None)) => Ok(Some(Backup::Simple(
+            opts.suffix.clone().unwrap_or(".old".to_string()),
+        ))),
+        _ => backup::resolve(opts.backup.clone(), opts.suffix.clone(), env),
+    }
+}
+
+// Identifies a run by everything that determines what it would do: the working directory, the
+// complete command line, the environment and config file it reads options from and the content of
+// every source. If any source can't be read there is no fingerprint, and the run goes ahead to
+// report the problem.
+fn run_fingerprint(
+    sources: &[std::ffi::OsString],
+    config: &config::Config,
+    env: impl Fn(&str) -> Option<String>,
+) -> std::io::Result<String> {
+    let mut hasher = sha256::Sha256::new();
+
+    let mut field = |value: &[u8]| {
+        hasher.update(&(value.len() as u64).to_le_bytes());
+        hasher.update(value);
+    };
+
+    field(std::env::current_dir()?.to_string_lossy().as_bytes());
+
+    for arg in std::env::args_os().skip(1) {
+        field(arg.as_encoded_bytes());
+    }
+
+    // An unset variable and an empty one are told apart, as they aren't always treated alike.
+    for name in cli::environment_variables() {
+        match env(&name) {
+            Some(value) => field(format!("{}={}", name, value).as_bytes()),
+            None => field(name.as_bytes()),
+        }
+    }
+
+    for (key, value) in config.settings() {
+        field(format!("{}={}", key, value).as_bytes());
+    }
+
+    for source in sources {
+        field(sha256::file(source)?.as_bytes());
+    }
+
+    Ok(sha256::hex(&hasher.finish()))
+}
+
+// Whether everything the run would install is still there, since an unchanged run only has
+// nothing to do if nothing has been removed since it was last made.
+fn destinations_exist(opts: &cli::Options, args: &[std::ffi::OsString]) -> bool {
+    let installed =
+        |directory: &std::path::Path, source: &std::ffi::OsString| match std::path::Path::new(
+            source,
+        )
+        .file_name()
+        {
+            Some(name) => directory.join(name).exists(),
+            None => false,
+        };
+
+    if opts.directory_arguments {
+        return args.iter().all(|d| std::path::Path::new(d).is_dir());
+    }
+
+    if !opts.target_directories.is_empty() {
+        return opts
+            .target_directories
+            .iter()
+            .all(|target| args.iter().all(|source| installed(target, source)));
+    }
+
+    let Some((last, sources)) = args.split_last() else {
+        return false;
+    };
+
+    let last = std::path::Path::new(last);
+
+    match last.is_dir() && !opts.no_target_directory {
+        true => sources.iter().all(|source| installed(last, source)),
+        false => last.exists(),
+    }
+}
+
+// Returns the directories that were created, outermost first, leaving out any that were already
+// there. None means one couldn't be created, which has been reported.
+fn create_directory<P: AsRef<std::path::Path>>(
+    p: P,
+    make_all_directories: bool,
+    verbose: Option<messages::Router>,
+) -> Option<Vec<std::path::PathBuf>> {
+    // Rebuilding the path from its components drops any trailing separators.
+    let p = p.as_ref().components().collect::<std::path::PathBuf>();
+
+    if let Some(name) = paths::reserved_name(&p) {
+        eprintln!(
+            "winstall: cannot create directory {}: '{}' is a reserved device name on Windows",
+            quoting::quote(p),
+            name
+        );
+
+        return None;
+    }
+
+    // A root (a drive such as C:\, or a share such as \\server\share) can never be created, so
+    // if it isn't there nothing beneath it can be either.
+    let root = p
+        .components()
+        .take_while(|c| {
+            matches!(
+                c,
+                std::path::Component::Prefix(_) | std::path::Component::RootDir
+            )
+        })
+        .collect::<std::path::PathBuf>();
+
+    if !root.as_os_str().is_empty() {
+        if !root.is_dir() {
+            eprintln!(
+                concat!(
+                    "winstall: cannot create directory {}: the root {} does not exist or is ",
+                    "not reachable",
+                ),
+                quoting::quote(p),
+                quoting::quote(root)
+            );
+
+            return None;
+        }
+
+        if p == root {
+            return Some(Vec::new());
+        }
+    }
+
+    // With -D (or -d) each missing component is created in turn, so that a failure names the
+    // component that couldn't be created rather than the whole path.
+    let components = match make_all_directories {
+        true => p
+            .ancestors()
+            .take_while(|a| !a.as_os_str().is_empty() && *a != root)
+            .collect::<Vec<_>>(),
+        false => vec![p.as_path()],
+    };
+
+    let mut created = Vec::new();
+
+    for component in components.into_iter().rev() {
+        if make_all_directories && component.is_dir() {
+            continue;
+        }
+
+        match std::fs::create_dir(component) {
+            Ok(_) => {
+                if let Some(router) = verbose {
+                    router.send(messages::creating_directory(component));
+                }
+
+                created.push(component.to_path_buf());
+            }
+            Err(e)
+                if e.kind() == std::io::ErrorKind::AlreadyExists
+                    && (!make_all_directories || component.is_dir()) => {}
+            Err(e) => {
+                // GNU install words these the same way everywhere, where the text of an
+                // io::Error differs between platforms.
+                let reason = match e.kind() {
+                    std::io::ErrorKind::NotFound => "No such file or directory".to_string(),
+                    std::io::ErrorKind::PermissionDenied if !errors::is_unc(component) => {
+                        "Permission denied".to_string()
+                    }
+                    std::io::ErrorKind::InvalidFilename if cfg!(unix) => {
+                        "File name too long".to_string()
+                    }
+                    std::io::ErrorKind::AlreadyExists => "File exists".to_string(),
+                    _ => errors::describe(&e, component),
+                };
+
+                eprintln!(
+                    "winstall: cannot create directory {}: {}",
+                    quoting::quote(component),
+                    reason
+                );
+
+                return None;
+            }
+        }
+    }
+
+    Some(created)
+}
+
+fn file_target<F: AsRef<std::path::Path>, T: AsRef<std::path::Path>>(
+    from: F,
+    to: T,
+    install: &Install,
+    stats: &mut stats::Stats,
+) -> Vec<FileResult> {
+    let not_installed = |outcome| {
+        vec![FileResult {
+            source: from.as_ref().to_path_buf(),
+            destination: to.as_ref().to_path_buf(),
+            outcome,
+            hooks_succeeded: true,
+        }]
+    };
+
+    if from.as_ref().is_dir() {
+        eprintln!(
+            "winstall: omitting directory {}",
+            quoting::quote(from.as_ref())
+        );
+        stats.failed += 1;
+        return not_installed(Outcome::Failed(None));
+    }
+
+    // Only reachable with -T, as otherwise a directory destination is installed into.
+    if paths::classify(to.as_ref()).is_directory() {
+        eprintln!(
+            "winstall: cannot overwrite directory {} with non-directory",
+            quoting::quote(to.as_ref())
+        );
+        stats.failed += 1;
+        return not_installed(Outcome::Failed(None));
+    }
+
+    // As with GNU install, the directories leading to DEST are only created with -D. Without it
+    // they have to exist already, and if they don't, opening DEST says so.
+    if install.make_all_directories {
+        let parent = to
+            .as_ref()
+            .parent()
+            .and_then(|p| {
+                if p == std::path::Path::new("") {
+                    return None;
+                }
+
+                Some(p)
+            })
+            .unwrap_or(std::path::Path::new("."));
+
+        if create_directory(parent, true, install.verbose).is_none() {
+            stats.failed += 1;
+            return not_installed(Outcome::Failed(None));
+        }
+    }
+
+    let result = copy_file(from.as_ref(), to.as_ref(), install, stats);
+
+    if !result.succeeded() {
+        stats.failed += 1;
+    }
+
+    vec![result]
+}
+
+fn directory_target<F: AsRef<std::path::Path>, T: AsRef<std::path::Path>>(
+    files: &[F],
+    target: T,
+    install: &Install,
+    stats: &mut stats::Stats,
+) -> Vec<FileResult> {
+    let mut results = Vec::new();
+
+    let not_installed = |file: &std::path::Path, outcome| FileResult {
+        source: file.to_path_buf(),
+        destination: target.as_ref().join(file.file_name().unwrap_or_default()),
+        outcome,
+        hooks_succeeded: true,
+    };
+
+    if create_directory(
+        target.as_ref(),
+        install.make_all_directories,
+        install.verbose,
+    )
+    .is_none()
+    {
+        for file in files {
+            results.push(not_installed(file.as_ref(), Outcome::Failed(None)));
+        }
+
+        return results;
+    }
+
+    for file in files {
+        if install.filter.leaves_out(file.as_ref()) {
+            if let Some(router) = install.verbose.filter(|_| install.debug) {
+                router.send(messages::excluded(file.as_ref()));
+            }
+
+            continue;
+        }
+
+        if file.as_ref().is_dir() {
+            eprintln!(
+                "winstall: omitting directory {}",
+                quoting::quote(file.as_ref())
+            );
+            results.push(not_installed(file.as_ref(), Outcome::Skipped));
+            continue;
+        }
+
+        // A source such as 'dir/..' has no name to be installed under. One that exists is a
+        // directory and has been omitted already, so this is almost always one that doesn't.
+        let Some(source_name) = file.as_ref().file_name() else {
+            match std::fs::metadata(file.as_ref()) {
+                Ok(_) => eprintln!(
+                    "winstall: cannot install {}: it has no file name",
+                    quoting::quote(file.as_ref())
+                ),
+                Err(e) => {
+                    eprintln!(
+                        "winstall: cannot stat {}: {}",
+                        quoting::quote(file.as_ref()),
+                        e
+                    );
+
+                    stats.error(&e);
+                }
+            }
+
+            results.push(not_installed(file.as_ref(), Outcome::Failed(None)));
+            stats.failed += 1;
+
+            if install.stops_after_failure(stats) {
+                break;
+            }
+
+            continue;
+        };
+
+        let dest_path = target.as_ref().join(source_name);
+
+        let result = copy_file(file.as_ref(), dest_path, install, stats);
+        let success = result.succeeded();
+        results.push(result);
+
+        if !success {
+            stats.failed += 1;
+
+            if install.stops_after_failure(stats) {
+                break;
+            }
+        }
+    }
+
+    results
+}
+
+fn copy_file<F: AsRef<std::path::Path>, T: AsRef<std::path::Path>>(
+    from: F,
+    to: T,
+    install: &Install,
+    stats: &mut stats::Stats,
+) -> FileResult {
+    // A versioned install puts the file in place under its versioned name, and only once that
+    // has succeeded is the unversioned name switched over to it.
+    let destination = match &install.version {
+        Some(version) => versioned_name(to.as_ref(), version),
+        None => to.as_ref().to_path_buf(),
+    };
+
+    // The unversioned name is replaced like any other destination, and whether it may be is
+    // decided before anything is installed, so that a file that is left alone doesn't leave a new
+    // versioned file behind either.
+    let mut _readonly = ReadonlyGuard(None);
+
+    if install.version.is_some() && std::fs::symlink_metadata(to.as_ref()).is_ok() {
+        let allowed = match matches_expectation(to.as_ref(), install) {
+            true => may_replace(to.as_ref(), install, stats),
+            false => Err(Outcome::Failed(None)),
+        };
+
+        match allowed {
+            Ok(guard) => _readonly = guard,
+            Err(outcome) => {
+                return FileResult {
+                    source: from.as_ref().to_path_buf(),
+                    destination,
+                    outcome,
+                    hooks_succeeded: true,
+                }
+            }
+        }
+    }
+
+    let mut outcome = install_file(from.as_ref(), &destination, install, stats);
+
+    if install.version.is_some() && matches!(outcome, Outcome::Installed { .. }) {
+        if let Err(e) = update_current(&destination, to.as_ref(), install, stats) {
+            eprintln!(
+                "winstall: cannot point {} at {}: {}",
+                quoting::quote(to.as_ref()),
+                quoting::quote(&destination),
+                e
+            );
+
+            stats.error(&e);
+            outcome = Outcome::Failed(Some(e.kind()));
+        }
+    }
+
+    if install.sync == Some(Flush::Each) && matches!(outcome, Outcome::Installed { .. }) {
+        let synced = sync_file(&destination).and_then(|_| sync_directory(&destination));
+
+        if let Err(e) = synced {
+            eprintln!(
+                "winstall: cannot sync {}: {}",
+                quoting::quote(&destination),
+                e
+            );
+
+            stats.error(&e);
+            outcome = Outcome::Failed(Some(e.kind()));
+        }
+    }
+
+    let hooks_succeeded = match outcome {
+        Outcome::Installed { .. } => {
+            install
+                .hooks
+                .run(from.as_ref(), &destination, install.verbose, stats)
+        }
+        _ => true,
+    };
+
+    FileResult {
+        source: from.as_ref().to_path_buf(),
+        destination,
+        outcome,
+        hooks_succeeded,
+    }
+}
+
+// Inserts the version before the extension, so that 'app.exe' becomes 'app-1.2.3.exe'.
+fn versioned_name<P: AsRef<std::path::Path>>(p: P, version: &str) -> std::path::PathBuf {
+    let mut name = p.as_ref().file_stem().unwrap_or_default().to_os_string();
+    name.push(format!("-{}", version));
+
+    if let Some(extension) = p.as_ref().extension() {
+        name.push(".");
+        name.push(extension);
+    }
+
+    p.as_ref().with_file_name(name)
+}
+
+// Replaces current with a hard link to (or failing that, a copy of) the versioned file. The
+// replacement is made under a temporary name and renamed into place, so current is never missing.
+// Whether current may be replaced has already been decided, and with a backup it is preserved first
+// as any other destination would be, then put back if the replacement can't be renamed into place.
+fn update_current<V: AsRef<std::path::Path>, C: AsRef<std::path::Path>>(
+    versioned: V,
+    current: C,
+    install: &Install,
+    stats: &mut stats::Stats,
+) -> std::io::Result<()> {
+    let temporary = backup::add_suffix(
+        current.as_ref(),
+        &format!(".winstall-{}", std::process::id()),
+    );
+
+    _ = std::fs::remove_file(&temporary);
+
+    if std::fs::hard_link(versioned.as_ref(), &temporary).is_err() {
+        std::fs::copy(versioned.as_ref(), &temporary)?;
+    }
+
+    let mut backup_path = None;
+
+    if let Some(b) = &install.backup_method {
+        if std::fs::symlink_metadata(current.as_ref()).is_ok() {
+            match backup::make(current.as_ref(), b, install.backup_mode) {
+                Ok(name) => backup_path = Some(name),
+                Err((name, e)) => {
+                    eprintln!(
+                        "winstall: unable preserve {} as backup {}: {}",
+                        quoting::quote(current.as_ref()),
+                        quoting::quote(name),
+                        e
+                    );
+
+                    _ = std::fs::remove_file(&temporary);
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    if let Err(e) = std::fs::rename(&temporary, current.as_ref()) {
+        _ = std::fs::remove_file(&temporary);
+
+        if let Some(path) = &backup_path {
+            _ = match install.backup_mode {
+                backup::Mode::Rename => std::fs::rename(path, current.as_ref()),
+                backup::Mode::Copy => std::fs::remove_file(path),
+            };
+        }
+
+        return Err(e);
+    }
+
+    if backup_path.is_some() {
+        stats.backups += 1;
+    }
+
+    if let Some(router) = install.verbose {
+        router.send(messages::linked(
+            versioned.as_ref(),
+            current.as_ref(),
+            backup_path.as_deref(),
+        ));
+    }
+
+    Ok(())
+}
+
+// Where the replacement for to is written before it is renamed into place. A rename can't move a
+// file to another volume, so it is only put in the --tmp-dir directory when that is on the same
+// volume as to, and beside to otherwise.
+fn staging_path(to: &std::path::Path, install: &Install) -> std::path::PathBuf {
+    let suffix = format!(".winstall-{}", std::process::id());
+
+    if let (Some(dir), Some(name)) = (&install.staging_directory, to.file_name()) {
+        let parent = match to.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => std::path::Path::new("."),
+        };
+
+        let same_volume = paths::same_volume(dir, parent);
+        if let Some(router) = install.verbose.filter(|_| install.debug) {
+            router.send(messages::staging(to, dir, !same_volume));
+        }
+
+        if same_volume {
+            return backup::add_suffix(dir.join(name), &suffix);
+        }
+    }
+
+    backup::add_suffix(to, &suffix)
+}
+
+fn install_file<F: AsRef<std::path::Path>, T: AsRef<std::path::Path>>(
+    from: F,
+    to: T,
+    install: &Install,
+    stats: &mut stats::Stats,
+) -> Outcome {
+    // Opening the destination to write would truncate the source before it had been read.
+    if paths::same_file(from.as_ref(), to.as_ref()) {
+        eprintln!(
+            "winstall: {} and {} are the same file",
+            quoting::quote(from.as_ref()),
+            quoting::quote(to.as_ref())
+        );
+
+        return Outcome::Failed(None);
+    }
+
+    if let Some(touch) = install.touch {
+        return touch_file(from.as_ref(), to.as_ref(), touch, install, stats);
+    }
+
+    // A destination that hasn't changed since it was installed from a source that hasn't changed
+    // either is left alone without reading them.
+    if let Some(cache) = &install.cache {
+        if cache.unchanged(from.as_ref(), to.as_ref(), install.mtime_slack) {
+            if let Some(router) = install.verbose.filter(|_| install.debug) {
+                router.send(messages::unchanged_since(from.as_ref(), to.as_ref()));
+            }
+
+            stats.skipped += 1;
+            return Outcome::Skipped;
+        }
+    }
+
+    // With -C an identical destination is left alone, not even its timestamps are touched.
+    if let Some(policy) = install.compare {
+        let same = policy
+            .same(from.as_ref(), to.as_ref(), install.mtime_slack)
+            .unwrap_or(false);
+
+        if let Some(router) = install.verbose.filter(|_| install.debug) {
+            router.send(messages::compared(
+                from.as_ref(),
+                to.as_ref(),
+                policy.name(),
+                same,
+            ));
+        }
+
+        if same {
+            stats.skipped += 1;
+            return Outcome::Skipped;
+        }
+    }
+
+    if let Some(name) = paths::reserved_name(to.as_ref()) {
+        eprintln!(
+            "winstall: cannot install to {}: '{}' is a reserved device name on Windows",
+            quoting::quote(to.as_ref()),
+            name
+        );
+
+        return Outcome::Failed(None);
+    }
+
+    if !matches_expectation(to.as_ref(), install) {
+        return Outcome::Failed(None);
+    }
+
+    // Resuming appends the rest of the source as it is, which would leave a file only partly
+    // transformed.
+    if install.resume && install.transform.is_identity() {
+        if let Some(outcome) = resume_file(from.as_ref(), to.as_ref(), install, stats) {
+            return outcome;
+        }
+    }
+
+    if let Some(link) = install.link {
+        if let Some(outcome) = link_file(from.as_ref(), to.as_ref(), link, install, stats) {
+            return outcome;
+        }
+    }
+
+    if is_app_execution_alias(from.as_ref()) {
+        eprintln!(
+            concat!(
+                "winstall: cannot install {}: it is an app execution alias, a placeholder ",
+                "that only launches a Store app and has no content of its own; install the ",
+                "executable from the app's installation directory instead",
+            ),
+            quoting::quote(from.as_ref())
+        );
+
+        return Outcome::Failed(None);
+    }
+
+    let mut source = match std::fs::OpenOptions::new().read(true).open(from.as_ref()) {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!(
+                "winstall: cannot open file to read {}: {}",
+                quoting::quote(from.as_ref()),
+                errors::describe(&e, from.as_ref())
+            );
+
+            stats.error(&e);
+            return Outcome::Failed(Some(e.kind()));
+        }
+    };
+
+    let timestamps = preserved_times(&source, from.as_ref(), install);
+
+    let mut backup_path = None::<std::path::PathBuf>;
+
+    // An existing destination is never touched before its replacement is complete. The new
+    // content is written to a temporary file beside it (or in the --tmp-dir directory), and only
+    // then is the old file moved to its backup (or discarded) and the temporary file renamed into
+    // place. With --backup-mode=copy the backup is copied instead, and the destination overwritten
+    // in place from the temporary file so that it keeps its identity. At no point is the
+    // destination missing both its old content and its backup.
+    let mut readonly = ReadonlyGuard(None);
+
+    let created = with_grace(install, stats, false, || {
+        destination_options(install)
+            .create_new(true)
+            .open(to.as_ref())
+    });
+
+    let (mut dest, staging) = match created {
+        Ok(f) => (f, None),
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::AlreadyExists {
+                eprintln!(
+                    "winstall: cannot open file to write {}: {}",
+                    quoting::quote(to.as_ref()),
+                    errors::describe(&e, to.as_ref())
+                );
+
+                stats.error(&e);
+                return Outcome::Failed(Some(e.kind()));
+            }
+
+            match may_replace(to.as_ref(), install, stats) {
+                Ok(guard) => readonly = guard,
+                Err(outcome) => return outcome,
+            }
+
+            // The name is only ever used by this process, so a file already there was left by an
+            // earlier run that was killed before it could remove it, and whose process ID has
+            // since been reused.
+            let temporary = staging_path(to.as_ref(), install);
+            _ = std::fs::remove_file(&temporary);
+
+            let opened = with_grace(install, stats, false, || {
+                destination_options(install)
+                    .create_new(true)
+                    .open(&temporary)
+            });
+
+            match opened {
+                Ok(f) => (f, Some(temporary)),
+                Err(e) => {
+                    eprintln!(
+                        "winstall: cannot open file to write {}: {}",
+                        quoting::quote(to.as_ref()),
+                        errors::describe(&e, to.as_ref())
+                    );
+
+                    stats.error(&e);
+                    return Outcome::Failed(Some(e.kind()));
+                }
+            }
+        }
+    };
+
+    // FAT32 can't hold a file of 4 GiB or more, and would otherwise only say so once the copy
+    // reaches that point. Extending the file to its final size up front fails straight away.
+    let length = source.metadata().map(|m| m.len()).unwrap_or(0);
+    if length > u32::MAX as u64 {
+        if let Err(e) = dest.set_len(length) {
+            match e.kind() {
+                std::io::ErrorKind::FileTooLarge => eprintln!(
+                    "winstall: cannot install {}: file too large for destination filesystem",
+                    quoting::quote(to.as_ref())
+                ),
+                _ => eprintln!(
+                    "winstall: cannot allocate {}: {}",
+                    quoting::quote(to.as_ref()),
+                    e
+                ),
+            }
+
+            drop(dest);
+            match staging {
+                Some(temporary) => _ = std::fs::remove_file(temporary),
+                None => _ = std::fs::remove_file(to.as_ref()),
+            }
+
+            stats.error(&e);
+            return Outcome::Failed(Some(e.kind()));
+        }
+    }
+
+    let copied = match (install.transform.is_identity(), install.buffer_size) {
+        (false, size) => {
+            let size = size.unwrap_or(64 * 1024);
+
+            transform::copy(&mut source, &mut dest, &install.transform, size).and_then(|n| {
+                // The file may have been extended to the length of the source, which the
+                // rewritten content no longer fills.
+                if length > u32::MAX as u64 {
+                    dest.set_len(n)?;
+                }
+
+                Ok(n)
+            })
+        }
+        (true, Some(size)) => std::io::copy(
+            &mut std::io::BufReader::with_capacity(size, source),
+            &mut dest,
+        ),
+        (true, None) => std::io::copy(&mut source, &mut dest),
+    };
+
+    let bytes = match copied {
+        Ok(n) => n,
+        Err(e) => {
+            eprintln!("winstall: cannot copy file: {}", e);
+
+            drop(dest);
+            match staging {
+                Some(temporary) => _ = std::fs::remove_file(temporary),
+                None => _ = std::fs::remove_file(to.as_ref()),
+            }
+
+            stats.error(&e);
+            return Outcome::Failed(Some(e.kind()));
+        }
+    };
+
+    // Elsewhere there is no equivalent of FILE_FLAG_WRITE_THROUGH that std can ask for, so the
+    // data is flushed to the device once it has all been written instead.
+    if install.write_through && !cfg!(windows) {
+        if let Err(e) = dest.sync_all() {
+            eprintln!(
+                "winstall: cannot flush {}: {}",
+                quoting::quote(to.as_ref()),
+                errors::describe(&e, to.as_ref())
+            );
+
+            if let Some(temporary) = staging {
+                drop(dest);
+                _ = std::fs::remove_file(temporary);
+            }
+
+            stats.error(&e);
+            return Outcome::Failed(Some(e.kind()));
+        }
+    }
+
+    // The owner is changed and the mode set before the file is put in place, so that it never has
+    // the wrong ones at its destination.
+    #[cfg(unix)]
+    {
+        let path = staging.as_deref().unwrap_or(to.as_ref());
+
+        if let Err(e) = apply_attributes(path, &mut dest, install) {
+            eprintln!(
+                "winstall: cannot set attributes of {}: {}",
+                quoting::quote(to.as_ref()),
+                e
+            );
+
+            drop(dest);
+            match staging {
+                Some(temporary) => _ = std::fs::remove_file(temporary),
+                None => _ = std::fs::remove_file(to.as_ref()),
+            }
+
+            stats.error(&e);
+            return Outcome::Failed(Some(e.kind()));
+        }
+    }
+
+    let times_set = timestamps.filter(|t| match dest.set_times(t.file_times()) {
+        Ok(_) => true,
+        Err(e) => {
+            errors::warn(
+                stats,
+                &format!(
+                    "unable to set file times for {}: {}",
+                    quoting::quote(to.as_ref()),
+                    e
+                ),
+            );
+
+            false
+        }
+    });
+
+    drop(dest);
+
+    if let Some(t) = times_set {
+        let shortfall = t.verify(staging.as_deref().unwrap_or(to.as_ref()));
+        warn_imprecise(stats, shortfall, to.as_ref());
+    }
+
+    if let Some(temporary) = staging {
+        crash_point("staged");
+
+        match &install.backup_method {
+            Some(b) => match backup::make(to.as_ref(), b, install.backup_mode) {
+                Ok(name) => backup_path = Some(name),
+                Err((name, e)) => {
+                    eprintln!(
+                        "winstall: unable preserve {} as backup {}: {}",
+                        quoting::quote(to.as_ref()),
+                        quoting::quote(name),
+                        e
+                    );
+
+                    _ = std::fs::remove_file(temporary);
+                    stats.error(&e);
+                    return Outcome::Failed(Some(e.kind()));
+                }
+            },
+            None => {
+                if let Some(router) = install.verbose {
+                    router.send(messages::removed(to.as_ref()));
+                }
+            }
+        }
+
+        crash_point("backed up");
+
+        let in_place = backup_path.is_some() && matches!(install.backup_mode, backup::Mode::Copy);
+        let replaced = match in_place {
+            true => overwrite(&temporary, to.as_ref(), install, stats).map(|dest| {
+                if let Some(t) = times_set {
+                    if let Err(e) = dest.set_times(t.file_times()) {
+                        errors::warn(
+                            stats,
+                            &format!(
+                                "unable to set file times for {}: {}",
+                                quoting::quote(to.as_ref()),
+                                e
+                            ),
+                        );
+                    }
+                }
+            }),
+            false => with_grace(install, stats, true, || {
+                std::fs::rename(&temporary, to.as_ref())
+            }),
+        };
+
+        if let Err(e) = replaced {
+            eprintln!(
+                "winstall: cannot replace {}: {}",
+                quoting::quote(to.as_ref()),
+                errors::describe(&e, to.as_ref())
+            );
+
+            // Put the original back where it was, so a failed install leaves things as they were.
+            // A copy is left where it is, as the destination may have been partly overwritten.
+            if let (Some(path), false) = (&backup_path, in_place) {
+                _ = std::fs::rename(path, to.as_ref());
+            }
+
+            _ = std::fs::remove_file(temporary);
+            stats.error(&e);
+            return Outcome::Failed(Some(e.kind()));
+        }
+
+        if in_place {
+            _ = std::fs::remove_file(temporary);
+        }
+    }
+
+    stats.files += 1;
+    stats.bytes += bytes;
+
+    if let Some(path) = &backup_path {
+        stats.backups += 1;
+
+        if readonly.0.is_some() {
+            _ = set_readonly(path, true);
+        }
+    }
+
+    if let Some(router) = install.verbose {
+        router.send(messages::installed(
+            from.as_ref(),
+            to.as_ref(),
+            backup_path.as_deref(),
+        ));
+    }
+
+    Outcome::Installed {
+        backup: backup_path,
+        bytes,
+    }
+}
+
+// Overwrites to in place with the staged replacement, for --backup-mode=copy, so that anything
+// attached to the destination itself is kept. The owner and mode are applied again, as they were to
+// the staged file, and the file is returned for its times to be set.
+fn overwrite(
+    staged: &std::path::Path,
+    to: &std::path::Path,
+    install: &Install,
+    stats: &mut stats::Stats,
+) -> std::io::Result<std::fs::File> {
+    let mut source = std::fs::File::open(staged)?;
+    let mut dest = with_grace(install, stats, false, || {
+        destination_options(install).truncate(true).open(to)
+    })?;
+
+    crash_point("truncated");
+    std::io::copy(&mut source, &mut dest)?;
+
+    if install.write_through && !cfg!(windows) {
+        dest.sync_all()?;
+    }
+
+    #[cfg(unix)]
+    unix::apply(to, &install.attributes, false)?;
+
+    Ok(dest)
+}
+
+// Where a test can stop an install dead, as if the process had been killed at that point. Nothing
+// after it runs, so what is left on disk is exactly what such a crash would leave.
+#[cfg(not(test))]
+fn crash_point(_: &str) {}
+
+#[cfg(test)]
+fn crash_point(point: &str) {
+    if tests::CRASH_AT.with(|at| at.get() == Some(point)) {
+        panic!("crashed at {}", point);
+    }
+}
+
+// The times to give a copy of source, if they are to be preserved.
+fn preserved_times(
+    source: &std::fs::File,
+    from: &std::path::Path,
+    install: &Install,
+) -> Option<timestamps::Timestamps> {
+    if !install.preserve_timestamps {
+        return None;
+    }
+
+    let metadata = source.metadata().ok()?;
+    let times = timestamps::Timestamps::read(&metadata, from)?;
+
+    match install.preserve_creation_time {
+        true => Some(times.with_created(&metadata, from)),
+        false => Some(times),
+    }
+}
+
+// Strips the file written to path, if asked to, then gives it its owner and mode. Stripping
+// replaces the file, so dest is opened again for its times to be set.
+#[cfg(unix)]
+fn apply_attributes(
+    path: &std::path::Path,
+    dest: &mut std::fs::File,
+    install: &Install,
+) -> std::io::Result<()> {
+    unix::strip(path, &install.attributes)?;
+
+    if install.attributes.strip.is_some() {
+        *dest = std::fs::OpenOptions::new().write(true).open(path)?;
+    }
+
+    unix::apply(path, &install.attributes, false)
+}
+
+fn warn_imprecise<P: AsRef<std::path::Path>>(
+    stats: &mut stats::Stats,
+    shortfall: Option<std::time::Duration>,
+    p: P,
+) {
+    if let Some(difference) = shortfall {
+        errors::warn(
+            stats,
+            &format!(
+                concat!(
+                    "modification time of {} could only be preserved to within {:?} by the ",
+                    "destination file system",
+                ),
+                quoting::quote(p.as_ref()),
+                difference
+            ),
+        );
+    }
+}
+
+// A destination that holds the start of the source is what an interrupted copy leaves behind, so
+// only the remainder is copied onto the end of it. It is still an existing destination, so it is
+// only appended to where it could have been replaced, and with a backup a copy of the partial file
+// is kept first. Returns None when there is nothing to resume, or it can't be, and a full install
+// should be made instead.
+fn resume_file<F: AsRef<std::path::Path>, T: AsRef<std::path::Path>>(
+    from: F,
+    to: T,
+    install: &Install,
+    stats: &mut stats::Stats,
+) -> Option<Outcome> {
+    use std::io::Seek;
+
+    let offset = compare::is_prefix(to.as_ref(), from.as_ref()).ok()??;
+
+    let _readonly = match may_replace(to.as_ref(), install, stats) {
+        Ok(guard) => guard,
+        Err(outcome) => return Some(outcome),
+    };
+
+    let mut source = std::fs::File::open(from.as_ref()).ok()?;
+    let mut dest = destination_options(install).open(to.as_ref()).ok()?;
+
+    // The backup is a copy, as the partial file itself is what is appended to.
+    let backup_path = match &install.backup_method {
+        None => None,
+        Some(b) => match backup::make(to.as_ref(), b, backup::Mode::Copy) {
+            Ok(name) => Some(name),
+            Err((name, e)) => {
+                eprintln!(
+                    "winstall: unable preserve {} as backup {}: {}",
+                    quoting::quote(to.as_ref()),
+                    quoting::quote(name),
+                    e
+                );
+
+                stats.error(&e);
+                return Some(Outcome::Failed(Some(e.kind())));
+            }
+        },
+    };
+
+    let copied = source
+        .seek(std::io::SeekFrom::Start(offset))
+        .and_then(|_| dest.seek(std::io::SeekFrom::Start(offset)))
+        .and_then(|_| std::io::copy(&mut source, &mut dest));
+
+    let bytes = match copied {
+        Ok(n) => n,
+        Err(e) => {
+            eprintln!(
+                "winstall: cannot resume copying to {}: {}",
+                quoting::quote(to.as_ref()),
+                errors::describe(&e, to.as_ref())
+            );
+
+            stats.error(&e);
+            return Some(Outcome::Failed(Some(e.kind())));
+        }
+    };
+
+    // The file is finished off as one installed in full would be, apart from being put in place.
+    #[cfg(unix)]
+    if let Err(e) = apply_attributes(to.as_ref(), &mut dest, install) {
+        eprintln!(
+            "winstall: cannot set attributes of {}: {}",
+            quoting::quote(to.as_ref()),
+            e
+        );
+
+        stats.error(&e);
+        return Some(Outcome::Failed(Some(e.kind())));
+    }
+
+    if let Some(t) = preserved_times(&source, from.as_ref(), install) {
+        match dest.set_times(t.file_times()) {
+            Ok(_) => {
+                drop(dest);
+                warn_imprecise(stats, t.verify(to.as_ref()), to.as_ref());
+            }
+            Err(e) => errors::warn(
+                stats,
+                &format!(
+                    "unable to set file times for {}: {}",
+                    quoting::quote(to.as_ref()),
+                    e
+                ),
+            ),
+        }
+    }
+
+    stats.files += 1;
+    stats.bytes += bytes;
+
+    if backup_path.is_some() {
+        stats.backups += 1;
+    }
+
+    if let Some(router) = install.verbose {
+        router.send(messages::resumed(
+            from.as_ref(),
+            to.as_ref(),
+            offset,
+            backup_path.as_deref(),
+        ));
+    }
+
+    Some(Outcome::Installed {
+        backup: backup_path,
+        bytes,
+    })
+}
+
+// With --touch-only the content of an existing destination is left as it is and only its times are
+// set, for build systems that decide what is out of date by them.
+fn touch_file<F: AsRef<std::path::Path>, T: AsRef<std::path::Path>>(
+    from: F,
+    to: T,
+    touch: Touch,
+    install: &Install,
+    stats: &mut stats::Stats,
+) -> Outcome {
+    let times = match touch {
+        Touch::Source => std::fs::metadata(from.as_ref())
+            .map(|m| timestamps::Timestamps::read(&m, from.as_ref())),
+        Touch::Now => Ok(Some(timestamps::Timestamps::now())),
+    };
+
+    let times = match times {
+        Ok(Some(times)) => times,
+        Ok(None) => return Outcome::Failed(None),
+        Err(e) => {
+            eprintln!(
+                "winstall: cannot stat {}: {}",
+                quoting::quote(from.as_ref()),
+                e
+            );
+
+            stats.error(&e);
+            return Outcome::Failed(Some(e.kind()));
+        }
+    };
+
+    let touched = std::fs::OpenOptions::new()
+        .write(true)
+        .open(to.as_ref())
+        .and_then(|f| f.set_times(times.file_times()));
+
+    if let Err(e) = touched {
+        eprintln!(
+            "winstall: cannot touch {}: {}",
+            quoting::quote(to.as_ref()),
+            errors::describe(&e, to.as_ref())
+        );
+
+        stats.error(&e);
+        return Outcome::Failed(Some(e.kind()));
+    }
+
+    warn_imprecise(stats, times.verify(to.as_ref()), to.as_ref());
+    stats.files += 1;
+
+    if let Some(router) = install.verbose {
+        router.send(messages::touched(from.as_ref(), to.as_ref()));
+    }
+
+    Outcome::Installed {
+        backup: None,
+        bytes: 0,
+    }
+}
+
+// Flushes the content of an installed file to the device. Windows only flushes a file that is
+// open for writing.
+fn sync_file<P: AsRef<std::path::Path>>(p: P) -> std::io::Result<()> {
+    std::fs::OpenOptions::new()
+        .write(cfg!(windows))
+        .read(!cfg!(windows))
+        .open(p.as_ref())?
+        .sync_all()
+}
+
+// Flushes the directory containing p, so that the entry for a file that was created or renamed
+// into it survives a crash. NTFS journals its directories and can't flush them through std, so
+// this does nothing on Windows.
+fn sync_directory<P: AsRef<std::path::Path>>(p: P) -> std::io::Result<()> {
+    if cfg!(windows) {
+        return Ok(());
+    }
+
+    let parent = match p.as_ref().parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => std::path::Path::new("."),
+    };
+
+    std::fs::File::open(parent)?.sync_all()
+}
+
+// Antivirus scanners on Windows open a file as soon as it is created, and for a moment anything
+// else that opens or renames it is refused. With --av-grace-ms those refusals are retried, waiting
+// twice as long each time, until the grace period has been used up. Elsewhere nothing is retried,
+// as the same errors there don't pass. Access being denied is only taken as such a refusal for a
+// file this run has just created, since anywhere else it is far more likely to be lasting.
+fn with_grace<T>(
+    install: &Install,
+    stats: &mut stats::Stats,
+    just_created: bool,
+    mut operation: impl FnMut() -> std::io::Result<T>,
+) -> std::io::Result<T> {
+    let mut waited = std::time::Duration::ZERO;
+    let mut delay = std::time::Duration::from_millis(10);
+
+    loop {
+        match operation() {
+            Err(e) if is_transient(&e, just_created) && waited < install.av_grace => {
+                let wait = delay.min(install.av_grace - waited);
+                std::thread::sleep(wait);
+
+                waited += wait;
+                delay *= 2;
+                stats.retries += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION, and ERROR_ACCESS_DENIED for a file that was
+// just created.
+fn is_transient(e: &std::io::Error, just_created: bool) -> bool {
+    match e.raw_os_error() {
+        Some(32) | Some(33) => cfg!(windows),
+        Some(5) => cfg!(windows) && just_created,
+        _ => false,
+    }
+}
+
+fn destination_options(install: &Install) -> std::fs::OpenOptions {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true);
+
+    // FILE_FLAG_WRITE_THROUGH, so that each write reaches the device (typically removable media)
+    // before it is reported as done.
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+
+        if install.write_through {
+            options.custom_flags(0x8000_0000);
+        }
+    }
+
+    #[cfg(not(windows))]
+    let _ = install;
+
+    options
+}
+
+// Links are made under a temporary name beside the destination first, so that nothing is backed
+// up or removed until it is known the link can be made (and a copy can still be made instead).
+// Returns None when the link could not be made but falling back to a copy is allowed.
+fn link_file<F: AsRef<std::path::Path>, T: AsRef<std::path::Path>>(
+    from: F,
+    to: T,
+    link: Link,
+    install: &Install,
+    stats: &mut stats::Stats,
+) -> Option<Outcome> {
+    let temporary = backup::add_suffix(
+        to.as_ref(),
+        &format!(".winstall-link-{}", std::process::id()),
+    );
+
+    let kind = match link {
+        Link::Hard | Link::HardOrFail => "hard link",
+        Link::Symbolic { .. } => "symbolic link",
+    };
+
+    let linked = match link {
+        Link::Hard | Link::HardOrFail => std::fs::hard_link(from.as_ref(), &temporary),
+        Link::Symbolic { relative } => symlink(from.as_ref(), &temporary, relative),
+    };
+
+    if let Err(e) = linked {
+        let can_fall_back = matches!(
+            e.kind(),
+            std::io::ErrorKind::CrossesDevices | std::io::ErrorKind::Unsupported
+        );
+
+        if can_fall_back && matches!(link, Link::Hard) {
+            return None;
+        }
+
+        // ERROR_PRIVILEGE_NOT_HELD
+        if cfg!(windows) && e.raw_os_error() == Some(1314) {
+            eprintln!(
+                concat!(
+                    "winstall: cannot create symbolic link {} to {}: the process does not ",
+                    "hold SeCreateSymbolicLinkPrivilege (run elevated or enable Developer Mode)",
+                ),
+                quoting::quote(to.as_ref()),
+                quoting::quote(from.as_ref()),
+            );
+        } else {
+            eprintln!(
+                "winstall: cannot create {} {} to {}: {}",
+                kind,
+                quoting::quote(to.as_ref()),
+                quoting::quote(from.as_ref()),
+                e
+            );
+        }
+
+        stats.error(&e);
+        return Some(Outcome::Failed(Some(e.kind())));
+    }
+
+    let mut backup_path = None::<std::path::PathBuf>;
+    let mut readonly = ReadonlyGuard(None);
+
+    if std::fs::symlink_metadata(to.as_ref()).is_ok() {
+        match may_replace(to.as_ref(), install, stats) {
+            Ok(guard) => readonly = guard,
+            Err(outcome) => {
+                _ = std::fs::remove_file(&temporary);
+                return Some(outcome);
+            }
+        }
+
+        match &install.backup_method {
+            None => {
+                if let Some(router) = install.verbose {
+                    router.send(messages::removed(to.as_ref()));
+                }
+            }
+            Some(b) => match backup::make(to.as_ref(), b, install.backup_mode) {
+                Ok(name) => backup_path = Some(name),
+                Err((name, e)) => {
+                    eprintln!(
+                        "winstall: unable preserve {} as backup {}: {}",
+                        quoting::quote(to.as_ref()),
+                        quoting::quote(name),
+                        e
+                    );
+
+                    _ = std::fs::remove_file(&temporary);
+                    stats.error(&e);
+                    return Some(Outcome::Failed(Some(e.kind())));
+                }
+            },
+        }
+    }
+
+    if let Err(e) = std::fs::rename(&temporary, to.as_ref()) {
+        eprintln!(
+            "winstall: cannot create {} {} to {}: {}",
+            kind,
+            quoting::quote(to.as_ref()),
+            quoting::quote(from.as_ref()),
+            e
+        );
+
+        _ = std::fs::remove_file(&temporary);
+        stats.error(&e);
+        return Some(Outcome::Failed(Some(e.kind())));
+    }
+
+    stats.files += 1;
+
+    if let Some(path) = &backup_path {
+        stats.backups += 1;
+
+        if readonly.0.is_some() {
+            _ = set_readonly(path, true);
+        }
+    }
+
+    if let Some(router) = install.verbose {
+        router.send(messages::linked(
+            from.as_ref(),
+            to.as_ref(),
+            backup_path.as_deref(),
+        ));
+    }
+
+    Some(Outcome::Installed {
+        backup: backup_path,
+        bytes: 0,
+    })
+}
+
+fn symlink<F: AsRef<std::path::Path>, T: AsRef<std::path::Path>>(
+    from: F,
+    to: T,
+    relative: bool,
+) -> std::io::Result<()> {
+    let absolute = std::fs::canonicalize(from.as_ref())?;
+
+    let target = match relative {
+        true => {
+            let parent = to
+                .as_ref()
+                .parent()
+                .filter(|p| *p != std::path::Path::new(""))
+                .unwrap_or(std::path::Path::new("."));
+
+            relative_path(std::fs::canonicalize(parent)?, &absolute)
+        }
+        false => absolute.clone(),
+    };
+
+    #[cfg(windows)]
+    return match absolute.is_dir() {
+        true => std::os::windows::fs::symlink_dir(target, to.as_ref()),
+        false => std::os::windows::fs::symlink_file(target, to.as_ref()),
+    };
+
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(target, to.as_ref());
+
+    #[cfg(not(any(windows, unix)))]
+    return Err(std::io::Error::from(std::io::ErrorKind::Unsupported));
+}
+
+// Both paths are expected to be absolute and canonical. Paths on different Windows drives have no
+// relative form, so the target is returned unchanged.
+fn relative_path<B: AsRef<std::path::Path>, P: AsRef<std::path::Path>>(
+    base: B,
+    p: P,
+) -> std::path::PathBuf {
+    let base = base.as_ref().components().collect::<Vec<_>>();
+    let target = p.as_ref().components().collect::<Vec<_>>();
+
+    if base.first() != target.first() {
+        return p.as_ref().to_path_buf();
+    }
+
+    let common = base
+        .iter()
+        .zip(target.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut relative = std::path::PathBuf::new();
+
+    for _ in common..base.len() {
+        relative.push("..");
+    }
+
+    for component in &target[common..] {
+        relative.push(component);
+    }
+
+    relative
+}
+
+fn matches_expectation<P: AsRef<std::path::Path>>(p: P, install: &Install) -> bool {
+    if install.expectations.is_empty() {
+        return true;
+    }
+
+    let canonical = std::fs::canonicalize(p.as_ref()).ok();
+
+    let expected = install.expectations.iter().find(|(path, _)| {
+        path == p.as_ref()
+            || canonical
+                .as_ref()
+                .is_some_and(|canonical| path == canonical)
+    });
+
+    let Some((_, expected)) = expected else {
+        return true;
+    };
+
+    match sha256::file(p.as_ref()) {
+        Ok(actual) if &actual == expected => true,
+        Ok(actual) => {
+            eprintln!(
+                "winstall: conflict: {} has SHA-256 {} but {} was expected, not overwriting",
+                quoting::quote(p.as_ref()),
+                actual,
+                expected
+            );
+
+            false
+        }
+        Err(e) => {
+            eprintln!(
+                "winstall: conflict: cannot verify expected content of {}: {}",
+                quoting::quote(p.as_ref()),
+                e
+            );
+
+            false
+        }
+    }
+}
+
+// App execution aliases (like the python.exe in WindowsApps) are empty reparse points which
+// aren't symbolic links. Reading the reparse tag needs DeviceIoControl, which std doesn't expose,
+// so this relies on that combination of properties instead.
+#[cfg(windows)]
+fn is_app_execution_alias<P: AsRef<std::path::Path>>(p: P) -> bool {
+    use std::os::windows::fs::MetadataExt;
+
+    const FILE_ATTRIBUTE_REPARSE_POINT: u32 = 0x0400;
+
+    std::fs::symlink_metadata(p.as_ref()).is_ok_and(|m| {
+        m.file_attributes() & FILE_ATTRIBUTE_REPARSE_POINT != 0
+            && !m.file_type().is_symlink()
+            && !m.is_dir()
+            && m.len() == 0
+    })
+}
+
+#[cfg(not(windows))]
+fn is_app_execution_alias<P: AsRef<std::path::Path>>(_: P) -> bool {
+    false
+}
+
+// Restores the read-only attribute to whatever file is at the path when dropped, which is the
+// newly installed file on success or the original one on failure.
+struct ReadonlyGuard(Option<std::path::PathBuf>);
+
+impl Drop for ReadonlyGuard {
+    fn drop(&mut self) {
+        if let Some(path) = &self.0 {
+            _ = set_readonly(path, true);
+        }
+    }
+}
+
+pub(crate) fn set_readonly<P: AsRef<std::path::Path>>(p: P, readonly: bool) -> std::io::Result<()> {
+    let mut permissions = std::fs::metadata(p.as_ref())?.permissions();
+
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(readonly);
+
+    std::fs::set_permissions(p.as_ref(), permissions)
+}
+
+// Windows refuses to replace or overwrite a file with the read-only attribute. With --force the
+// attribute is cleared for the duration of the install, otherwise it is reported as an error.
+// Unix permissions don't prevent the file being replaced, so there is nothing to do there.
+fn clear_readonly<P: AsRef<std::path::Path>>(p: P, force: bool) -> Result<ReadonlyGuard, ()> {
+    let readonly =
+        cfg!(windows) && std::fs::metadata(p.as_ref()).is_ok_and(|m| m.permissions().readonly());
+
+    if !readonly {
+        return Ok(ReadonlyGuard(None));
+    }
+
+    if !force {
+        eprintln!(
+            "winstall: cannot overwrite {}: destination is read-only (use --force)",
+            quoting::quote(p.as_ref())
+        );
+
+        return Err(());
+    }
+
+    match set_readonly(p.as_ref(), false) {
+        Ok(_) => Ok(ReadonlyGuard(Some(p.as_ref().to_path_buf()))),
+        Err(e) => {
+            eprintln!(
+                "winstall: cannot clear read-only attribute of {}: {}",
+                quoting::quote(p.as_ref()),
+                e
+            );
+
+            Err(())
+        }
+    }
+}
+
+// Whether an existing destination may be replaced: -n leaves it alone, -i asks first, and one that
+// is read-only needs --force. The guard restores the read-only attribute once it has been replaced.
+fn may_replace(
+    to: &std::path::Path,
+    install: &Install,
+    stats: &mut stats::Stats,
+) -> Result<ReadonlyGuard, Outcome> {
+    if install.no_clobber {
+        eprintln!("winstall: not replacing {}", quoting::quote(to));
+        stats.skipped += 1;
+        return Err(Outcome::Skipped);
+    }
+
+    if install.interactive && !(install.confirm)(to) {
+        stats.skipped += 1;
+        return Err(Outcome::Skipped);
+    }
+
+    clear_readonly(to, install.force).map_err(|_| Outcome::Failed(None))
+}
+
+fn confirm_overwrite(p: &std::path::Path) -> bool {
+    eprint!("winstall: overwrite {}? ", quoting::quote(p));
+
+    let mut answer = String::new();
+    match std::io::stdin().read_line(&mut answer) {
+        Ok(_) => answer.trim_start().starts_with(['y', 'Y']),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scratch::{Element, Entry, Scratch};
+
+    thread_local! {
+        // The point at which crash_point stops an install made on this thread.
+        pub(super) static CRASH_AT: std::cell::Cell<Option<&'static str>> =
+            const { std::cell::Cell::new(None) };
+    }
+
+    // An interactive install that answers every prompt with answer, along with the destinations
+    // it was asked about.
+    fn interactive(
+        answer: bool,
+    ) -> (
+        crate::Install,
+        std::rc::Rc<std::cell::RefCell<Vec<std::path::PathBuf>>>,
+    ) {
+        let asked = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut install = crate::Install::new(None, crate::backup::Mode::Rename, false);
+
+        let record = asked.clone();
+        install.interactive = true;
+        install.confirm = Box::new(move |p| {
+            record.borrow_mut().push(p.to_path_buf());
+            answer
+        });
+
+        (install, asked)
+    }
+
+    fn install(scratch: &Scratch, install: &crate::Install) -> bool {
+        let mut stats = crate::stats::Stats::default();
+        let results = crate::file_target(
+            scratch.join("source"),
+            scratch.join("dest"),
+            install,
+            &mut stats,
+        );
+
+        matches!(results[0].outcome, crate::Outcome::Installed { .. })
+    }
+
+    #[test]
+    fn declining_the_prompt_leaves_the_destination_alone() {
+        let scratch = Scratch::unique("declined");
+        scratch.declare(&[
+            ("source", Element::File("new")),
+            ("dest", Element::File("old")),
+        ]);
+
+        let (declining, asked) = interactive(false);
+        assert!(!install(&scratch, &declining));
+
+        assert_eq!(*asked.borrow(), vec![scratch.join("dest")]);
+        assert_eq!(scratch.tree()["dest"], Entry::file("old"));
+    }
+
+    #[test]
+    fn accepting_the_prompt_replaces_the_destination() {
+        let scratch = Scratch::unique("accepted");
+        scratch.declare(&[
+            ("source", Element::File("new")),
+            ("dest", Element::File("old")),
+        ]);
+
+        let (accepting, asked) = interactive(true);
+        assert!(install(&scratch, &accepting));
+
+        assert_eq!(asked.borrow().len(), 1);
+        assert_eq!(scratch.tree()["dest"], Entry::file("new"));
+    }
+
+    #[test]
+    fn nothing_is_asked_when_there_is_nothing_to_replace() {
+        let scratch = Scratch::unique("unasked");
+        scratch.declare(&[("source", Element::File("new"))]);
+
+        let (declining, asked) = interactive(false);
+        assert!(install(&scratch, &declining));
+
+        assert!(asked.borrow().is_empty());
+        assert_eq!(scratch.tree()["dest"], Entry::file("new"));
+    }
+
+    #[test]
+    fn links_are_only_made_over_a_destination_once_accepted() {
+        let scratch = Scratch::unique("linked");
+        scratch.declare(&[
+            ("source", Element::File("new")),
+            ("dest", Element::File("old")),
+        ]);
+
+        let (mut declining, asked) = interactive(false);
+        declining.link = Some(crate::Link::Hard);
+        assert!(!install(&scratch, &declining));
+        assert_eq!(asked.borrow().len(), 1);
+        assert_eq!(scratch.tree()["dest"], Entry::file("old"));
+
+        let (mut accepting, _) = interactive(true);
+        accepting.link = Some(crate::Link::Hard);
+        assert!(install(&scratch, &accepting));
+        assert_eq!(scratch.tree()["dest"], Entry::file("new"));
+    }
+
+    // Replaces dest, which holds "old", with a backup made in mode, stopping dead at point. Returns
+    // what is left behind.
+    fn crash(
+        scratch: &Scratch,
+        mode: crate::backup::Mode,
+        point: &'static str,
+    ) -> std::collections::BTreeMap<String, Entry> {
+        let backup = Some(crate::Backup::Simple("~".to_string()));
+        let install = crate::Install::new(backup, mode, false);
+
+        CRASH_AT.set(Some(point));
+        let crashed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            self::install(scratch, &install)
+        }));
+        CRASH_AT.set(None);
+
+        assert!(crashed.is_err(), "the install didn't reach {}", point);
+        scratch.tree()
+    }
+
+    fn staged() -> String {
+        format!("dest.winstall-{}", std::process::id())
+    }
+
+    // Wherever an install stops, the old content is either still at the destination or in its
+    // backup. An install run again afterwards, by a process that happens to have the same ID,
+    // stages its replacement over the file left behind rather than failing.
+    #[test]
+    fn a_crash_never_loses_the_old_content() {
+        use crate::backup::Mode;
+
+        let expected = [
+            (Mode::Rename, "staged", Some("old"), None),
+            (Mode::Rename, "backed up", None, Some("old")),
+            (Mode::Copy, "staged", Some("old"), None),
+            (Mode::Copy, "backed up", Some("old"), Some("old")),
+            (Mode::Copy, "truncated", Some(""), Some("old")),
+        ];
+
+        for (mode, point, dest, backup) in expected {
+            let scratch = Scratch::unique("crash");
+            scratch.declare(&[
+                ("source", Element::File("new")),
+                ("dest", Element::File("old")),
+            ]);
+
+            let tree = crash(&scratch, mode, point);
+            assert_eq!(
+                tree.get("dest"),
+                dest.map(Entry::file).as_ref(),
+                "{}",
+                point
+            );
+            assert_eq!(
+                tree.get("dest~"),
+                backup.map(Entry::file).as_ref(),
+                "{}",
+                point
+            );
+            assert_eq!(tree[&staged()], Entry::file("new"), "{}", point);
+
+            let install = crate::Install::new(None, mode, false);
+            assert!(self::install(&scratch, &install), "{}", point);
+
+            // Without a destination nothing is staged, and so nothing is replaced.
+            let tree = scratch.tree();
+            assert_eq!(tree["dest"], Entry::file("new"), "{}", point);
+            assert_eq!(!tree.contains_key(&staged()), dest.is_some(), "{}", point);
+        }
+    }
+
+    // With --backup-mode=copy the destination is overwritten in place, so it is still the same
+    // file afterwards.
+    #[cfg(unix)]
+    #[test]
+    fn copy_backups_keep_the_destination_itself() {
+        use std::os::unix::fs::MetadataExt;
+
+        let scratch = Scratch::unique("copy-in-place");
+        scratch.declare(&[
+            ("source", Element::File("new")),
+            ("dest", Element::File("old")),
+        ]);
+
+        let before = std::fs::metadata(scratch.join("dest")).unwrap().ino();
+
+        let backup = Some(crate::Backup::Simple("~".to_string()));
+        let install = crate::Install::new(backup, crate::backup::Mode::Copy, false);
+        assert!(self::install(&scratch, &install));
+
+        assert_eq!(
+            std::fs::metadata(scratch.join("dest")).unwrap().ino(),
+            before
+        );
+        assert_eq!(
+            scratch.tree().into_iter().collect::<Vec<_>>(),
+            [
+                ("dest".to_string(), Entry::file("new")),
+                ("dest~".to_string(), Entry::file("old")),
+                ("source".to_string(), Entry::file("new")),
+            ]
+        );
+    }
+
+    // An install that resumes, with a partial copy of the source as the destination.
+    fn resuming(scratch: &Scratch, backup_method: Option<crate::Backup>) -> crate::Install {
+        scratch.declare(&[
+            ("source", Element::File("content")),
+            ("dest", Element::File("con")),
+        ]);
+
+        let mut install = crate::Install::new(backup_method, crate::backup::Mode::Rename, false);
+        install.resume = true;
+        install
+    }
+
+    #[test]
+    fn resumes_a_partial_copy() {
+        let scratch = Scratch::unique("resume");
+        let install = resuming(&scratch, None);
+
+        assert!(self::install(&scratch, &install));
+        assert_eq!(scratch.tree()["dest"], Entry::file("content"));
+    }
+
+    #[test]
+    fn resuming_leaves_the_destination_alone_with_no_clobber() {
+        let scratch = Scratch::unique("resume-no-clobber");
+        let mut install = resuming(&scratch, None);
+        install.no_clobber = true;
+
+        assert!(!self::install(&scratch, &install));
+        assert_eq!(scratch.tree()["dest"], Entry::file("con"));
+    }
+
+    #[test]
+    fn resuming_asks_first_when_interactive() {
+        for (answer, expected) in [(false, "con"), (true, "content")] {
+            let scratch = Scratch::unique("resume-interactive");
+            let (mut install, asked) = interactive(answer);
+            install.resume = true;
+
+            scratch.declare(&[
+                ("source", Element::File("content")),
+                ("dest", Element::File("con")),
+            ]);
+
+            assert_eq!(self::install(&scratch, &install), answer);
+            assert_eq!(asked.borrow().len(), 1);
+            assert_eq!(scratch.tree()["dest"], Entry::file(expected));
+        }
+    }
+
+    #[test]
+    fn resuming_keeps_a_copy_of_the_partial_file_as_its_backup() {
+        let scratch = Scratch::unique("resume-backup");
+        let install = resuming(&scratch, Some(crate::Backup::Simple("~".to_string())));
+
+        assert!(self::install(&scratch, &install));
+        assert_eq!(scratch.tree()["dest"], Entry::file("content"));
+        assert_eq!(scratch.tree()["dest~"], Entry::file("con"));
+    }
+
+    #[test]
+    fn resuming_checks_the_expected_content_first() {
+        let scratch = Scratch::unique("resume-expected");
+        let mut install = resuming(&scratch, None);
+
+        let dest = std::fs::canonicalize(scratch.join("dest")).unwrap();
+        install.expectations = vec![(dest, "0".repeat(64))];
+
+        assert!(!self::install(&scratch, &install));
+        assert_eq!(scratch.tree()["dest"], Entry::file("con"));
+    }
+
+    // Windows refuses to write to a read-only file, which is only cleared with --force.
+    #[cfg(windows)]
+    #[test]
+    fn resuming_a_read_only_destination_needs_force() {
+        for (force, expected) in [(false, "con"), (true, "content")] {
+            let scratch = Scratch::unique("resume-read-only");
+            scratch.declare(&[
+                ("source", Element::File("content")),
+                ("dest", Element::ReadOnly(Box::new(Element::File("con")))),
+            ]);
+
+            let mut install = crate::Install::new(None, crate::backup::Mode::Rename, false);
+            install.resume = true;
+            install.force = force;
+
+            assert_eq!(self::install(&scratch, &install), force);
+            assert_eq!(scratch.tree()["dest"], Entry::file(expected));
+
+            let metadata = std::fs::metadata(scratch.join("dest")).unwrap();
+            assert!(metadata.permissions().readonly());
+        }
+    }
+
+    // A resumed file is finished off just as one installed in full is, with the same mode and
+    // times.
+    #[test]
+    fn resuming_leaves_the_same_metadata_as_a_full_install() {
+        let metadata = |resume: bool| {
+            let scratch = Scratch::unique("resume-metadata");
+            scratch.declare(&[
+                ("source", Element::File("content")),
+                ("dest", Element::File(if resume { "con" } else { "" })),
+            ]);
+
+            let source = std::fs::File::options()
+                .write(true)
+                .open(scratch.join("source"))
+                .unwrap();
+            let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+            source.set_modified(time).unwrap();
+
+            let mut install = crate::Install::new(None, crate::backup::Mode::Rename, false);
+            install.resume = resume;
+            install.preserve_timestamps = true;
+            install.preserve_creation_time = true;
+            install.attributes.mode = Some("640".to_string());
+
+            assert!(self::install(&scratch, &install));
+            assert_eq!(scratch.tree()["dest"], Entry::file("content"));
+
+            std::fs::metadata(scratch.join("dest")).unwrap()
+        };
+
+        let (full, resumed) = (metadata(false), metadata(true));
+        assert_eq!(resumed.modified().unwrap(), full.modified().unwrap());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            assert_eq!(full.permissions().mode() & 0o7777, 0o640);
+            assert_eq!(resumed.permissions().mode(), full.permissions().mode());
+        }
+    }
+
+    // A versioned install whose unversioned name is already there.
+    fn versioned(scratch: &Scratch, install: &mut crate::Install) {
+        scratch.declare(&[
+            ("source", Element::File("new")),
+            ("dest", Element::File("old")),
+        ]);
+
+        install.version = Some("1.0".to_string());
+    }
+
+    #[test]
+    fn asks_before_replacing_the_unversioned_name() {
+        let scratch = Scratch::unique("versioned-interactive");
+        let (mut declining, asked) = interactive(false);
+        versioned(&scratch, &mut declining);
+
+        assert!(!install(&scratch, &declining));
+        assert_eq!(*asked.borrow(), vec![scratch.join("dest")]);
+
+        // Nothing was installed under the versioned name either.
+        assert_eq!(
+            scratch.tree().into_keys().collect::<Vec<_>>(),
+            ["dest", "source"]
+        );
+    }
+
+    #[test]
+    fn checks_the_expected_content_of_the_unversioned_name() {
+        let scratch = Scratch::unique("versioned-expected");
+        let mut install = crate::Install::new(None, crate::backup::Mode::Rename, false);
+        versioned(&scratch, &mut install);
+
+        let dest = std::fs::canonicalize(scratch.join("dest")).unwrap();
+        install.expectations = vec![(dest, "0".repeat(64))];
+
+        assert!(!self::install(&scratch, &install));
+        assert_eq!(
+            scratch.tree().into_keys().collect::<Vec<_>>(),
+            ["dest", "source"]
+        );
+    }
+
+    #[test]
+    fn counts_the_backup_of_the_unversioned_name() {
+        let scratch = Scratch::unique("versioned-backup");
+        let mut install = crate::Install::new(
+            Some(crate::Backup::Numbered),
+            crate::backup::Mode::Copy,
+            false,
+        );
+        versioned(&scratch, &mut install);
+
+        let mut stats = crate::stats::Stats::default();
+        crate::file_target(
+            scratch.join("source"),
+            scratch.join("dest"),
+            &install,
+            &mut stats,
+        );
+
+        assert_eq!(stats.backups, 1);
+        assert_eq!(scratch.tree()["dest"], Entry::file("new"));
+        assert_eq!(scratch.tree()["dest.~1~"], Entry::file("old"));
+        assert_eq!(scratch.tree()["dest-1.0"], Entry::file("new"));
+    }
+
+    // A read-only file can still be flushed, as it is opened for reading where that's enough, and
+    // a name without a directory has the current directory flushed.
+    #[test]
+    fn syncs_files_and_the_directories_holding_them() {
+        let scratch = Scratch::unique("sync");
+        scratch.declare(&[
+            ("file", Element::File("content")),
+            ("read-only", Element::File("content")),
+        ]);
+
+        crate::set_readonly(scratch.join("read-only"), true).unwrap();
+
+        for name in ["file", "read-only"] {
+            crate::sync_file(scratch.join(name)).unwrap();
+            crate::sync_directory(scratch.join(name)).unwrap();
+        }
+
+        crate::sync_directory("file").unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reports_what_cannot_be_synced() {
+        let scratch = Scratch::unique("sync-missing");
+
+        let missing = crate::sync_file(scratch.join("missing")).unwrap_err();
+        assert_eq!(missing.kind(), std::io::ErrorKind::NotFound);
+
+        let missing = crate::sync_directory(scratch.join("missing/file")).unwrap_err();
+        assert_eq!(missing.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn installs_as_usual_when_syncing_each_file() {
+        let scratch = Scratch::unique("sync-each");
+        scratch.declare(&[
+            ("source", Element::File("new")),
+            ("dest", Element::Directory(vec![])),
+        ]);
+
+        let mut install = crate::Install::new(None, crate::backup::Mode::Rename, false);
+        install.sync = Some(crate::Flush::Each);
+
+        let mut stats = crate::stats::Stats::default();
+        let results = crate::directory_target(
+            &[scratch.join("source")],
+            scratch.join("dest"),
+            &install,
+            &mut stats,
+        );
+
+        assert!(results.iter().all(|r| r.succeeded()));
+        assert_eq!(stats.failed, 0);
+        assert_eq!(scratch.tree()["dest/source"], Entry::file("new"));
+    }
+
+    #[test]
+    fn only_retries_refusals_that_pass() {
+        let error = std::io::Error::from_raw_os_error;
+
+        assert_eq!(crate::is_transient(&error(32), false), cfg!(windows));
+        assert_eq!(crate::is_transient(&error(33), false), cfg!(windows));
+        assert_eq!(crate::is_transient(&error(5), true), cfg!(windows));
+        assert!(!crate::is_transient(&error(5), false));
+        assert!(!crate::is_transient(&error(2), true));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn creates_nothing_for_the_root() {
+        for make_all_directories in [false, true] {
+            assert_eq!(
+                crate::create_directory("/", make_all_directories, None),
+                Some(vec![])
+            );
+            assert_eq!(
+                crate::create_directory("//", make_all_directories, None),
+                Some(vec![])
+            );
+        }
+    }
+
+    // Components that were already there aren't counted among those created.
+    #[test]
+    fn reports_the_directories_it_creates() {
+        let scratch = Scratch::unique("reports-created");
+        scratch.declare(&[("a", Element::Directory(vec![]))]);
+
+        assert_eq!(
+            crate::create_directory(scratch.join("a/b/c"), true, None),
+            Some(vec![scratch.join("a/b"), scratch.join("a/b/c")])
+        );
+    }
+
+    #[test]
+    fn ignores_trailing_separators() {
+        let scratch = Scratch::unique("trailing-separators");
+        let separators = match cfg!(windows) {
+            true => ["/", "//", "\\", "/\\"],
+            false => ["/", "//", "///", "/./"],
+        };
+
+        for (i, separators) in separators.into_iter().enumerate() {
+            let mut single = scratch.join(format!("single{}", i)).into_os_string();
+            single.push(separators);
+            assert!(crate::create_directory(&single, false, None).is_some());
+
+            // Made again, an existing directory is left as it is.
+            assert_eq!(crate::create_directory(&single, false, None), Some(vec![]));
+
+            let mut nested = scratch.join(format!("nested{}/a", i)).into_os_string();
+            nested.push(separators);
+            assert!(crate::create_directory(&nested, true, None).is_some());
+        }
+
+        let tree = scratch.tree();
+        assert_eq!(tree.len(), 12, "{:?}", tree.keys());
+        assert!(tree.values().all(|entry| *entry == Entry::Directory));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn creates_nothing_for_a_drive_root() {
+        let scratch = Scratch::unique("drive-root");
+        let root = scratch
+            .path()
+            .components()
+            .take(2)
+            .collect::<std::path::PathBuf>();
+
+        assert_eq!(crate::create_directory(&root, false, None), Some(vec![]));
+        assert_eq!(crate::create_directory(&root, true, None), Some(vec![]));
+    }
+
+    // Beneath a drive or share that isn't there, nothing is attempted.
+    #[cfg(windows)]
+    #[test]
+    fn refuses_directories_on_missing_roots() {
+        let missing = (b'D'..=b'Z')
+            .map(|letter| format!("{}:\\", letter as char))
+            .find(|root| !std::path::Path::new(root).is_dir());
+
+        if let Some(root) = missing {
+            assert!(crate::create_directory(format!("{}a\\b", root), true, None).is_none());
+        }
+
+        let share = "\\\\localhost\\winstall-no-such-share\\a";
+        assert!(crate::create_directory(share, true, None).is_none());
+        assert!(crate::create_directory(share, false, None).is_none());
+    }
 }
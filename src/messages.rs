@@ -0,0 +1,347 @@
+// Every message printed with --verbose is put together here, so that the wording (which follows
+// GNU install where there is an equivalent) only changes when it is changed deliberately.
+
+// Where verbose messages are sent. GNU install writes them to standard output, which remains the
+// default, but they can be kept apart from the output of other programs on standard error.
+#[derive(Clone, Copy)]
+pub enum Router {
+    Stdout,
+    Stderr,
+}
+
+impl Router {
+    pub fn send(self, message: String) {
+        self.write(&mut std::io::stdout(), &mut std::io::stderr(), message);
+    }
+
+    // Writes message as a line of whichever stream the router chooses.
+    fn write<'a>(
+        self,
+        stdout: &'a mut dyn std::io::Write,
+        stderr: &'a mut dyn std::io::Write,
+        message: String,
+    ) {
+        let stream = match self {
+            Router::Stdout => stdout,
+            Router::Stderr => stderr,
+        };
+
+        _ = writeln!(stream, "{}", message);
+    }
+}
+
+fn backup_note(backup: Option<&std::path::Path>) -> String {
+    match backup {
+        Some(path) => format!(" (backup: {})", crate::quoting::quote(path)),
+        None => String::new(),
+    }
+}
+
+pub fn installed<F: AsRef<std::path::Path>, T: AsRef<std::path::Path>>(
+    from: F,
+    to: T,
+    backup: Option<&std::path::Path>,
+) -> String {
+    format!(
+        "{} -> {}{}",
+        crate::quoting::quote(from.as_ref()),
+        crate::quoting::quote(to.as_ref()),
+        backup_note(backup)
+    )
+}
+
+pub fn linked<F: AsRef<std::path::Path>, T: AsRef<std::path::Path>>(
+    from: F,
+    to: T,
+    backup: Option<&std::path::Path>,
+) -> String {
+    format!(
+        "{} => {}{}",
+        crate::quoting::quote(from.as_ref()),
+        crate::quoting::quote(to.as_ref()),
+        backup_note(backup)
+    )
+}
+
+pub fn resumed<F: AsRef<std::path::Path>, T: AsRef<std::path::Path>>(
+    from: F,
+    to: T,
+    offset: u64,
+    backup: Option<&std::path::Path>,
+) -> String {
+    format!(
+        "{} -> {} (resumed at byte {}){}",
+        crate::quoting::quote(from.as_ref()),
+        crate::quoting::quote(to.as_ref()),
+        offset,
+        backup_note(backup)
+    )
+}
+
+pub fn touched<F: AsRef<std::path::Path>, T: AsRef<std::path::Path>>(from: F, to: T) -> String {
+    format!(
+        "{} -> {} (times only)",
+        crate::quoting::quote(from.as_ref()),
+        crate::quoting::quote(to.as_ref())
+    )
+}
+
+pub fn compared<F: AsRef<std::path::Path>, T: AsRef<std::path::Path>>(
+    from: F,
+    to: T,
+    policy: &str,
+    same: bool,
+) -> String {
+    format!(
+        "winstall: compared {} with {} by {}: {}",
+        crate::quoting::quote(from.as_ref()),
+        crate::quoting::quote(to.as_ref()),
+        policy,
+        if same { "same, skipping" } else { "different" }
+    )
+}
+
+pub fn unchanged_since<F: AsRef<std::path::Path>, T: AsRef<std::path::Path>>(
+    from: F,
+    to: T,
+) -> String {
+    format!(
+        "winstall: {} and {} are unchanged since they were last installed, skipping",
+        crate::quoting::quote(from.as_ref()),
+        crate::quoting::quote(to.as_ref())
+    )
+}
+
+pub fn removed<P: AsRef<std::path::Path>>(p: P) -> String {
+    format!("removed {}", crate::quoting::quote(p.as_ref()))
+}
+
+pub fn excluded<P: AsRef<std::path::Path>>(p: P) -> String {
+    format!("winstall: excluding {}", crate::quoting::quote(p.as_ref()))
+}
+
+pub fn staging<P: AsRef<std::path::Path>, D: AsRef<std::path::Path>>(
+    p: P,
+    directory: D,
+    other_volume: bool,
+) -> String {
+    match other_volume {
+        true => format!(
+            "winstall: staging {} beside it, {} is on another volume",
+            crate::quoting::quote(p.as_ref()),
+            crate::quoting::quote(directory.as_ref())
+        ),
+        false => format!(
+            "winstall: staging {} in {}",
+            crate::quoting::quote(p.as_ref()),
+            crate::quoting::quote(directory.as_ref())
+        ),
+    }
+}
+
+pub fn creating_directory<P: AsRef<std::path::Path>>(p: P) -> String {
+    format!(
+        "winstall: creating directory {}",
+        crate::quoting::quote(p.as_ref())
+    )
+}
+
+pub fn unchanged() -> String {
+    "winstall: nothing has changed since the last run, skipping".to_string()
+}
+
+pub fn signing<P: AsRef<std::path::Path>>(p: P) -> String {
+    format!("winstall: signing {}", crate::quoting::quote(p.as_ref()))
+}
+
+pub fn running(command: &str) -> String {
+    format!("winstall: running: {}", command)
+}
+
+pub fn installed_into<P: AsRef<std::path::Path>>(
+    p: P,
+    files: u64,
+    bytes: u64,
+    backups: u64,
+) -> String {
+    format!(
+        "winstall: installed {} file(s) ({} bytes, {} backup(s)) into {}",
+        files,
+        bytes,
+        backups,
+        crate::quoting::quote(p.as_ref())
+    )
+}
+
+pub fn failed<F: AsRef<std::path::Path>, T: AsRef<std::path::Path>>(
+    from: F,
+    to: T,
+    reason: Option<&str>,
+) -> String {
+    format!(
+        "winstall: failed: {} -> {}{}",
+        crate::quoting::quote(from.as_ref()),
+        crate::quoting::quote(to.as_ref()),
+        reason.map(|r| format!(" ({})", r)).unwrap_or_default()
+    )
+}
+
+pub fn summary(stats: &crate::stats::Stats, elapsed: std::time::Duration) -> String {
+    let retries = match stats.retries {
+        0 => String::new(),
+        n => format!(" ({} retries)", n),
+    };
+
+    format!(
+        "winstall: {} file(s) installed, {} backed up, {} skipped, {} bytes copied in {:.2}s{}",
+        stats.files,
+        stats.backups,
+        stats.skipped,
+        stats.bytes,
+        elapsed.as_secs_f64(),
+        retries
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Router;
+
+    #[test]
+    fn routes_each_message_to_one_stream() {
+        for (router, expected) in [(Router::Stdout, (1, 0)), (Router::Stderr, (0, 1))] {
+            let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+            router.write(&mut stdout, &mut stderr, "message".to_string());
+
+            let lines = |stream: &[u8]| stream.split(|b| *b == b'\n').count() - 1;
+            assert_eq!((lines(&stdout), lines(&stderr)), expected);
+            assert!([&stdout, &stderr].contains(&&b"message\n".to_vec()));
+        }
+    }
+
+    #[test]
+    fn installed() {
+        assert_eq!(super::installed("a", "b", None), "'a' -> 'b'");
+        assert_eq!(
+            super::installed("a", "b", Some("b~".as_ref())),
+            "'a' -> 'b' (backup: 'b~')"
+        );
+    }
+
+    #[test]
+    fn linked() {
+        assert_eq!(super::linked("a", "b", None), "'a' => 'b'");
+        assert_eq!(
+            super::linked("a", "b", Some("b.~1~".as_ref())),
+            "'a' => 'b' (backup: 'b.~1~')"
+        );
+    }
+
+    #[test]
+    fn resumed_and_touched() {
+        assert_eq!(
+            super::resumed("a", "b", 4096, None),
+            "'a' -> 'b' (resumed at byte 4096)"
+        );
+        assert_eq!(
+            super::resumed("a", "b", 4096, Some("b~".as_ref())),
+            "'a' -> 'b' (resumed at byte 4096) (backup: 'b~')"
+        );
+        assert_eq!(super::touched("a", "b"), "'a' -> 'b' (times only)");
+    }
+
+    #[test]
+    fn compared() {
+        assert_eq!(
+            super::compared("a", "b", "hash", true),
+            "winstall: compared 'a' with 'b' by hash: same, skipping"
+        );
+        assert_eq!(
+            super::compared("a", "b", "size", false),
+            "winstall: compared 'a' with 'b' by size: different"
+        );
+    }
+
+    #[test]
+    fn skipped() {
+        assert_eq!(
+            super::unchanged_since("a", "b"),
+            "winstall: 'a' and 'b' are unchanged since they were last installed, skipping"
+        );
+        assert_eq!(
+            super::unchanged(),
+            "winstall: nothing has changed since the last run, skipping"
+        );
+        assert_eq!(super::excluded("a"), "winstall: excluding 'a'");
+    }
+
+    #[test]
+    fn removed() {
+        assert_eq!(super::removed("b.~1~"), "removed 'b.~1~'");
+    }
+
+    #[test]
+    fn staging() {
+        assert_eq!(
+            super::staging("b", "tmp", false),
+            "winstall: staging 'b' in 'tmp'"
+        );
+        assert_eq!(
+            super::staging("b", "tmp", true),
+            "winstall: staging 'b' beside it, 'tmp' is on another volume"
+        );
+    }
+
+    #[test]
+    fn directories_and_hooks() {
+        assert_eq!(
+            super::creating_directory("d"),
+            "winstall: creating directory 'd'"
+        );
+        assert_eq!(super::signing("b"), "winstall: signing 'b'");
+        assert_eq!(super::running("make post"), "winstall: running: make post");
+    }
+
+    #[test]
+    fn results() {
+        assert_eq!(
+            super::installed_into("d", 2, 10, 1),
+            "winstall: installed 2 file(s) (10 bytes, 1 backup(s)) into 'd'"
+        );
+        assert_eq!(
+            super::failed("a", "b", None),
+            "winstall: failed: 'a' -> 'b'"
+        );
+        assert_eq!(
+            super::failed("a", "b", Some("conflict")),
+            "winstall: failed: 'a' -> 'b' (conflict)"
+        );
+    }
+
+    #[test]
+    fn summary() {
+        let mut stats = crate::stats::Stats {
+            files: 3,
+            backups: 1,
+            skipped: 2,
+            bytes: 100,
+            ..Default::default()
+        };
+
+        let elapsed = std::time::Duration::from_millis(1250);
+
+        assert_eq!(
+            super::summary(&stats, elapsed),
+            "winstall: 3 file(s) installed, 1 backed up, 2 skipped, 100 bytes copied in 1.25s"
+        );
+
+        stats.retries = 4;
+        assert_eq!(
+            super::summary(&stats, elapsed),
+            concat!(
+                "winstall: 3 file(s) installed, 1 backed up, 2 skipped, 100 bytes copied in 1.25s",
+                " (4 retries)"
+            )
+        );
+    }
+}
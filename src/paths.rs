@@ -0,0 +1,356 @@
+const RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+// Windows treats these names as devices in any directory and whatever the extension, so 'NUL.txt'
+// silently discards what is written to it and 'CON' writes to the console. Returns the first
+// component of p that is one of them.
+pub fn reserved_name<P: AsRef<std::path::Path>>(p: P) -> Option<String> {
+    if !cfg!(windows) {
+        return None;
+    }
+
+    p.as_ref().components().find_map(|c| match c {
+        std::path::Component::Normal(name) => {
+            let name = name.to_string_lossy();
+            is_reserved(&name).then(|| name.into_owned())
+        }
+        _ => None,
+    })
+}
+
+// Whether name is a device, which it is when what comes before its first '.' is one of the
+// reserved names, ignoring case and any spaces after it.
+fn is_reserved(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    RESERVED.iter().any(|r| r.eq_ignore_ascii_case(stem))
+}
+
+// What an operand refers to, for deciding whether it names a directory to install into. Unlike
+// is_dir, a path that can't be examined is told apart from one that doesn't exist.
+pub enum Target {
+    Directory,
+    SymlinkToDirectory,
+    File,
+    DanglingSymlink,
+    NotFound,
+    Inaccessible(std::io::Error),
+}
+
+impl Target {
+    pub fn is_directory(&self) -> bool {
+        matches!(self, Target::Directory | Target::SymlinkToDirectory)
+    }
+}
+
+pub fn classify<P: AsRef<std::path::Path>>(p: P) -> Target {
+    let link = match std::fs::symlink_metadata(p.as_ref()) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Target::NotFound,
+        Err(e) => return Target::Inaccessible(e),
+    };
+
+    if !link.file_type().is_symlink() {
+        return match link.is_dir() {
+            true => Target::Directory,
+            false => Target::File,
+        };
+    }
+
+    match std::fs::metadata(p.as_ref()) {
+        Ok(metadata) if metadata.is_dir() => Target::SymlinkToDirectory,
+        Ok(_) => Target::File,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Target::DanglingSymlink,
+        Err(e) => Target::Inaccessible(e),
+    }
+}
+
+// Whether an operand ends with a path separator, which says that it names a directory whatever
+// there is (or isn't) at that path. Either separator counts on Windows.
+pub fn ends_with_separator<P: AsRef<std::path::Path>>(operand: P) -> bool {
+    let operand = operand.as_ref().as_os_str().to_string_lossy();
+    operand.ends_with('/') || (cfg!(windows) && operand.ends_with('\\'))
+}
+
+// Whether a and b are the same file, however they are named. On unix this is decided by device and
+// inode, so hard links are caught too. Windows only exposes the file index through std on nightly,
+// so there the canonical paths are compared, which sees through links and differences in case.
+pub fn same_file<A: AsRef<std::path::Path>, B: AsRef<std::path::Path>>(a: A, b: B) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        if let (Ok(a), Ok(b)) = (std::fs::metadata(a.as_ref()), std::fs::metadata(b.as_ref())) {
+            return a.dev() == b.dev() && a.ino() == b.ino();
+        }
+    }
+
+    match (
+        std::fs::canonicalize(a.as_ref()),
+        std::fs::canonicalize(b.as_ref()),
+    ) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+// Whether a and b, which have to exist, are on the same volume, so that a file can be renamed from
+// one to the other. On unix this is decided by device. Windows only exposes the volume serial
+// number through std on nightly, so there the drive or share that the canonical paths start with
+// is compared, which doesn't see volumes mounted in a folder.
+pub fn same_volume<A: AsRef<std::path::Path>, B: AsRef<std::path::Path>>(a: A, b: B) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        if let (Ok(a), Ok(b)) = (std::fs::metadata(a.as_ref()), std::fs::metadata(b.as_ref())) {
+            return a.dev() == b.dev();
+        }
+    }
+
+    match (
+        std::fs::canonicalize(a.as_ref()),
+        std::fs::canonicalize(b.as_ref()),
+    ) {
+        (Ok(a), Ok(b)) => match (a.components().next(), b.components().next()) {
+            (Some(std::path::Component::Prefix(a)), Some(std::path::Component::Prefix(b))) => {
+                a.as_os_str().eq_ignore_ascii_case(b.as_os_str())
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+// Orders paths the way Explorer lists them: ignoring case, and with runs of digits compared by
+// their value so that 'file2' comes before 'file10'. Paths that only differ in case or leading
+// zeros fall back to a plain comparison, so the order is total.
+pub fn natural_cmp<A: AsRef<std::path::Path>, B: AsRef<std::path::Path>>(
+    a: A,
+    b: B,
+) -> std::cmp::Ordering {
+    let a = a.as_ref().to_string_lossy();
+    let b = b.as_ref().to_string_lossy();
+
+    let mut chars_a = a.chars().peekable();
+    let mut chars_b = b.chars().peekable();
+
+    loop {
+        let (x, y) = match (chars_a.peek(), chars_b.peek()) {
+            (None, None) => return a.cmp(&b),
+            (None, Some(_)) => return std::cmp::Ordering::Less,
+            (Some(_), None) => return std::cmp::Ordering::Greater,
+            (Some(x), Some(y)) => (*x, *y),
+        };
+
+        let ordering = match x.is_ascii_digit() && y.is_ascii_digit() {
+            true => {
+                let x = digits(&mut chars_a);
+                let y = digits(&mut chars_b);
+
+                x.len().cmp(&y.len()).then_with(|| x.cmp(&y))
+            }
+            false => {
+                chars_a.next();
+                chars_b.next();
+
+                x.to_lowercase().cmp(y.to_lowercase())
+            }
+        };
+
+        if ordering != std::cmp::Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+// Takes a run of digits, without any leading zeros so that the length orders the values.
+fn digits(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut digits = String::new();
+
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        if !(digits.is_empty() && c == '0') {
+            digits.push(c);
+        }
+    }
+
+    digits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Target;
+    use crate::scratch::{Element, Scratch};
+
+    #[test]
+    fn classifies_what_a_path_names() {
+        let scratch = Scratch::unique("classify");
+        scratch.declare(&[
+            ("directory", Element::Directory(vec![])),
+            ("file", Element::File("content")),
+            ("to-directory", Element::Symlink("directory")),
+            ("to-file", Element::Symlink("file")),
+            ("dangling", Element::Symlink("missing")),
+        ]);
+
+        let classify = |name| super::classify(scratch.join(name));
+
+        assert!(matches!(classify("directory"), Target::Directory));
+        assert!(matches!(classify("file"), Target::File));
+        assert!(matches!(classify("missing"), Target::NotFound));
+
+        assert!(matches!(
+            classify("to-directory"),
+            Target::SymlinkToDirectory
+        ));
+        assert!(matches!(classify("to-file"), Target::File));
+        assert!(matches!(classify("dangling"), Target::DanglingSymlink));
+
+        assert!(classify("directory").is_directory());
+        assert!(!classify("file").is_directory());
+    }
+
+    // Looking beneath a file isn't the same as looking for something that isn't there.
+    #[cfg(unix)]
+    #[test]
+    fn tells_paths_that_cant_be_examined_apart() {
+        let scratch = Scratch::unique("classify-inaccessible");
+        scratch.declare(&[("file", Element::File("content"))]);
+
+        let beneath = super::classify(scratch.join("file").join("child"));
+        assert!(matches!(beneath, Target::Inaccessible(_)));
+    }
+
+    #[test]
+    fn finds_trailing_separators() {
+        assert!(super::ends_with_separator("dest/"));
+        assert!(super::ends_with_separator("a/b//"));
+        assert!(super::ends_with_separator("/"));
+        assert!(!super::ends_with_separator("dest"));
+        assert!(!super::ends_with_separator("a/dest"));
+        assert!(!super::ends_with_separator(""));
+
+        // A backslash only separates on Windows, and is part of a name elsewhere.
+        assert_eq!(super::ends_with_separator("dest\\"), cfg!(windows));
+        assert_eq!(super::ends_with_separator("a/dest\\"), cfg!(windows));
+        assert!(super::ends_with_separator("a\\dest/"));
+    }
+
+    // A hard link is the same file under another name, while a copy with the same content isn't.
+    #[test]
+    fn finds_the_same_file_under_another_name() {
+        let scratch = Scratch::unique("same-file");
+        scratch.declare(&[
+            ("file", Element::File("content")),
+            ("copy", Element::File("content")),
+            ("directory", Element::Directory(vec![])),
+        ]);
+
+        std::fs::hard_link(scratch.join("file"), scratch.join("link")).unwrap();
+
+        let same = |a, b| super::same_file(scratch.join(a), scratch.join(b));
+
+        assert!(same("file", "file"));
+        assert!(same("file", "link"));
+        assert!(same("file", "directory/../file"));
+        assert!(!same("file", "copy"));
+        assert!(!same("file", "missing"));
+        assert!(!same("missing", "missing"));
+    }
+
+    #[test]
+    fn finds_paths_on_the_same_volume() {
+        let scratch = Scratch::unique("same-volume");
+        scratch.declare(&[
+            (
+                "a",
+                Element::Directory(vec![("file", Element::File("content"))]),
+            ),
+            ("b", Element::Directory(vec![])),
+        ]);
+
+        assert!(super::same_volume(scratch.join("a"), scratch.join("b")));
+        assert!(super::same_volume(scratch.join("a/file"), scratch.path()));
+        assert!(!super::same_volume(
+            scratch.join("a"),
+            scratch.join("missing")
+        ));
+    }
+
+    // /proc is always a file system of its own.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn tells_volumes_apart() {
+        let scratch = Scratch::unique("other-volume");
+        assert!(!super::same_volume(scratch.path(), "/proc"));
+    }
+
+    fn sorted(names: &[&str]) -> Vec<String> {
+        let mut names = names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        names.sort_by(|a, b| super::natural_cmp(a, b));
+        names
+    }
+
+    #[test]
+    fn orders_numbers_by_value() {
+        assert_eq!(
+            sorted(&["file10", "file2", "file1", "file20"]),
+            ["file1", "file2", "file10", "file20"]
+        );
+        assert_eq!(
+            sorted(&["v1.10", "v1.9", "v1.2"]),
+            ["v1.2", "v1.9", "v1.10"]
+        );
+        assert_eq!(sorted(&["a10b", "a9c", "a9b"]), ["a9b", "a9c", "a10b"]);
+    }
+
+    #[test]
+    fn ignores_leading_zeros_and_case() {
+        use std::cmp::Ordering;
+
+        assert_eq!(super::natural_cmp("file002", "file10"), Ordering::Less);
+        assert_eq!(super::natural_cmp("File", "file2"), Ordering::Less);
+        assert_eq!(sorted(&["b", "A", "C", "a2"]), ["A", "a2", "b", "C"]);
+    }
+
+    // Names that are only told apart by case or leading zeros still have an order, and so are
+    // never equal unless they are the same.
+    #[test]
+    fn breaks_ties_between_equal_names() {
+        use std::cmp::Ordering;
+
+        assert_eq!(super::natural_cmp("file1", "file1"), Ordering::Equal);
+        assert_eq!(super::natural_cmp("file01", "file1"), Ordering::Less);
+        assert_eq!(super::natural_cmp("file1", "file01"), Ordering::Greater);
+        assert_eq!(super::natural_cmp("FILE", "file"), Ordering::Less);
+        assert_eq!(super::natural_cmp("file", "FILE"), Ordering::Greater);
+    }
+
+    #[test]
+    fn recognises_reserved_names() {
+        for name in ["CON", "con", "con.txt", "NUL.tar.gz", "COM1", "lpt9", "Aux"] {
+            assert!(super::is_reserved(name), "{}", name);
+        }
+
+        for name in ["COM0", "LPT10", "CONSOLE", "icon", "con-1", "file.con", ""] {
+            assert!(!super::is_reserved(name), "{}", name);
+        }
+    }
+
+    // Windows drops trailing dots and spaces from a name, so they don't hide a device.
+    #[test]
+    fn recognises_reserved_names_with_trailing_dots_or_spaces() {
+        for name in ["CON.", "CON ", "con . txt", "NUL...", "COM1 .log"] {
+            assert!(super::is_reserved(name), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn finds_reserved_names_anywhere_in_a_path_on_windows() {
+        let found = super::reserved_name("out/con.txt/app.exe");
+        assert_eq!(found.as_deref(), cfg!(windows).then_some("con.txt"));
+
+        assert_eq!(super::reserved_name("out/console/app.exe"), None);
+    }
+}
@@ -0,0 +1,116 @@
+// How paths are written in messages. A name containing a quote or a newline would otherwise make
+// a message ambiguous, or split it across lines.
+#[derive(Clone, Copy)]
+pub enum Style {
+    Literal,
+    Shell,
+    C,
+}
+
+static STYLE: std::sync::OnceLock<Style> = std::sync::OnceLock::new();
+
+// Sets the style used by quote for the rest of the run. Only the first call has any effect.
+pub fn set(style: Style) {
+    let _ = STYLE.set(style);
+}
+
+// Quotes p in the selected style, which is shell unless another one has been set. The shell style
+// always quotes the name, as messages did before there was a choice, and writes control characters
+// as $'...' sequences so that the result can be pasted back into a POSIX shell.
+pub fn quote<P: AsRef<std::path::Path>>(p: P) -> String {
+    let name = p.as_ref().to_string_lossy();
+
+    match STYLE.get().copied().unwrap_or(Style::Shell) {
+        Style::Literal => name.into_owned(),
+        Style::Shell => shell(&name),
+        Style::C => c(&name),
+    }
+}
+
+fn shell(name: &str) -> String {
+    let mut quoted = String::from("'");
+
+    for ch in name.chars() {
+        match ch {
+            '\'' => quoted.push_str("'\\''"),
+            ch if ch.is_control() => quoted.push_str(&format!("'$'{}''", escape(ch))),
+            ch => quoted.push(ch),
+        }
+    }
+
+    quoted.push('\'');
+    quoted
+}
+
+fn c(name: &str) -> String {
+    let mut quoted = String::from("\"");
+
+    for ch in name.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            ch if ch.is_control() => quoted.push_str(&escape(ch)),
+            ch => quoted.push(ch),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+fn escape(ch: char) -> String {
+    match ch {
+        '\n' => "\\n".to_string(),
+        '\r' => "\\r".to_string(),
+        '\t' => "\\t".to_string(),
+        ch if (ch as u32) < 0x100 => format!("\\{:03o}", ch as u32),
+        ch => format!("\\u{:04x}", ch as u32),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // The style can only be set once in a run, so the tests call each style directly.
+    #[test]
+    fn quotes_for_the_shell() {
+        let quoted = [
+            ("plain", r#"'plain'"#),
+            ("with space", r#"'with space'"#),
+            ("it's", r#"'it'\''s'"#),
+            ("\"double\"", r#"'"double"'"#),
+            ("back\\slash", r#"'back\slash'"#),
+            ("a\nb", r#"'a'$'\n''b'"#),
+            ("tab\there", r#"'tab'$'\t''here'"#),
+            ("bell\u{7}", r#"'bell'$'\007'''"#),
+            ("", "''"),
+        ];
+
+        for (name, expected) in quoted {
+            assert_eq!(super::shell(name), expected, "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn quotes_as_c_strings() {
+        let quoted = [
+            ("plain", r#""plain""#),
+            ("it's", r#""it's""#),
+            ("\"double\"", r#""\"double\"""#),
+            ("back\\slash", r#""back\\slash""#),
+            ("a\nb\r", r#""a\nb\r""#),
+            ("bell\u{7}", r#""bell\007""#),
+            ("next\u{85}line", r#""next\205line""#),
+            ("", r#""""#),
+        ];
+
+        for (name, expected) in quoted {
+            assert_eq!(super::c(name), expected, "{:?}", name);
+        }
+    }
+
+    // Nothing sets a style in the unit tests, so quote falls back to the shell style.
+    #[test]
+    fn quotes_for_the_shell_by_default() {
+        assert_eq!(super::quote("dir/it's"), r#"'dir/it'\''s'"#);
+    }
+}
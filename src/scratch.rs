@@ -0,0 +1,398 @@
+// Directories for tests to work in. Each is made under the system's temporary directory with a
+// name that no other test, whether running alongside it or in an earlier run that crashed, will
+// have used, and is removed again when it is dropped. Nothing is ever written to the working
+// directory.
+//
+// The same module is used by the unit tests and, through tests/support, by the end-to-end tests, so
+// some of it goes unused in each.
+#![allow(dead_code)]
+
+// What a test declares should be in a scratch directory before it starts.
+pub enum Element {
+    File(&'static str),
+    Binary(&'static [u8]),
+    Directory(Vec<(&'static str, Element)>),
+    // A symbolic link to the path given, which is relative to the directory the link is in.
+    Symlink(&'static str),
+    // The element with the read-only attribute set (on unix, with its write permissions removed).
+    ReadOnly(Box<Element>),
+}
+
+// What is found in a scratch directory, as read back by Scratch::tree.
+#[derive(Debug, PartialEq)]
+pub enum Entry {
+    File(Vec<u8>),
+    Directory,
+    Symlink(std::path::PathBuf),
+}
+
+impl Entry {
+    pub fn file(content: &str) -> Entry {
+        Entry::File(content.as_bytes().to_vec())
+    }
+}
+
+pub struct Scratch {
+    path: std::path::PathBuf,
+    denied: std::cell::RefCell<Vec<std::path::PathBuf>>,
+}
+
+impl Scratch {
+    // Makes a new, empty directory whose name starts with prefix.
+    pub fn unique(prefix: &str) -> Scratch {
+        use std::hash::{BuildHasher, Hasher};
+
+        static COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+        loop {
+            // RandomState is seeded randomly for each process, which is enough to keep apart the
+            // names chosen by test binaries that run at the same time.
+            let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+            hasher.write_u32(std::process::id());
+            hasher.write_u64(COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed));
+
+            let path =
+                std::env::temp_dir().join(format!("winstall-{}-{:016x}", prefix, hasher.finish()));
+
+            match std::fs::create_dir(&path) {
+                Ok(_) => {
+                    return Scratch {
+                        path,
+                        denied: Default::default(),
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => panic!("cannot create scratch directory {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    pub fn join<P: AsRef<std::path::Path>>(&self, p: P) -> std::path::PathBuf {
+        self.path.join(p)
+    }
+
+    // Makes each of the elements in the directory, under the names given.
+    pub fn declare(&self, elements: &[(&str, Element)]) {
+        make(&self.path, elements);
+    }
+
+    // Everything in the directory, by its path relative to it with '/' between the components.
+    pub fn tree(&self) -> std::collections::BTreeMap<String, Entry> {
+        let mut tree = std::collections::BTreeMap::new();
+        read(&self.path, "", &mut tree);
+        tree
+    }
+
+    // Makes an empty directory that files can't be created in, by removing its write permissions
+    // on unix and by denying the current user write access to it on Windows (which doesn't take
+    // the read-only attribute of a directory into account). Returns None when that doesn't stop
+    // this process, as when it runs as root, so that a test that needs one can be skipped.
+    pub fn unwritable(&self, name: &str) -> Option<std::path::PathBuf> {
+        let path = self.path.join(name);
+        std::fs::create_dir(&path)
+            .unwrap_or_else(|e| panic!("cannot make {}: {}", path.display(), e));
+
+        self.denied.borrow_mut().push(path.clone());
+        deny(&path);
+
+        let probe = path.join("probe");
+        match std::fs::File::create_new(&probe) {
+            Ok(_) => {
+                _ = std::fs::remove_file(probe);
+                None
+            }
+            Err(_) => Some(path),
+        }
+    }
+
+    // The content of the file at p, relative to the directory.
+    pub fn read<P: AsRef<std::path::Path>>(&self, p: P) -> String {
+        let path = self.path.join(p);
+
+        std::fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("cannot read {}: {}", path.display(), e))
+    }
+}
+
+fn make(directory: &std::path::Path, elements: &[(&str, Element)]) {
+    for (name, element) in elements {
+        let path = directory.join(name);
+
+        if let Err(e) = make_element(&path, element) {
+            panic!("cannot make {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn make_element(path: &std::path::Path, element: &Element) -> std::io::Result<()> {
+    match element {
+        Element::File(content) => std::fs::write(path, content),
+        Element::Binary(content) => std::fs::write(path, content),
+        Element::Directory(children) => std::fs::create_dir(path).map(|_| make(path, children)),
+        Element::Symlink(target) => symlink(target, path),
+        // The element is made before it is protected, so a read-only directory can still be given
+        // content.
+        Element::ReadOnly(element) => {
+            make_element(path, element)?;
+            set_readonly(path, true)
+        }
+    }
+}
+
+#[cfg(unix)]
+fn symlink(target: &str, link: &std::path::Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &str, link: &std::path::Path) -> std::io::Result<()> {
+    match link.parent().unwrap().join(target).is_dir() {
+        true => std::os::windows::fs::symlink_dir(target, link),
+        false => std::os::windows::fs::symlink_file(target, link),
+    }
+}
+
+#[cfg(unix)]
+fn deny(path: &std::path::Path) {
+    set_readonly(path, true).unwrap();
+}
+
+#[cfg(unix)]
+fn allow(path: &std::path::Path) {
+    _ = set_readonly(path, false);
+}
+
+#[cfg(windows)]
+fn deny(path: &std::path::Path) {
+    _ = icacls(path, "/deny");
+}
+
+#[cfg(windows)]
+fn allow(path: &std::path::Path) {
+    _ = icacls(path, "/remove:d");
+}
+
+#[cfg(windows)]
+fn icacls(path: &std::path::Path, action: &str) -> std::io::Result<std::process::ExitStatus> {
+    let user = std::env::var("USERNAME").unwrap_or_default();
+
+    let grant = match action {
+        "/deny" => format!("{}:(W,AD)", user),
+        _ => user,
+    };
+
+    std::process::Command::new("icacls")
+        .arg(path)
+        .args([action, &grant])
+        .stdout(std::process::Stdio::null())
+        .status()
+}
+
+// The end-to-end tests build this module into a crate of their own, so it can't share the
+// binary's set_readonly. Unlike that one it doesn't follow symbolic links.
+fn set_readonly(path: &std::path::Path, readonly: bool) -> std::io::Result<()> {
+    let mut permissions = std::fs::symlink_metadata(path)?.permissions();
+
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(readonly);
+
+    std::fs::set_permissions(path, permissions)
+}
+
+// Clears the read-only attribute from everything under path, as neither a read-only directory's
+// content nor (on Windows) a read-only file can otherwise be removed.
+fn make_writable(path: &std::path::Path) {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return;
+    };
+
+    if metadata.file_type().is_symlink() {
+        return;
+    }
+
+    if metadata.permissions().readonly() {
+        _ = set_readonly(path, false);
+    }
+
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path).into_iter().flatten().flatten() {
+            make_writable(&entry.path());
+        }
+    }
+}
+
+fn read(
+    directory: &std::path::Path,
+    prefix: &str,
+    tree: &mut std::collections::BTreeMap<String, Entry>,
+) {
+    let entries = std::fs::read_dir(directory)
+        .unwrap_or_else(|e| panic!("cannot read {}: {}", directory.display(), e));
+
+    for entry in entries.map(Result::unwrap) {
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        let path = entry.path();
+
+        let file_type = entry.file_type().unwrap();
+
+        if file_type.is_symlink() {
+            tree.insert(name, Entry::Symlink(std::fs::read_link(&path).unwrap()));
+        } else if file_type.is_dir() {
+            read(&path, &format!("{}/", name), tree);
+            tree.insert(name, Entry::Directory);
+        } else {
+            tree.insert(name, Entry::File(std::fs::read(&path).unwrap()));
+        }
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        for path in self.denied.borrow().iter() {
+            allow(path);
+        }
+
+        make_writable(&self.path);
+        _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Element, Entry, Scratch};
+
+    #[test]
+    fn is_made_under_the_temporary_directory() {
+        let scratch = Scratch::unique("location");
+
+        assert!(scratch.path().is_dir());
+        assert!(scratch.path().starts_with(std::env::temp_dir()));
+        assert!(scratch
+            .path()
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("winstall-location-"));
+    }
+
+    #[test]
+    fn names_are_never_reused() {
+        let scratches = (0..64)
+            .map(|_| Scratch::unique("names"))
+            .collect::<Vec<_>>();
+
+        let names = scratches
+            .iter()
+            .map(|s| s.path().to_path_buf())
+            .collect::<std::collections::BTreeSet<_>>();
+
+        assert_eq!(names.len(), scratches.len());
+    }
+
+    #[test]
+    fn is_removed_with_its_content_when_dropped() {
+        let scratch = Scratch::unique("removed");
+        let path = scratch.path().to_path_buf();
+
+        std::fs::create_dir(scratch.join("directory")).unwrap();
+        std::fs::write(scratch.join("directory").join("file"), "content").unwrap();
+
+        drop(scratch);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn reads_back_what_was_declared() {
+        let scratch = Scratch::unique("declare");
+
+        scratch.declare(&[
+            ("file", Element::File("top")),
+            (
+                "directory",
+                Element::Directory(vec![
+                    ("nested", Element::File("inside")),
+                    ("empty", Element::Directory(vec![])),
+                ]),
+            ),
+        ]);
+
+        assert_eq!(
+            scratch.tree().into_iter().collect::<Vec<_>>(),
+            vec![
+                ("directory".to_string(), Entry::Directory),
+                ("directory/empty".to_string(), Entry::Directory),
+                ("directory/nested".to_string(), Entry::file("inside")),
+                ("file".to_string(), Entry::file("top")),
+            ]
+        );
+
+        assert_eq!(scratch.read("directory/nested"), "inside");
+    }
+
+    #[test]
+    fn declares_binary_files_links_and_read_only_elements() {
+        let scratch = Scratch::unique("elements");
+
+        scratch.declare(&[
+            ("binary", Element::Binary(b"\x00\xff\r\n")),
+            ("link", Element::Symlink("binary")),
+            ("locked", Element::ReadOnly(Box::new(Element::File("kept")))),
+            (
+                "sealed",
+                Element::ReadOnly(Box::new(Element::Directory(vec![(
+                    "inside",
+                    Element::File("nested"),
+                )]))),
+            ),
+        ]);
+
+        let tree = scratch.tree();
+        assert_eq!(tree["binary"], Entry::File(b"\x00\xff\r\n".to_vec()));
+        assert_eq!(tree["link"], Entry::Symlink("binary".into()));
+        assert_eq!(tree["locked"], Entry::file("kept"));
+        assert_eq!(tree["sealed/inside"], Entry::file("nested"));
+
+        for name in ["locked", "sealed"] {
+            let metadata = std::fs::metadata(scratch.join(name)).unwrap();
+            assert!(metadata.permissions().readonly(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn removes_read_only_content_when_dropped() {
+        let scratch = Scratch::unique("read-only");
+        let path = scratch.path().to_path_buf();
+
+        scratch.declare(&[(
+            "sealed",
+            Element::ReadOnly(Box::new(Element::Directory(vec![(
+                "locked",
+                Element::ReadOnly(Box::new(Element::File("content"))),
+            )]))),
+        )]);
+
+        drop(scratch);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn unwritable_directories_refuse_new_files() {
+        let scratch = Scratch::unique("unwritable");
+
+        let Some(path) = scratch.unwritable("denied") else {
+            eprintln!("skipped: this process can write to any directory");
+            return;
+        };
+
+        let e = std::fs::File::create_new(path.join("file")).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied);
+
+        let root = scratch.path().to_path_buf();
+        drop(scratch);
+        assert!(!root.exists());
+    }
+}
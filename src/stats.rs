@@ -0,0 +1,95 @@
+use std::io::Write;
+
+#[derive(Default)]
+pub struct Stats {
+    pub files: u64,
+    pub failed: u64,
+    pub skipped: u64,
+    pub bytes: u64,
+    pub backups: u64,
+    pub directories: u64,
+    pub hook_failures: u64,
+    pub warnings: u64,
+    pub retries: u64,
+    pub errors: std::collections::BTreeMap<String, u64>,
+}
+
+impl Stats {
+    pub fn error(&mut self, e: &std::io::Error) {
+        *self.errors.entry(format!("{:?}", e.kind())).or_default() += 1;
+    }
+
+    // Appends a single JSON line describing the run. Only counts and the names of the options
+    // that were used are recorded, never any paths or option values.
+    pub fn append_to<P: AsRef<std::path::Path>>(
+        &self,
+        p: P,
+        elapsed: std::time::Duration,
+        options: &std::collections::BTreeSet<String>,
+        exit_code: i32,
+    ) -> std::io::Result<()> {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let errors = self
+            .errors
+            .iter()
+            .map(|(kind, count)| format!("{}:{}", json_string(kind), count))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let options = options
+            .iter()
+            .map(|o| json_string(o))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let line = format!(
+            concat!(
+                "{{\"timestamp\":{},\"duration_ms\":{},\"exit_code\":{},\"files\":{},",
+                "\"failed\":{},\"skipped\":{},\"bytes\":{},\"backups\":{},\"directories\":{},",
+                "\"hook_failures\":{},\"warnings\":{},\"retries\":{},",
+                "\"errors\":{{{}}},\"options\":[{}]}}\n",
+            ),
+            timestamp,
+            elapsed.as_millis(),
+            exit_code,
+            self.files,
+            self.failed,
+            self.skipped,
+            self.bytes,
+            self.backups,
+            self.directories,
+            self.hook_failures,
+            self.warnings,
+            self.retries,
+            errors,
+            options,
+        );
+
+        std::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(p.as_ref())?
+            .write_all(line.as_bytes())
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+    out
+}
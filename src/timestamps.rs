@@ -0,0 +1,233 @@
+#[derive(Clone, Copy)]
+pub struct Timestamps {
+    accessed: std::time::SystemTime,
+    modified: std::time::SystemTime,
+    #[cfg_attr(not(windows), allow(dead_code))]
+    created: Option<std::time::SystemTime>,
+}
+
+// Differences smaller than this are below the resolution of NTFS (100ns) and aren't worth
+// reporting, anything larger means the destination file system couldn't hold the exact time.
+const TOLERANCE: std::time::Duration = std::time::Duration::from_micros(1);
+
+impl Timestamps {
+    pub fn read<P: AsRef<std::path::Path>>(metadata: &std::fs::Metadata, p: P) -> Option<Self> {
+        let accessed = metadata
+            .accessed()
+            .map_err(|e| {
+                eprintln!(
+                    "winstall: unable to get last accessed time for {}: {}",
+                    crate::quoting::quote(p.as_ref()),
+                    e
+                )
+            })
+            .ok();
+
+        let modified = metadata
+            .modified()
+            .map_err(|e| {
+                eprintln!(
+                    "winstall: unable to get last modified time for {}: {}",
+                    crate::quoting::quote(p.as_ref()),
+                    e
+                )
+            })
+            .ok();
+
+        Option::zip(accessed, modified).map(|(accessed, modified)| Timestamps {
+            accessed,
+            modified,
+            created: None,
+        })
+    }
+
+    // Creation times are only kept on request, as GNU install has no notion of them. They can
+    // only be set on Windows, so elsewhere they are read but never applied.
+    pub fn with_created<P: AsRef<std::path::Path>>(
+        self,
+        metadata: &std::fs::Metadata,
+        p: P,
+    ) -> Self {
+        let created = metadata
+            .created()
+            .map_err(|e| {
+                eprintln!(
+                    "winstall: unable to get creation time for {}: {}",
+                    crate::quoting::quote(p.as_ref()),
+                    e
+                )
+            })
+            .ok();
+
+        Timestamps { created, ..self }
+    }
+
+    pub fn now() -> Self {
+        let now = std::time::SystemTime::now();
+
+        Timestamps {
+            accessed: now,
+            modified: now,
+            created: None,
+        }
+    }
+
+    pub fn file_times(&self) -> std::fs::FileTimes {
+        let times = std::fs::FileTimes::new()
+            .set_accessed(self.accessed)
+            .set_modified(self.modified);
+
+        #[cfg(windows)]
+        {
+            use std::os::windows::fs::FileTimesExt;
+
+            if let Some(created) = self.created {
+                return times.set_created(created);
+            }
+        }
+
+        times
+    }
+
+    // File systems like FAT32 and exFAT only store modification times to the nearest 2 seconds
+    // (or 10 milliseconds), so the time that was set is read back and compared. Rather than
+    // silently differing from the source, the shortfall is returned to be reported. Access times
+    // aren't checked as the act of checking can update them.
+    pub fn verify<P: AsRef<std::path::Path>>(&self, p: P) -> Option<std::time::Duration> {
+        let actual = std::fs::metadata(p.as_ref())
+            .and_then(|m| m.modified())
+            .ok()?;
+
+        let difference = actual
+            .duration_since(self.modified)
+            .or_else(|_| self.modified.duration_since(actual))
+            .unwrap_or_default();
+
+        (difference >= TOLERANCE).then_some(difference)
+    }
+}
+
+pub fn set_directory<P: AsRef<std::path::Path>>(
+    p: P,
+    timestamps: &Timestamps,
+) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+
+    // Directories can only be opened on Windows with FILE_FLAG_BACKUP_SEMANTICS, and setting
+    // the times only requires FILE_WRITE_ATTRIBUTES rather than full write access.
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        options.access_mode(0x0100).custom_flags(0x0200_0000);
+    }
+
+    #[cfg(not(windows))]
+    options.read(true);
+
+    options.open(p.as_ref())?.set_times(timestamps.file_times())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Timestamps;
+    use crate::scratch::{Element, Scratch};
+
+    fn at(seconds: u64, nanos: u32) -> std::time::SystemTime {
+        std::time::UNIX_EPOCH + std::time::Duration::new(seconds, nanos)
+    }
+
+    fn times(accessed: std::time::SystemTime, modified: std::time::SystemTime) -> Timestamps {
+        Timestamps {
+            accessed,
+            modified,
+            created: None,
+        }
+    }
+
+    fn set(p: std::path::PathBuf, timestamps: &Timestamps) {
+        let file = std::fs::OpenOptions::new().write(true).open(p).unwrap();
+        file.set_times(timestamps.file_times()).unwrap();
+    }
+
+    #[test]
+    fn carries_times_from_one_file_to_another() {
+        let scratch = Scratch::unique("timestamps-carry");
+        scratch.declare(&[("a", Element::File("a")), ("b", Element::File("b"))]);
+        set(
+            scratch.join("a"),
+            &times(at(1_000_000, 0), at(2_000_000, 500)),
+        );
+
+        let metadata = std::fs::metadata(scratch.join("a")).unwrap();
+        let read = Timestamps::read(&metadata, scratch.join("a")).unwrap();
+        set(scratch.join("b"), &read);
+
+        let metadata = std::fs::metadata(scratch.join("b")).unwrap();
+        assert_eq!(metadata.accessed().unwrap(), at(1_000_000, 0));
+        assert_eq!(metadata.modified().unwrap(), at(2_000_000, 500));
+        assert_eq!(read.verify(scratch.join("b")), None);
+    }
+
+    // A time the file system rounded, either way, is reported with how far it is out. Less than
+    // the tolerance isn't worth a warning.
+    #[test]
+    fn reports_how_far_a_time_was_rounded() {
+        let scratch = Scratch::unique("timestamps-rounded");
+        scratch.declare(&[("a", Element::File("a"))]);
+        set(
+            scratch.join("a"),
+            &times(at(1_000_000, 0), at(2_000_000, 0)),
+        );
+
+        let wanted =
+            |seconds, nanos| times(at(1_000_000, 0), at(seconds, nanos)).verify(scratch.join("a"));
+        assert_eq!(
+            wanted(2_000_001, 0),
+            Some(std::time::Duration::from_secs(1))
+        );
+        assert_eq!(
+            wanted(1_999_999, 0),
+            Some(std::time::Duration::from_secs(1))
+        );
+        assert_eq!(
+            wanted(2_000_000, 10_000_000),
+            Some(std::time::Duration::from_millis(10))
+        );
+        assert_eq!(wanted(2_000_000, 999), None);
+    }
+
+    // Nothing can be said about a file that can't be read, so nothing is reported.
+    #[test]
+    fn reports_nothing_for_a_missing_file() {
+        let scratch = Scratch::unique("timestamps-missing");
+        let timestamps = times(at(1_000_000, 0), at(2_000_000, 0));
+        assert_eq!(timestamps.verify(scratch.join("missing")), None);
+    }
+
+    #[test]
+    fn sets_the_times_of_a_directory() {
+        let scratch = Scratch::unique("timestamps-directory");
+        scratch.declare(&[("d", Element::Directory(vec![]))]);
+
+        let timestamps = times(at(1_000_000, 0), at(2_000_000, 0));
+        super::set_directory(scratch.join("d"), &timestamps).unwrap();
+
+        let metadata = std::fs::metadata(scratch.join("d")).unwrap();
+        assert_eq!(metadata.accessed().unwrap(), at(1_000_000, 0));
+        assert_eq!(metadata.modified().unwrap(), at(2_000_000, 0));
+    }
+
+    // Creation times are only read when they're asked for.
+    #[test]
+    fn reads_the_creation_time_on_request() {
+        let scratch = Scratch::unique("timestamps-created");
+        scratch.declare(&[("a", Element::File("a"))]);
+
+        let metadata = std::fs::metadata(scratch.join("a")).unwrap();
+        let read = Timestamps::read(&metadata, scratch.join("a")).unwrap();
+        assert_eq!(read.created, None);
+
+        let created = read.with_created(&metadata, scratch.join("a")).created;
+        assert_eq!(created, metadata.created().ok());
+    }
+}
@@ -0,0 +1,374 @@
+// Changes made to the content of a file as it is copied, for text files that move between Windows
+// and unix checkouts or that are filled in from the environment as they are deployed. A file with
+// a NUL byte in its first 8000 bytes is taken to be binary and is copied as it is, which is the
+// same test git uses.
+use std::io::{Read, Write};
+
+const SNIFF: usize = 8000;
+
+#[derive(Clone, Copy)]
+pub enum LineEndings {
+    Lf,
+    Crlf,
+}
+
+#[derive(Default)]
+pub struct Transform {
+    pub line_endings: Option<LineEndings>,
+    pub variables: Option<std::collections::BTreeMap<String, String>>,
+}
+
+impl Transform {
+    pub fn is_identity(&self) -> bool {
+        self.line_endings.is_none() && self.variables.is_none()
+    }
+}
+
+// Copies source to dest with the transform applied, returning the number of bytes written.
+// Placeholders are expanded first, so the line endings in the value of a variable are rewritten
+// along with the rest.
+pub fn copy<R: Read, W: Write>(
+    source: &mut R,
+    dest: &mut W,
+    transform: &Transform,
+    buffer_size: usize,
+) -> std::io::Result<u64> {
+    let mut buffer = vec![0; buffer_size.max(SNIFF)];
+
+    let mut filled = 0;
+    while filled < SNIFF {
+        match read(source, &mut buffer[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+
+    if buffer[..filled].contains(&0) {
+        dest.write_all(&buffer[..filled])?;
+        return Ok(filled as u64 + std::io::copy(source, dest)?);
+    }
+
+    let mut expander = transform.variables.as_ref().map(Expander::new);
+    let mut endings = transform.line_endings.map(Endings::new);
+
+    let mut expanded = Vec::new();
+    let mut converted = Vec::new();
+    let mut written = 0;
+    let mut n = filled;
+
+    loop {
+        expanded.clear();
+        converted.clear();
+
+        let input = match &mut expander {
+            Some(expander) => {
+                match n {
+                    0 => expander.finish(&mut expanded),
+                    n => expander.feed(&buffer[..n], &mut expanded)?,
+                }
+
+                &expanded[..]
+            }
+            None => &buffer[..n],
+        };
+
+        let output = match &mut endings {
+            Some(endings) => {
+                endings.feed(input, &mut converted);
+
+                if n == 0 {
+                    endings.finish(&mut converted);
+                }
+
+                &converted[..]
+            }
+            None => input,
+        };
+
+        dest.write_all(output)?;
+        written += output.len() as u64;
+
+        if n == 0 {
+            return Ok(written);
+        }
+
+        n = read(source, &mut buffer)?;
+    }
+}
+
+fn read<R: Read>(source: &mut R, buffer: &mut [u8]) -> std::io::Result<usize> {
+    loop {
+        match source.read(buffer) {
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+// Rewrites line endings. A carriage return that isn't part of a line ending is left alone either
+// way, and with LF endings one is held back until it is known whether a line feed follows.
+struct Endings {
+    endings: LineEndings,
+    carriage_return: bool,
+}
+
+impl Endings {
+    fn new(endings: LineEndings) -> Endings {
+        Endings {
+            endings,
+            carriage_return: false,
+        }
+    }
+
+    fn feed(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        for &b in input {
+            match self.endings {
+                LineEndings::Lf => {
+                    if self.carriage_return && b != b'\n' {
+                        out.push(b'\r');
+                    }
+
+                    if b != b'\r' {
+                        out.push(b);
+                    }
+                }
+                LineEndings::Crlf => {
+                    if b == b'\n' && !self.carriage_return {
+                        out.push(b'\r');
+                    }
+
+                    out.push(b);
+                }
+            }
+
+            self.carriage_return = b == b'\r';
+        }
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        if self.carriage_return && matches!(self.endings, LineEndings::Lf) {
+            out.push(b'\r');
+        }
+    }
+}
+
+// Expands ${NAME} placeholders, where NAME is made up of letters, digits and '_'. '$$' stands for a
+// single '$', so '$${NAME}' is written as '${NAME}'. A placeholder for a variable that isn't set is
+// an error rather than being left in place or emptied, as either would deploy a broken file
+// without saying so. Anything else, including a '${' that isn't closed, is copied as it is.
+struct Expander<'a> {
+    variables: &'a std::collections::BTreeMap<String, String>,
+    state: State,
+}
+
+enum State {
+    Text,
+    Dollar,
+    Name(Vec<u8>),
+}
+
+impl<'a> Expander<'a> {
+    fn new(variables: &'a std::collections::BTreeMap<String, String>) -> Expander<'a> {
+        Expander {
+            variables,
+            state: State::Text,
+        }
+    }
+
+    fn feed(&mut self, input: &[u8], out: &mut Vec<u8>) -> std::io::Result<()> {
+        for &b in input {
+            self.state = match (std::mem::replace(&mut self.state, State::Text), b) {
+                (State::Text, b'$') => State::Dollar,
+                (State::Text, b) => {
+                    out.push(b);
+                    State::Text
+                }
+                (State::Dollar, b'$') => {
+                    out.push(b'$');
+                    State::Text
+                }
+                (State::Dollar, b'{') => State::Name(Vec::new()),
+                (State::Dollar, b) => {
+                    out.extend_from_slice(&[b'$', b]);
+                    State::Text
+                }
+                (State::Name(name), b'}') if !name.is_empty() => {
+                    let name = String::from_utf8_lossy(&name);
+
+                    match self.variables.get(name.as_ref()) {
+                        Some(value) => out.extend_from_slice(value.as_bytes()),
+                        None => {
+                            return Err(std::io::Error::other(format!(
+                                "variable '{}' is not set",
+                                name
+                            )))
+                        }
+                    }
+
+                    State::Text
+                }
+                (State::Name(mut name), b) if b.is_ascii_alphanumeric() || b == b'_' => {
+                    name.push(b);
+                    State::Name(name)
+                }
+                (State::Name(name), b) => {
+                    out.extend_from_slice(b"${");
+                    out.extend_from_slice(&name);
+
+                    match b {
+                        b'$' => State::Dollar,
+                        b => {
+                            out.push(b);
+                            State::Text
+                        }
+                    }
+                }
+            };
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        match std::mem::replace(&mut self.state, State::Text) {
+            State::Text => (),
+            State::Dollar => out.push(b'$'),
+            State::Name(name) => {
+                out.extend_from_slice(b"${");
+                out.extend_from_slice(&name);
+            }
+        }
+    }
+}
+
+// Whether name can be used in a placeholder, and so given to --define.
+pub fn is_variable_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LineEndings, Transform};
+
+    // A reader that hands out one byte at a time, so that every sequence is split across reads.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl std::io::Read for Trickle<'_> {
+        fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+            match (self.0.split_first(), buffer.first_mut()) {
+                (Some((&b, rest)), Some(first)) => {
+                    *first = b;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    fn copy(input: &[u8], transform: &Transform) -> Vec<u8> {
+        let mut whole = Vec::new();
+        let written = super::copy(&mut &input[..], &mut whole, transform, 0).unwrap();
+        assert_eq!(written, whole.len() as u64);
+
+        let mut trickled = Vec::new();
+        super::copy(&mut Trickle(input), &mut trickled, transform, 0).unwrap();
+        assert_eq!(whole, trickled, "{:?}", String::from_utf8_lossy(input));
+
+        whole
+    }
+
+    fn endings(endings: LineEndings) -> Transform {
+        Transform {
+            line_endings: Some(endings),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn converts_line_endings() {
+        let lf = endings(LineEndings::Lf);
+        let crlf = endings(LineEndings::Crlf);
+
+        assert_eq!(copy(b"a\r\nb\nc\r\n", &lf), b"a\nb\nc\n");
+        assert_eq!(copy(b"a\r\nb\nc\n", &crlf), b"a\r\nb\r\nc\r\n");
+    }
+
+    #[test]
+    fn leaves_lone_carriage_returns() {
+        let lf = endings(LineEndings::Lf);
+        let crlf = endings(LineEndings::Crlf);
+
+        assert_eq!(copy(b"a\rb\r", &lf), b"a\rb\r");
+        assert_eq!(copy(b"a\r\rb\r\r\n", &lf), b"a\r\rb\r\n");
+        assert_eq!(copy(b"a\rb\r", &crlf), b"a\rb\r");
+    }
+
+    #[test]
+    fn copies_binary_files_as_they_are() {
+        let lf = endings(LineEndings::Lf);
+
+        let binary = b"a\r\n\0".to_vec();
+        assert_eq!(copy(&binary, &lf), binary);
+
+        // Only the first SNIFF bytes are looked at for a NUL.
+        let mut binary = vec![b'a'; super::SNIFF - 1];
+        binary.extend_from_slice(b"\0\r\n");
+        assert_eq!(copy(&binary, &lf), binary);
+
+        let mut text = vec![b'a'; super::SNIFF];
+        text.extend_from_slice(b"\0\r\n");
+        assert_eq!(copy(&text, &lf).len(), text.len() - 1);
+    }
+
+    fn expanding(variables: &[(&str, &str)]) -> Transform {
+        let variables = variables
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()));
+
+        Transform {
+            variables: Some(variables.collect()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn expands_variables() {
+        let transform = expanding(&[("X", "1"), ("LONG_NAME_2", "two")]);
+
+        assert_eq!(copy(b"${X}", &transform), b"1");
+        assert_eq!(copy(b"a${X}b${LONG_NAME_2}c", &transform), b"a1btwoc");
+        assert_eq!(copy(b"$X ${} $", &transform), b"$X ${} $");
+    }
+
+    #[test]
+    fn escapes_dollars() {
+        let transform = expanding(&[("X", "1")]);
+
+        assert_eq!(copy(b"$$", &transform), b"$");
+        assert_eq!(copy(b"$${X}", &transform), b"${X}");
+        assert_eq!(copy(b"$$$${X}", &transform), b"$${X}");
+        assert_eq!(copy(b"$$${X}", &transform), b"$1");
+    }
+
+    #[test]
+    fn copies_unclosed_placeholders() {
+        let transform = expanding(&[("X", "1")]);
+
+        assert_eq!(copy(b"${X", &transform), b"${X");
+        assert_eq!(copy(b"${X-${X}", &transform), b"${X-1");
+        assert_eq!(copy(b"${X${X}", &transform), b"${X1");
+    }
+
+    #[test]
+    fn refuses_missing_variables() {
+        let transform = expanding(&[]);
+        let missing = |e: std::io::Error| e.to_string() == "variable 'MISSING' is not set";
+
+        let whole = super::copy(&mut &b"a${MISSING}b"[..], &mut Vec::new(), &transform, 0);
+        assert!(whole.is_err_and(missing));
+
+        let trickled = super::copy(&mut Trickle(b"${MISSING}"), &mut Vec::new(), &transform, 0);
+        assert!(trickled.is_err_and(missing));
+    }
+}
@@ -0,0 +1,205 @@
+// The options of install that only have a meaning on unix: -m sets the mode of what is installed,
+// -o and -g its owner and group, and -s strips symbols from it. Elsewhere they are accepted and
+// ignored.
+use std::os::unix::fs::PermissionsExt;
+
+// Resolves the options as given on the command line, returning a message for the first one that
+// isn't valid.
+pub fn attributes(
+    mode: Option<&str>,
+    owner: Option<&str>,
+    group: Option<&str>,
+    strip: Option<&std::ffi::OsStr>,
+) -> Result<crate::Attributes, String> {
+    if let Some(mode) = mode {
+        if parse_mode(mode, false).is_none() {
+            return Err(format!("invalid mode '{}'", mode));
+        }
+    }
+
+    let owner = match owner {
+        Some(name) => Some(lookup(getpwnam, name).ok_or(format!("invalid user '{}'", name))?),
+        None => None,
+    };
+
+    let group = match group {
+        Some(name) => Some(lookup(getgrnam, name).ok_or(format!("invalid group '{}'", name))?),
+        None => None,
+    };
+
+    // As with GNU install, what is installed is given mode 0755 unless -m says otherwise.
+    Ok(crate::Attributes {
+        mode: Some(mode.unwrap_or("0755").to_string()),
+        owner,
+        group,
+        strip: strip.map(std::ffi::OsStr::to_os_string),
+    })
+}
+
+// Strips p with the strip program, if stripping was asked for.
+pub fn strip<P: AsRef<std::path::Path>>(
+    p: P,
+    attributes: &crate::Attributes,
+) -> std::io::Result<()> {
+    let Some(program) = &attributes.strip else {
+        return Ok(());
+    };
+
+    // A relative name starting with '-' would be taken as an option by strip.
+    let path = match p.as_ref().to_string_lossy().starts_with('-') {
+        true => std::path::Path::new(".").join(p.as_ref()),
+        false => p.as_ref().to_path_buf(),
+    };
+
+    let status = std::process::Command::new(program).arg(path).status()?;
+
+    match status.success() {
+        true => Ok(()),
+        false => Err(std::io::Error::other("strip process terminated abnormally")),
+    }
+}
+
+// Changes the owner and group of p and then its mode, in that order because changing the owner can
+// clear the setuid and setgid bits.
+pub fn apply<P: AsRef<std::path::Path>>(
+    p: P,
+    attributes: &crate::Attributes,
+    directory: bool,
+) -> std::io::Result<()> {
+    if attributes.owner.is_some() || attributes.group.is_some() {
+        std::os::unix::fs::chown(p.as_ref(), attributes.owner, attributes.group)?;
+    }
+
+    if let Some(mode) = attributes
+        .mode
+        .as_deref()
+        .and_then(|m| parse_mode(m, directory))
+    {
+        std::fs::set_permissions(p.as_ref(), std::fs::Permissions::from_mode(mode))?;
+    }
+
+    Ok(())
+}
+
+// Parses a mode as chmod does, either in octal or as symbolic clauses such as 'u=rwx,go=rx'. As
+// with GNU install, a symbolic mode starts from no permissions at all rather than from those of
+// the file, and 'X' only grants execute permission to directories.
+fn parse_mode(mode: &str, directory: bool) -> Option<u32> {
+    if !mode.is_empty() && mode.chars().all(|c| c.is_digit(8)) {
+        return u32::from_str_radix(mode, 8).ok().filter(|&m| m <= 0o7777);
+    }
+
+    let mut result = 0;
+
+    for clause in mode.split(',') {
+        let operator = clause.find(['+', '-', '='])?;
+        let (who, mut actions) = clause.split_at(operator);
+
+        let mut mask = 0;
+        for c in who.chars() {
+            mask |= match c {
+                'u' => 0o4700,
+                'g' => 0o2070,
+                'o' => 0o1007,
+                'a' => 0o7777,
+                _ => return None,
+            };
+        }
+
+        if mask == 0 {
+            mask = 0o7777;
+        }
+
+        while let Some(operator) = actions.chars().next() {
+            let end = actions[1..]
+                .find(['+', '-', '='])
+                .map_or(actions.len(), |i| i + 1);
+
+            let mut bits = 0;
+            for c in actions[1..end].chars() {
+                bits |= match c {
+                    'r' => 0o444,
+                    'w' => 0o222,
+                    'x' => 0o111,
+                    'X' if directory => 0o111,
+                    'X' => 0,
+                    's' => 0o6000,
+                    't' => 0o1000,
+                    _ => return None,
+                };
+            }
+
+            match operator {
+                '+' => result |= bits & mask,
+                '-' => result &= !(bits & mask),
+                _ => result = (result & !mask) | (bits & mask),
+            }
+
+            actions = &actions[end..];
+        }
+    }
+
+    Some(result)
+}
+
+// The fields that struct passwd and struct group start with. Only the id after them is read, and
+// both are only ever used through a pointer returned by the C library.
+#[repr(C)]
+struct Entry {
+    name: *const std::ffi::c_char,
+    passwd: *const std::ffi::c_char,
+    id: u32,
+}
+
+extern "C" {
+    fn getpwnam(name: *const std::ffi::c_char) -> *const Entry;
+    fn getgrnam(name: *const std::ffi::c_char) -> *const Entry;
+}
+
+// Finds the id of name with getpwnam or getgrnam, so that users and groups from any source the
+// system is configured with are found. A name that isn't known is taken as a numeric id.
+fn lookup(
+    find: unsafe extern "C" fn(*const std::ffi::c_char) -> *const Entry,
+    name: &str,
+) -> Option<u32> {
+    let known = std::ffi::CString::new(name).ok().and_then(|c| {
+        // SAFETY: c is a valid NUL terminated string, and the entry returned (if any) stays valid
+        // until the next lookup, which can't happen before its id has been copied out.
+        unsafe { find(c.as_ptr()).as_ref().map(|entry| entry.id) }
+    });
+
+    known.or_else(|| name.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn looks_up_users_and_groups() {
+        assert_eq!(super::lookup(super::getpwnam, "root"), Some(0));
+        assert_eq!(super::lookup(super::getgrnam, "root"), Some(0));
+    }
+
+    #[test]
+    fn takes_an_unknown_name_as_an_id() {
+        assert_eq!(super::lookup(super::getpwnam, "4242"), Some(4242));
+        assert_eq!(super::lookup(super::getgrnam, "no such group"), None);
+        assert_eq!(super::lookup(super::getpwnam, "a\0b"), None);
+    }
+
+    #[test]
+    fn defaults_the_mode() {
+        let attributes = super::attributes(None, None, None, None).unwrap();
+        assert_eq!(attributes.mode.as_deref(), Some("0755"));
+
+        let attributes = super::attributes(Some("u=rw"), None, None, None).unwrap();
+        assert_eq!(attributes.mode.as_deref(), Some("u=rw"));
+    }
+
+    #[test]
+    fn rejects_unknown_users() {
+        assert_eq!(
+            super::attributes(None, Some("no such user"), None, None).err(),
+            Some("invalid user 'no such user'".to_string())
+        );
+    }
+}
@@ -0,0 +1,257 @@
+pub const MODIFIED: i32 = 2;
+pub const MISSING: i32 = 4;
+pub const EXTRA: i32 = 8;
+
+// Checks the files under root against a list of checksums in the format written by sha256sum
+// ("HASH  PATH", or "HASH *PATH"), reporting files that are modified, missing or present but not
+// listed. The exit code has a bit set for each kind of difference found, or is 1 if the check
+// could not be carried out.
+pub fn tree<C: AsRef<std::path::Path>, R: AsRef<std::path::Path>>(checksums: C, root: R) -> i32 {
+    let content = match std::fs::read_to_string(checksums.as_ref()) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!(
+                "winstall: cannot read checksums {}: {}",
+                crate::quoting::quote(checksums.as_ref()),
+                e
+            );
+
+            return 1;
+        }
+    };
+
+    let mut listed = std::collections::BTreeMap::<std::path::PathBuf, String>::new();
+
+    for (number, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let parsed = line.split_once(' ').and_then(|(hash, path)| {
+            let path = path.strip_prefix([' ', '*'])?;
+            let path = path.strip_prefix("./").unwrap_or(path);
+
+            match crate::sha256::is_hex_digest(hash) && !path.is_empty() {
+                true => Some((hash.to_ascii_lowercase(), path)),
+                false => None,
+            }
+        });
+
+        match parsed {
+            Some((hash, path)) => _ = listed.insert(root.as_ref().join(path), hash),
+            None => {
+                eprintln!(
+                    "winstall: {}:{}: improperly formatted checksum line",
+                    crate::quoting::quote(checksums.as_ref()),
+                    number + 1
+                );
+
+                return 1;
+            }
+        }
+    }
+
+    let mut present = std::collections::BTreeSet::new();
+    if let Err(e) = walk(root.as_ref(), &mut present) {
+        eprintln!(
+            "winstall: cannot read directory {}: {}",
+            crate::quoting::quote(root.as_ref()),
+            e
+        );
+
+        return 1;
+    }
+
+    // The checksums file may well live in the tree it describes.
+    if let Ok(checksums) = std::fs::canonicalize(checksums.as_ref()) {
+        present.retain(|p| std::fs::canonicalize(p).map_or(true, |p| p != checksums));
+    }
+
+    let mut ok = 0;
+    let (mut modified, mut missing) = (Vec::new(), Vec::new());
+
+    for (path, expected) in &listed {
+        match crate::sha256::file(path) {
+            Ok(actual) if &actual == expected => ok += 1,
+            Ok(_) => modified.push(path),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => missing.push(path),
+            Err(e) => {
+                eprintln!(
+                    "winstall: cannot read {}: {}",
+                    crate::quoting::quote(path),
+                    e
+                );
+                return 1;
+            }
+        }
+    }
+
+    let mut extra = present
+        .iter()
+        .filter(|p| !listed.contains_key(*p))
+        .collect::<Vec<_>>();
+
+    for (label, paths) in [
+        ("MODIFIED", &mut modified),
+        ("MISSING", &mut missing),
+        ("EXTRA", &mut extra),
+    ] {
+        paths.sort_by(|a, b| crate::paths::natural_cmp(a, b));
+
+        for path in paths.iter() {
+            println!("{} {}", label, crate::quoting::quote(path));
+        }
+    }
+
+    println!(
+        "winstall: {} ok, {} modified, {} missing, {} extra",
+        ok,
+        modified.len(),
+        missing.len(),
+        extra.len()
+    );
+
+    let mut code = 0;
+
+    for (paths, bit) in [(modified, MODIFIED), (missing, MISSING), (extra, EXTRA)] {
+        if !paths.is_empty() {
+            code |= bit;
+        }
+    }
+
+    code
+}
+
+fn walk(
+    p: &std::path::Path,
+    files: &mut std::collections::BTreeSet<std::path::PathBuf>,
+) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(p)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+
+        if file_type.is_dir() {
+            walk(&entry.path(), files)?;
+        } else {
+            files.insert(entry.path());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scratch::{Element, Scratch};
+
+    fn digest(content: &str) -> String {
+        let mut hasher = crate::sha256::Sha256::new();
+        hasher.update(content.as_bytes());
+        crate::sha256::hex(&hasher.finish())
+    }
+
+    // A tree under "root" with a file at the top and one in a directory, and a checksums file
+    // beside it that lists them both.
+    fn installed(prefix: &str) -> Scratch {
+        let scratch = Scratch::unique(prefix);
+        scratch.declare(&[(
+            "root",
+            Element::Directory(vec![
+                ("a", Element::File("alpha")),
+                (
+                    "dir",
+                    Element::Directory(vec![("b", Element::File("beta"))]),
+                ),
+            ]),
+        )]);
+
+        let sums = format!("{}  a\n{}  dir/b\n", digest("alpha"), digest("beta"));
+        std::fs::write(scratch.join("sums"), sums).unwrap();
+
+        scratch
+    }
+
+    fn verify(scratch: &Scratch) -> i32 {
+        super::tree(scratch.join("sums"), scratch.join("root"))
+    }
+
+    #[test]
+    fn passes_an_unchanged_tree() {
+        assert_eq!(verify(&installed("verify-unchanged")), 0);
+    }
+
+    #[test]
+    fn reports_each_kind_of_difference() {
+        let scratch = installed("verify-modified");
+        std::fs::write(scratch.join("root/a"), "changed").unwrap();
+        assert_eq!(verify(&scratch), super::MODIFIED);
+
+        let scratch = installed("verify-missing");
+        std::fs::remove_file(scratch.join("root/dir/b")).unwrap();
+        assert_eq!(verify(&scratch), super::MISSING);
+
+        let scratch = installed("verify-extra");
+        std::fs::write(scratch.join("root/dir/c"), "gamma").unwrap();
+        assert_eq!(verify(&scratch), super::EXTRA);
+    }
+
+    #[test]
+    fn combines_the_kinds_of_difference() {
+        let scratch = installed("verify-combined");
+        std::fs::write(scratch.join("root/a"), "changed").unwrap();
+        std::fs::remove_file(scratch.join("root/dir/b")).unwrap();
+        std::fs::write(scratch.join("root/c"), "gamma").unwrap();
+
+        assert_eq!(
+            verify(&scratch),
+            super::MODIFIED | super::MISSING | super::EXTRA
+        );
+    }
+
+    #[test]
+    fn fails_on_an_improperly_formatted_line() {
+        for line in [
+            "not a checksum  a",
+            "0123  a",
+            &format!("{} a", digest("alpha")),
+        ] {
+            let scratch = installed("verify-malformed");
+            let sums = std::fs::read_to_string(scratch.join("sums")).unwrap();
+            std::fs::write(scratch.join("sums"), format!("{}{}\n", sums, line)).unwrap();
+
+            assert_eq!(verify(&scratch), 1, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn fails_without_a_checksums_file() {
+        let scratch = installed("verify-no-checksums");
+        std::fs::remove_file(scratch.join("sums")).unwrap();
+
+        assert_eq!(verify(&scratch), 1);
+    }
+
+    // Binary mode entries and paths starting with "./", as sha256sum writes them, name the same
+    // files as plain ones. Upper case digests and blank lines are accepted too.
+    #[test]
+    fn reads_the_forms_sha256sum_writes() {
+        let scratch = installed("verify-forms");
+        let sums = format!(
+            "{} *a\n\n{}  ./dir/b\n",
+            digest("alpha").to_ascii_uppercase(),
+            digest("beta")
+        );
+        std::fs::write(scratch.join("sums"), sums).unwrap();
+
+        assert_eq!(verify(&scratch), 0);
+    }
+
+    #[test]
+    fn leaves_out_a_checksums_file_inside_the_tree() {
+        let scratch = installed("verify-inside");
+        std::fs::rename(scratch.join("sums"), scratch.join("root/SHA256SUMS")).unwrap();
+
+        let code = super::tree(scratch.join("root/SHA256SUMS"), scratch.join("root"));
+        assert_eq!(code, 0);
+    }
+}
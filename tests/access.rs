@@ -0,0 +1,108 @@
+mod support;
+
+use support::{Element, Entry, Scratch};
+
+#[test]
+fn installing_into_a_directory_that_cannot_be_written_fails() {
+    let scratch = Scratch::unique("access-install");
+    scratch.declare(&[("source", Element::File("content"))]);
+
+    let Some(denied) = scratch.unwritable("denied") else {
+        eprintln!("skipped: this process can write to any directory");
+        return;
+    };
+
+    let run = support::run(&scratch, ["source".as_ref(), denied.as_os_str()]);
+
+    assert_eq!(run.code, Some(1));
+    assert!(
+        run.stderr.contains("cannot open file to write"),
+        "{}",
+        run.stderr
+    );
+    assert!(std::fs::read_dir(&denied).unwrap().next().is_none());
+}
+
+#[test]
+fn creating_a_directory_where_it_cannot_be_written_fails() {
+    let scratch = Scratch::unique("access-directory");
+
+    let Some(denied) = scratch.unwritable("denied") else {
+        eprintln!("skipped: this process can write to any directory");
+        return;
+    };
+
+    let run = support::run(&scratch, ["-d".as_ref(), denied.join("a/b").as_os_str()]);
+
+    assert_eq!(run.code, Some(1));
+    assert!(
+        run.stderr.contains("cannot create directory") && run.stderr.contains("Permission denied"),
+        "{}",
+        run.stderr
+    );
+}
+
+#[test]
+fn backups_are_not_made_where_they_cannot_be_written() {
+    let scratch = Scratch::unique("access-backup");
+    scratch.declare(&[("source", Element::File("new"))]);
+
+    let Some(denied) = scratch.unwritable("denied") else {
+        eprintln!("skipped: this process can write to any directory");
+        return;
+    };
+
+    let run = support::run(
+        &scratch,
+        [
+            "-b".as_ref(),
+            "source".as_ref(),
+            denied.join("dest").as_os_str(),
+        ],
+    );
+
+    assert_eq!(run.code, Some(1));
+    assert!(!denied.join("dest").exists());
+}
+
+// Unix permissions don't stop a file from being replaced, only from being written to.
+#[cfg(unix)]
+#[test]
+fn a_read_only_destination_is_replaced() {
+    let scratch = Scratch::unique("access-read-only");
+    scratch.declare(&[
+        ("source", Element::File("new")),
+        ("dest", Element::ReadOnly(Box::new(Element::File("old")))),
+    ]);
+
+    let run = support::run(&scratch, ["source", "dest"]);
+
+    assert_eq!(run.code, Some(0), "{}", run.stderr);
+    assert_eq!(scratch.tree()["dest"], Entry::file("new"));
+}
+
+#[cfg(windows)]
+#[test]
+fn a_read_only_destination_is_only_replaced_with_force() {
+    let scratch = Scratch::unique("access-read-only");
+    scratch.declare(&[
+        ("source", Element::File("new")),
+        ("dest", Element::ReadOnly(Box::new(Element::File("old")))),
+    ]);
+
+    let run = support::run(&scratch, ["source", "dest"]);
+
+    assert_eq!(run.code, Some(1));
+    assert!(run
+        .stderr
+        .contains("destination is read-only (use --force)"));
+    assert_eq!(scratch.tree()["dest"], Entry::file("old"));
+
+    let run = support::run(&scratch, ["--force", "source", "dest"]);
+
+    assert_eq!(run.code, Some(0), "{}", run.stderr);
+    assert_eq!(scratch.tree()["dest"], Entry::file("new"));
+
+    let metadata = std::fs::metadata(scratch.join("dest")).unwrap();
+    assert!(metadata.permissions().readonly());
+}
@@ -0,0 +1,153 @@
+mod support;
+
+use support::{Element, Scratch};
+
+// A scratch directory whose config file, which is where winstall looks for it in the tests, sets
+// each of the options the precedence tests look at.
+fn configured(prefix: &str) -> Scratch {
+    let scratch = Scratch::unique(prefix);
+    scratch.declare(&[(
+        ".config",
+        Element::Directory(vec![(
+            "winstall",
+            Element::Directory(vec![(
+                "config.toml",
+                Element::File(concat!(
+                    "backup = \"simple\"\n",
+                    "suffix = \".cfg\"\n",
+                    "backup-mode = \"copy\"\n",
+                    "verbose = true\n",
+                )),
+            )]),
+        )]),
+    )]);
+
+    scratch
+}
+
+// The environment the precedence tests set, each variable overriding one of the config settings.
+const ENVIRONMENT: [(&str, &str); 4] = [
+    ("VERSION_CONTROL", "numbered"),
+    ("SIMPLE_BACKUP_SUFFIX", ".env"),
+    ("WINSTALL_BACKUP_MODE", "rename"),
+    ("WINSTALL_VERBOSE", "stderr"),
+];
+
+fn show_config(scratch: &Scratch, environment: &[(&str, &str)], args: &[&str]) -> String {
+    let mut command = support::winstall(scratch);
+    command.arg("--show-config").args(args);
+    command.envs(environment.iter().copied());
+
+    let run = support::finish(&mut command);
+    assert_eq!(run.code, Some(0), "{}", run.stderr);
+
+    run.stdout
+}
+
+fn assert_shows(shown: &str, expected: &[&str]) {
+    for line in expected {
+        assert!(shown.lines().any(|l| l == *line), "{:?} in {}", line, shown);
+    }
+}
+
+#[test]
+fn uses_the_config_file_when_nothing_else_is_given() {
+    let scratch = configured("config-only");
+
+    assert_shows(
+        &show_config(&scratch, &[], &["-b"]),
+        &[
+            "backup: simple (suffix '.cfg')",
+            "backup mode: copy",
+            "verbose: yes (stdout)",
+        ],
+    );
+}
+
+#[test]
+fn prefers_the_environment_to_the_config_file() {
+    let scratch = configured("config-environment");
+
+    assert_shows(
+        &show_config(&scratch, &ENVIRONMENT, &["-b"]),
+        &[
+            "backup: numbered",
+            "backup mode: rename",
+            "verbose: yes (stderr)",
+        ],
+    );
+
+    // The suffix only shows with simple backups, which the environment can ask for too.
+    let environment = [("VERSION_CONTROL", "simple"), ENVIRONMENT[1]];
+    assert_shows(
+        &show_config(&scratch, &environment, &["-b"]),
+        &["backup: simple (suffix '.env')"],
+    );
+}
+
+#[test]
+fn prefers_the_command_line_to_the_environment() {
+    let scratch = configured("config-command-line");
+    let args = [
+        "--backup=existing",
+        "--suffix=.cli",
+        "--backup-mode=copy",
+        "--verbose=stdout",
+    ];
+
+    assert_shows(
+        &show_config(&scratch, &ENVIRONMENT, &args),
+        &[
+            "backup: existing (suffix '.cli')",
+            "backup mode: copy",
+            "verbose: yes (stdout)",
+        ],
+    );
+}
+
+#[test]
+fn ignores_the_config_file_and_environment_with_no_env() {
+    let scratch = configured("config-no-env");
+
+    assert_shows(
+        &show_config(&scratch, &ENVIRONMENT, &["-b", "--no-env"]),
+        &[
+            "backup: existing (suffix '~')",
+            "backup mode: rename",
+            "verbose: no",
+            "environment: ignored (--no-env)",
+        ],
+    );
+}
+
+// Patterns given on the command line replace those from the environment, rather than adding to
+// them, as a single value would be replaced.
+#[test]
+fn prefers_lists_on_the_command_line_to_the_environment() {
+    let scratch = Scratch::unique("config-lists");
+    scratch.declare(&[
+        ("app.exe", Element::File("app")),
+        ("app.pdb", Element::File("symbols")),
+        ("notes.txt", Element::File("notes")),
+        ("target", Element::Directory(vec![])),
+    ]);
+
+    let mut command = support::winstall(&scratch);
+    command
+        .args([
+            "--exclude=*.txt",
+            "-t",
+            "target",
+            "app.exe",
+            "app.pdb",
+            "notes.txt",
+        ])
+        .env("WINSTALL_EXCLUDE", "*.pdb");
+
+    let run = support::finish(&mut command);
+    assert_eq!(run.code, Some(0), "{}", run.stderr);
+
+    assert!(scratch.join("target/app.exe").exists());
+    assert!(scratch.join("target/app.pdb").exists());
+    assert!(!scratch.join("target/notes.txt").exists());
+}
@@ -0,0 +1,778 @@
+mod support;
+
+use support::{Element, Entry, Scenario};
+
+#[test]
+fn copies_a_file_to_a_file() {
+    Scenario {
+        given: vec![("source", Element::File("content"))],
+        args: vec!["-v", "source", "dest"],
+        stdout: vec!["'source' -> 'dest'"],
+        leaves: vec![
+            ("dest", Entry::file("content")),
+            ("source", Entry::file("content")),
+        ],
+        ..Default::default()
+    }
+    .run();
+}
+
+#[test]
+fn copies_several_files_into_a_directory() {
+    Scenario {
+        given: vec![
+            ("a", Element::File("first")),
+            ("b", Element::File("second")),
+            ("target", Element::Directory(vec![])),
+        ],
+        args: vec!["a", "b", "target"],
+        leaves: vec![
+            ("a", Entry::file("first")),
+            ("b", Entry::file("second")),
+            ("target", Entry::Directory),
+            ("target/a", Entry::file("first")),
+            ("target/b", Entry::file("second")),
+        ],
+        ..Default::default()
+    }
+    .run();
+}
+
+#[test]
+fn replaces_an_existing_destination() {
+    Scenario {
+        given: vec![
+            ("source", Element::File("new")),
+            ("dest", Element::File("old")),
+        ],
+        args: vec!["source", "dest"],
+        leaves: vec![("dest", Entry::file("new")), ("source", Entry::file("new"))],
+        ..Default::default()
+    }
+    .run();
+}
+
+#[test]
+fn keeps_a_backup_of_the_replaced_destination() {
+    Scenario {
+        given: vec![
+            ("source", Element::File("new")),
+            ("dest", Element::File("old")),
+        ],
+        args: vec!["-v", "--backup=simple", "--suffix=.bak", "source", "dest"],
+        stdout: vec!["(backup: 'dest.bak')"],
+        leaves: vec![
+            ("dest", Entry::file("new")),
+            ("dest.bak", Entry::file("old")),
+            ("source", Entry::file("new")),
+        ],
+        ..Default::default()
+    }
+    .run();
+}
+
+#[test]
+fn leaves_existing_destinations_alone_with_no_clobber() {
+    Scenario {
+        given: vec![
+            ("source", Element::File("new")),
+            ("dest", Element::File("old")),
+        ],
+        args: vec!["-n", "source", "dest"],
+        code: 2,
+        stderr: vec!["not replacing 'dest'"],
+        leaves: vec![("dest", Entry::file("old")), ("source", Entry::file("new"))],
+        ..Default::default()
+    }
+    .run();
+}
+
+#[test]
+fn creates_directories() {
+    Scenario {
+        args: vec!["-d", "a/b/c", "d"],
+        leaves: vec![
+            ("a", Entry::Directory),
+            ("a/b", Entry::Directory),
+            ("a/b/c", Entry::Directory),
+            ("d", Entry::Directory),
+        ],
+        ..Default::default()
+    }
+    .run();
+}
+
+// With -p and --reference, the directories that -d creates take the time of the reference, while
+// those that were already there are left to the filesystem.
+#[test]
+fn sets_the_times_of_created_directories_only() {
+    let scratch = support::Scratch::unique("directory-times");
+    scratch.declare(&[
+        ("reference", Element::File("content")),
+        ("a", Element::Directory(vec![])),
+    ]);
+
+    let modified = |name: &str| {
+        let metadata = std::fs::metadata(scratch.join(name)).unwrap();
+        metadata.modified().unwrap()
+    };
+
+    let reference = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+    let file = std::fs::File::options()
+        .write(true)
+        .open(scratch.join("reference"))
+        .unwrap();
+    file.set_modified(reference).unwrap();
+
+    let run = support::run(&scratch, ["-d", "-p", "--reference=reference", "a/b/c"]);
+    assert_eq!(run.code, Some(0), "{}", run.stderr);
+
+    assert_ne!(modified("a"), reference);
+    assert_eq!(modified("a/b"), reference);
+    assert_eq!(modified("a/b/c"), reference);
+}
+
+// With --sync=final the files are flushed once they've all been installed, which leaves the same
+// tree as any other install.
+#[test]
+fn installs_several_files_with_one_final_sync() {
+    Scenario {
+        given: vec![
+            ("a", Element::File("first")),
+            ("b", Element::File("second")),
+        ],
+        args: vec!["-D", "--sync=final", "a", "b", "-t", "target/nested"],
+        leaves: vec![
+            ("a", Entry::file("first")),
+            ("b", Entry::file("second")),
+            ("target", Entry::Directory),
+            ("target/nested", Entry::Directory),
+            ("target/nested/a", Entry::file("first")),
+            ("target/nested/b", Entry::file("second")),
+        ],
+        ..Default::default()
+    }
+    .run();
+}
+
+// A target directory whose name isn't valid Unicode is used as it was given, whether its value is
+// attached to -t or follows it.
+#[cfg(unix)]
+#[test]
+fn installs_into_a_directory_whose_name_is_not_unicode() {
+    use std::os::unix::ffi::OsStrExt;
+
+    let target = std::ffi::OsStr::from_bytes(b"d\xff");
+    let attached = std::ffi::OsStr::from_bytes(b"-td\xff");
+
+    for args in [vec!["-t".as_ref(), target], vec![attached]] {
+        let scratch = support::Scratch::unique("not-unicode");
+        scratch.declare(&[("source", Element::File("content"))]);
+        std::fs::create_dir(scratch.join(target)).unwrap();
+
+        let run = support::finish(support::winstall(&scratch).args(args).arg("source"));
+        assert_eq!(run.code, Some(0), "{}", run.stderr);
+
+        assert_eq!(
+            std::fs::read(scratch.join(target).join("source")).unwrap(),
+            b"content"
+        );
+    }
+}
+
+// A name after '--' is an operand however much it looks like an option, so a file named
+// --backup=none is installed, and doesn't turn off the backup asked for before it.
+#[test]
+fn installs_a_file_named_like_an_option_after_double_dash() {
+    Scenario {
+        given: vec![
+            ("--backup=none", Element::File("new")),
+            ("dest", Element::File("old")),
+        ],
+        args: vec!["-v", "-b", "--", "--backup=none", "dest"],
+        stdout: vec!["'--backup=none' -> 'dest' (backup: 'dest~')"],
+        leaves: vec![
+            ("--backup=none", Entry::file("new")),
+            ("dest", Entry::file("new")),
+            ("dest~", Entry::file("old")),
+        ],
+        ..Default::default()
+    }
+    .run();
+}
+
+// An option that isn't recognized is an error even when --help follows it, as with GNU install,
+// and nothing is installed.
+#[test]
+fn refuses_an_unrecognized_option_before_help() {
+    Scenario {
+        given: vec![("source", Element::File("content"))],
+        args: vec!["--badopt", "--help", "source", "dest"],
+        code: 1,
+        stderr: vec![
+            "winstall: unrecognized option '--badopt'",
+            "Try 'winstall --help' for more information.",
+        ],
+        leaves: vec![("source", Entry::file("content"))],
+        ..Default::default()
+    }
+    .run();
+}
+
+// The unversioned name is replaced like any other destination: it is backed up first, and the
+// message says which file it now stands for.
+#[test]
+fn backs_up_the_unversioned_name_when_it_is_replaced() {
+    Scenario {
+        given: vec![
+            ("source", Element::File("new")),
+            ("dest", Element::File("old")),
+        ],
+        args: vec![
+            "-v",
+            "-b",
+            "--versioned",
+            "--version-tag=1.0",
+            "source",
+            "dest",
+        ],
+        stdout: vec![
+            "'source' -> 'dest-1.0'",
+            "'dest-1.0' => 'dest' (backup: 'dest~')",
+        ],
+        leaves: vec![
+            ("dest", Entry::file("new")),
+            ("dest-1.0", Entry::file("new")),
+            ("dest~", Entry::file("old")),
+            ("source", Entry::file("new")),
+        ],
+        ..Default::default()
+    }
+    .run();
+}
+
+// With -n an unversioned name that is already there is left alone, and so nothing is installed.
+#[test]
+fn leaves_the_unversioned_name_alone_with_no_clobber() {
+    Scenario {
+        given: vec![
+            ("source", Element::File("new")),
+            ("dest", Element::File("old")),
+        ],
+        args: vec!["-n", "--versioned", "--version-tag=1.0", "source", "dest"],
+        code: 2,
+        stderr: vec!["not replacing 'dest'"],
+        leaves: vec![("dest", Entry::file("old")), ("source", Entry::file("new"))],
+        ..Default::default()
+    }
+    .run();
+}
+
+// Without -m a file is installed with mode 0755 whatever the mode of its source, as with GNU
+// install, while -m replaces it.
+#[cfg(unix)]
+#[test]
+fn sets_the_mode_of_installed_files() {
+    use std::os::unix::fs::PermissionsExt;
+
+    for (args, expected) in [(vec![], 0o755), (vec!["-m", "640"], 0o640)] {
+        let scratch = support::Scratch::unique("mode");
+        scratch.declare(&[("source", Element::File("content"))]);
+
+        let source = scratch.join("source");
+        std::fs::set_permissions(&source, std::fs::Permissions::from_mode(0o600)).unwrap();
+
+        let run = support::finish(
+            support::winstall(&scratch)
+                .args(args)
+                .args(["source", "dest"]),
+        );
+        assert_eq!(run.code, Some(0), "{}", run.stderr);
+
+        let mode = std::fs::metadata(scratch.join("dest"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o7777, expected);
+    }
+}
+
+// Links and --touch-only leave the mode of what they point at, or touch, alone.
+#[cfg(unix)]
+#[test]
+fn leaves_the_mode_of_links_and_touched_files() {
+    use std::os::unix::fs::PermissionsExt;
+
+    for args in [vec!["--link=hard"], vec!["--touch-only"]] {
+        let scratch = support::Scratch::unique("mode-untouched");
+        scratch.declare(&[
+            ("source", Element::File("content")),
+            ("dest", Element::File("content")),
+        ]);
+
+        for name in ["source", "dest"] {
+            let permissions = std::fs::Permissions::from_mode(0o600);
+            std::fs::set_permissions(scratch.join(name), permissions).unwrap();
+        }
+
+        let run = support::finish(
+            support::winstall(&scratch)
+                .args(&args)
+                .args(["source", "dest"]),
+        );
+        assert_eq!(run.code, Some(0), "{}", run.stderr);
+
+        for name in ["source", "dest"] {
+            let mode = std::fs::metadata(scratch.join(name))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o7777, 0o600, "{:?} {}", args, name);
+        }
+    }
+}
+
+// A run is only skipped as unchanged while everything it installed is still there, and while the
+// environment and config file it reads options from are as they were.
+#[test]
+fn skips_a_run_only_while_nothing_has_changed() {
+    let scratch = support::Scratch::unique("unchanged");
+    scratch.declare(&[
+        ("source", Element::File("content")),
+        ("target", Element::Directory(vec![])),
+    ]);
+
+    let runs: [&[&str]; 2] = [
+        &["-v", "--skip-if-unchanged=state", "source", "dest"],
+        &["-v", "--skip-if-unchanged=state", "-t", "target", "source"],
+    ];
+
+    for (args, installed) in runs.into_iter().zip(["dest", "target/source"]) {
+        let skipped = || {
+            let run = support::run(&scratch, args);
+            assert_eq!(run.code, Some(0), "{}", run.stderr);
+            run.stdout.contains("nothing has changed")
+        };
+
+        assert!(!skipped());
+        assert!(skipped());
+
+        std::fs::remove_file(scratch.join(installed)).unwrap();
+        assert!(!skipped());
+        assert!(skipped());
+    }
+
+    // Changing what winstall reads from the environment or config file makes the run go ahead,
+    // after which the same environment is unchanged again.
+    let skipped = |variable: Option<(&str, &str)>| {
+        let mut command = support::winstall(&scratch);
+        command.args(["-v", "--skip-if-unchanged=state", "source", "dest"]);
+        command.envs(variable);
+
+        support::finish(&mut command)
+            .stdout
+            .contains("nothing has changed")
+    };
+
+    assert!(!skipped(None));
+    assert!(!skipped(Some(("WINSTALL_BACKUP_MODE", "copy"))));
+    assert!(skipped(Some(("WINSTALL_BACKUP_MODE", "copy"))));
+    assert!(!skipped(Some(("VERSION_CONTROL", "numbered"))));
+
+    std::fs::create_dir_all(scratch.join(".config/winstall")).unwrap();
+    std::fs::write(
+        scratch.join(".config/winstall/config.toml"),
+        "backup = \"simple\"\n",
+    )
+    .unwrap();
+
+    assert!(!skipped(None));
+    assert!(skipped(None));
+}
+
+// The directories leading to DEST are only created with -D, however many of them are missing.
+#[test]
+fn creates_the_parents_of_dest_only_with_make_all_directories() {
+    let source = || ("source", Element::File("content"));
+    let installed = || ("source", Entry::file("content"));
+
+    let scenarios = [
+        Scenario {
+            given: vec![source(), ("a", Element::Directory(vec![]))],
+            args: vec!["source", "a/dest"],
+            leaves: vec![
+                ("a", Entry::Directory),
+                ("a/dest", Entry::file("content")),
+                installed(),
+            ],
+            ..Default::default()
+        },
+        Scenario {
+            given: vec![source()],
+            args: vec!["source", "a/dest"],
+            code: 1,
+            stderr: vec!["cannot open file to write 'a/dest': No such file or directory"],
+            leaves: vec![installed()],
+            ..Default::default()
+        },
+        Scenario {
+            given: vec![source()],
+            args: vec!["source", "a/b/dest"],
+            code: 1,
+            stderr: vec!["cannot open file to write 'a/b/dest': No such file or directory"],
+            leaves: vec![installed()],
+            ..Default::default()
+        },
+        Scenario {
+            given: vec![source(), ("a", Element::Directory(vec![]))],
+            args: vec!["-D", "source", "a/dest"],
+            leaves: vec![
+                ("a", Entry::Directory),
+                ("a/dest", Entry::file("content")),
+                installed(),
+            ],
+            ..Default::default()
+        },
+        Scenario {
+            given: vec![source()],
+            args: vec!["-D", "source", "a/dest"],
+            leaves: vec![
+                ("a", Entry::Directory),
+                ("a/dest", Entry::file("content")),
+                installed(),
+            ],
+            ..Default::default()
+        },
+        Scenario {
+            given: vec![source()],
+            args: vec!["-D", "source", "a/b/dest"],
+            leaves: vec![
+                ("a", Entry::Directory),
+                ("a/b", Entry::Directory),
+                ("a/b/dest", Entry::file("content")),
+                installed(),
+            ],
+            ..Default::default()
+        },
+    ];
+
+    for scenario in scenarios {
+        scenario.run();
+    }
+}
+
+// A missing target directory is created as it always has been, but only with -D can the
+// directories leading to it be created too.
+#[test]
+fn creates_target_directories() {
+    let source = || ("source", Element::File("content"));
+    let installed = || ("source", Entry::file("content"));
+
+    let scenarios = [
+        Scenario {
+            given: vec![source(), ("a", Element::Directory(vec![]))],
+            args: vec!["-t", "a", "source"],
+            leaves: vec![
+                ("a", Entry::Directory),
+                ("a/source", Entry::file("content")),
+                installed(),
+            ],
+            ..Default::default()
+        },
+        Scenario {
+            given: vec![source()],
+            args: vec!["-t", "a", "source"],
+            leaves: vec![
+                ("a", Entry::Directory),
+                ("a/source", Entry::file("content")),
+                installed(),
+            ],
+            ..Default::default()
+        },
+        Scenario {
+            given: vec![source()],
+            args: vec!["-t", "a/b", "source"],
+            code: 1,
+            stderr: vec!["cannot create directory 'a/b': No such file or directory"],
+            leaves: vec![installed()],
+            ..Default::default()
+        },
+        Scenario {
+            given: vec![source(), ("a", Element::Directory(vec![]))],
+            args: vec!["-D", "-t", "a", "source"],
+            leaves: vec![
+                ("a", Entry::Directory),
+                ("a/source", Entry::file("content")),
+                installed(),
+            ],
+            ..Default::default()
+        },
+        Scenario {
+            given: vec![source()],
+            args: vec!["-D", "-t", "a", "source"],
+            leaves: vec![
+                ("a", Entry::Directory),
+                ("a/source", Entry::file("content")),
+                installed(),
+            ],
+            ..Default::default()
+        },
+        Scenario {
+            given: vec![source()],
+            args: vec!["-D", "-t", "a/b", "source"],
+            leaves: vec![
+                ("a", Entry::Directory),
+                ("a/b", Entry::Directory),
+                ("a/b/source", Entry::file("content")),
+                installed(),
+            ],
+            ..Default::default()
+        },
+    ];
+
+    for scenario in scenarios {
+        scenario.run();
+    }
+}
+
+// A DEST ending in a separator names a directory, which is only created with -D and is never
+// installed to as a file.
+#[test]
+fn installs_into_a_dest_with_a_trailing_separator() {
+    let scenarios = [
+        Scenario {
+            given: vec![("source", Element::File("content"))],
+            args: vec!["source", "dest/"],
+            code: 1,
+            stderr: vec!["'dest/': No such file or directory"],
+            leaves: vec![("source", Entry::file("content"))],
+            ..Default::default()
+        },
+        Scenario {
+            given: vec![("source", Element::File("content"))],
+            args: vec!["-D", "source", "dest/"],
+            leaves: vec![
+                ("dest", Entry::Directory),
+                ("dest/source", Entry::file("content")),
+                ("source", Entry::file("content")),
+            ],
+            ..Default::default()
+        },
+        Scenario {
+            given: vec![
+                ("source", Element::File("content")),
+                ("dest", Element::File("old")),
+            ],
+            args: vec!["source", "dest/"],
+            code: 1,
+            stderr: vec!["Not a directory"],
+            leaves: vec![
+                ("dest", Entry::file("old")),
+                ("source", Entry::file("content")),
+            ],
+            ..Default::default()
+        },
+    ];
+
+    for scenario in scenarios {
+        scenario.run();
+    }
+}
+
+// A backslash is part of a file name on unix, so it doesn't make DEST a directory.
+#[cfg(unix)]
+#[test]
+fn installs_to_a_dest_ending_in_a_backslash_as_a_file() {
+    Scenario {
+        given: vec![("source", Element::File("content"))],
+        args: vec!["source", "dest\\"],
+        leaves: vec![
+            ("dest\\", Entry::file("content")),
+            ("source", Entry::file("content")),
+        ],
+        ..Default::default()
+    }
+    .run();
+}
+
+// Either separator names a directory on Windows, even when the two are mixed.
+#[cfg(windows)]
+#[test]
+fn installs_into_a_dest_with_a_trailing_backslash() {
+    let scenarios = [
+        Scenario {
+            given: vec![("source", Element::File("content"))],
+            args: vec!["source", "dest\\"],
+            code: 1,
+            stderr: vec!["No such file or directory"],
+            leaves: vec![("source", Entry::file("content"))],
+            ..Default::default()
+        },
+        Scenario {
+            given: vec![("source", Element::File("content"))],
+            args: vec!["-D", "source", "a/b\\"],
+            leaves: vec![
+                ("a", Entry::Directory),
+                ("a/b", Entry::Directory),
+                ("a/b/source", Entry::file("content")),
+                ("source", Entry::file("content")),
+            ],
+            ..Default::default()
+        },
+        Scenario {
+            given: vec![
+                ("source", Element::File("content")),
+                (
+                    "a",
+                    Element::Directory(vec![("dest", Element::File("old"))]),
+                ),
+            ],
+            args: vec!["source", "a\\dest/"],
+            code: 1,
+            stderr: vec!["Not a directory"],
+            leaves: vec![
+                ("a", Entry::Directory),
+                ("a/dest", Entry::file("old")),
+                ("source", Entry::file("content")),
+            ],
+            ..Default::default()
+        },
+    ];
+
+    for scenario in scenarios {
+        scenario.run();
+    }
+}
+
+// Several installs to the same destination at once each keep their own numbered backup of it.
+#[test]
+fn makes_a_backup_for_each_of_several_installs_at_once() {
+    const INSTALLS: usize = 12;
+
+    let scratch = support::Scratch::unique("concurrent-installs");
+    scratch.declare(&[("dest", Element::File("old"))]);
+
+    for i in 0..INSTALLS {
+        std::fs::write(scratch.join(format!("source{}", i)), format!("new{}", i)).unwrap();
+    }
+
+    let children = (0..INSTALLS)
+        .map(|i| {
+            support::winstall(&scratch)
+                .args(["--backup=numbered", "--backup-mode=copy"])
+                .arg(format!("source{}", i))
+                .arg("dest")
+                .stderr(std::process::Stdio::piped())
+                .spawn()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+
+    for child in children {
+        let output = child.wait_with_output().unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{}", stderr);
+    }
+
+    // Each install kept dest under a number no other one used. What it kept can't be checked, as
+    // copy backups overwrite dest in place, so one may catch another install part way through.
+    let tree = scratch.tree();
+
+    for n in 1..=INSTALLS {
+        let backup = format!("dest.~{}~", n);
+        assert!(
+            matches!(tree.get(&backup), Some(Entry::File(_))),
+            "{} is missing",
+            backup
+        );
+    }
+
+    assert_eq!(tree.len(), 1 + INSTALLS * 2, "{:?}", tree.keys());
+}
+
+// Patterns from --exclude and from the files given with --exclude-from all apply together.
+#[test]
+fn combines_exclude_and_exclude_from() {
+    Scenario {
+        given: vec![
+            ("app.exe", Element::File("app")),
+            ("app.pdb", Element::File("symbols")),
+            ("test_app.exe", Element::File("tests")),
+            ("patterns", Element::File("# tests\n\ntest_*\n")),
+            ("target", Element::Directory(vec![])),
+        ],
+        args: vec![
+            "--exclude=*.pdb",
+            "--exclude-from=patterns",
+            "-t",
+            "target",
+            "app.exe",
+            "app.pdb",
+            "test_app.exe",
+        ],
+        leaves: vec![
+            ("app.exe", Entry::file("app")),
+            ("app.pdb", Entry::file("symbols")),
+            ("patterns", Entry::file("# tests\n\ntest_*\n")),
+            ("target", Entry::Directory),
+            ("target/app.exe", Entry::file("app")),
+            ("test_app.exe", Entry::file("tests")),
+        ],
+        ..Default::default()
+    }
+    .run();
+}
+
+// An install killed part way through copying its source leaves the destination as it was, with
+// nothing but the partly written replacement beside it, whichever way backups are made. The
+// source is a pipe, so that the copy can be held up with only some of it written.
+#[cfg(unix)]
+#[test]
+fn an_install_killed_while_copying_leaves_the_destination_alone() {
+    use std::io::Write;
+
+    for mode in ["--backup-mode=rename", "--backup-mode=copy"] {
+        let scratch = support::Scratch::unique("killed");
+        scratch.declare(&[("dest", Element::File("old"))]);
+
+        let made = std::process::Command::new("mkfifo")
+            .arg(scratch.join("source"))
+            .status()
+            .expect("mkfifo should run");
+        assert!(made.success());
+
+        let mut child = support::winstall(&scratch)
+            .args(["-b", mode, "source", "dest"])
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .expect("winstall should run");
+
+        let staged = format!("dest.winstall-{}", child.id());
+
+        // Opening the pipe waits for winstall to open the other end.
+        let mut source = std::fs::OpenOptions::new()
+            .write(true)
+            .open(scratch.join("source"))
+            .unwrap();
+        source.write_all(b"partial").unwrap();
+
+        let started = std::time::Instant::now();
+        while std::fs::metadata(scratch.join(&staged)).map_or(0, |m| m.len()) < 7 {
+            assert!(started.elapsed().as_secs() < 10, "nothing was copied");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        // Reading the pipe back would wait for a writer that never comes.
+        drop(source);
+        std::fs::remove_file(scratch.join("source")).unwrap();
+
+        let tree = scratch.tree();
+        assert_eq!(tree["dest"], Entry::file("old"), "{}", mode);
+        assert_eq!(tree[&staged], Entry::file("partial"), "{}", mode);
+        assert_eq!(tree.len(), 2, "{}", mode);
+    }
+}
@@ -0,0 +1,115 @@
+// What the end-to-end tests share. Each test runs the winstall binary in a scratch directory, made
+// with the same module the unit tests use.
+#![allow(dead_code)]
+
+#[path = "../../src/scratch.rs"]
+mod scratch;
+
+pub use scratch::{Element, Entry, Scratch};
+
+// How a run of winstall ended.
+pub struct Run {
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+// A command that runs winstall in scratch. The variables of the test process that winstall reads
+// are left out, and the config file is looked for in scratch, so that a run is decided by what the
+// test gives it and not by where the tests happen to be run.
+pub fn winstall(scratch: &Scratch) -> std::process::Command {
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_winstall"));
+    command.current_dir(scratch.path());
+
+    for (name, _) in std::env::vars_os() {
+        let read = name.to_str().is_some_and(|name| {
+            name.starts_with("WINSTALL_")
+                || name == "VERSION_CONTROL"
+                || name == "SIMPLE_BACKUP_SUFFIX"
+        });
+
+        if read {
+            command.env_remove(name);
+        }
+    }
+
+    let config = scratch.join(".config");
+    command
+        .env("XDG_CONFIG_HOME", &config)
+        .env("APPDATA", &config);
+
+    command
+}
+
+pub fn run<I, S>(scratch: &Scratch, args: I) -> Run
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<std::ffi::OsStr>,
+{
+    finish(winstall(scratch).args(args))
+}
+
+pub fn finish(command: &mut std::process::Command) -> Run {
+    let output = command.output().expect("winstall should run");
+
+    Run {
+        code: output.status.code(),
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    }
+}
+
+// An end-to-end test in full: the files and directories there are to start with, the arguments
+// winstall is run with, and what it is expected to do. Text listed for stdout and stderr has to
+// appear somewhere in each, and the tree left behind is compared in full.
+#[derive(Default)]
+pub struct Scenario {
+    pub given: Vec<(&'static str, Element)>,
+    pub args: Vec<&'static str>,
+    pub code: i32,
+    pub stdout: Vec<&'static str>,
+    pub stderr: Vec<&'static str>,
+    pub leaves: Vec<(&'static str, Entry)>,
+}
+
+impl Scenario {
+    pub fn run(self) {
+        let scratch = Scratch::unique("scenario");
+        scratch.declare(&self.given);
+
+        let run = run(&scratch, &self.args);
+
+        assert_eq!(
+            run.code,
+            Some(self.code),
+            "exit status of {:?}\nstdout: {}\nstderr: {}",
+            self.args,
+            run.stdout,
+            run.stderr
+        );
+
+        for (stream, output, expected) in [
+            ("stdout", &run.stdout, &self.stdout),
+            ("stderr", &run.stderr, &self.stderr),
+        ] {
+            for text in expected {
+                assert!(
+                    output.contains(text),
+                    "{} of {:?} does not contain {:?}: {}",
+                    stream,
+                    self.args,
+                    text,
+                    output
+                );
+            }
+        }
+
+        let leaves = self
+            .leaves
+            .into_iter()
+            .map(|(name, entry)| (name.to_string(), entry))
+            .collect::<std::collections::BTreeMap<_, _>>();
+
+        assert_eq!(scratch.tree(), leaves, "tree left by {:?}", self.args);
+    }
+}